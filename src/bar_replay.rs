@@ -0,0 +1,80 @@
+use crate::market::MarketState;
+
+/// How far `BarReplay::step` moves the cursor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BarStep {
+    /// To the close of the next kline that isn't fully shown.
+    Kline,
+    /// To the next trade. Trades within the same millisecond show up together.
+    Trade,
+}
+impl std::str::FromStr for BarStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kline" => Ok(BarStep::Kline),
+            "trade" => Ok(BarStep::Trade),
+            _ => Err(format!("Unsupported bar replay step: {}", s)),
+        }
+    }
+}
+
+/// Cursor over loaded history, with the market as it stood at that time.
+pub struct BarReplay {
+    cursor: u64,
+    view: MarketState,
+}
+impl BarReplay {
+    pub fn new(market: &MarketState, cursor: u64) -> Self {
+        Self { cursor, view: market.until(cursor) }
+    }
+
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+    /// Data up to the cursor, what gets rendered.
+    pub fn view(&self) -> &MarketState {
+        &self.view
+    }
+
+    /// Moving forward extends the view, only seeking back rebuilds it.
+    pub fn seek(&mut self, market: &MarketState, cursor: u64) {
+        if cursor >= self.cursor {
+            market.extend_until(&mut self.view, self.cursor, cursor);
+        } else {
+            self.view = market.until(cursor);
+        }
+        self.cursor = cursor;
+    }
+    /// Rebuilds the view, e.g. after the bucket size changed or more history was loaded.
+    pub fn refresh(&mut self, market: &MarketState) {
+        self.view = market.until(self.cursor);
+    }
+
+    /// Moves the cursor forward by `step`. Returns false if there is nothing left to show.
+    pub fn step(&mut self, market: &MarketState, step: BarStep) -> bool {
+        let next = match step {
+            BarStep::Kline => {
+                let interval = market.timeframe().as_ms();
+                market.klines().range(market.timeframe().kline_open(self.cursor)..)
+                    .map(|(open_time, _)| open_time + interval - 1)
+                    .find(|&close_time| close_time > self.cursor)
+            },
+            BarStep::Trade => {
+                let current_kline_open = market.timeframe().kline_open(self.cursor);
+                market.footprint().range(current_kline_open..)
+                    .flat_map(|(_, trade_groups)| trade_groups.trades.iter())
+                    .map(|trade| trade.time)
+                    .find(|&time| time > self.cursor)
+            },
+        };
+        match next {
+            Some(cursor) => {
+                self.seek(market, cursor);
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,1426 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::cvd::CvdCandle;
+use crate::footprint::{Footprint, ImbalanceSide, ProfileStats, StatsRow, VolumeProfile};
+use crate::funding::MarkPriceUpdate;
+use crate::heatmap::{BookSnapshot, SNAPSHOTS_PER_KLINE};
+use crate::liquidation::{Liquidation, LiquidationSide, LiquidationTotals};
+use crate::open_interest::{OiMode, OiPoint};
+use crate::orderbook::{BookLiquidity, DepthMode, PriceLevels, TopOfBook};
+use crate::surface::{Surface, WebSurface};
+use crate::tape::{prints, TapeConfig};
+use crate::{log, Kline, Price, TickSize, Timeframe, Trade, DAY_IN_MS, HOUR_IN_MS, MINUTE_IN_MS};
+
+pub struct CanvasOrderbook<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) depth_mode: DepthMode,
+}
+impl<S: Surface> CanvasOrderbook<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            depth_mode: DepthMode::Levels,
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    /// In cumulative mode `bids` and `asks` have to reach from the best price into view, so the
+    /// running sums start at the top of the book.
+    pub fn render(&mut self, y_min: f64, y_max: f64, bids: &PriceLevels, asks: &PriceLevels, liquidity: &[BookLiquidity], klines: &Vec<(&u64, &Kline)>, last_depth_update: u64, tick_size: TickSize, num_possible_lines: f64) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+
+        let in_view = |price: &Price| {
+            let price = price.to_f64(tick_size);
+            price >= y_min && price <= y_max
+        };
+        let (bid_levels, ask_levels) = match self.depth_mode {
+            DepthMode::Levels => (bids.iter().collect::<Vec<_>>(), asks.iter().collect::<Vec<_>>()),
+            DepthMode::Cumulative => (bids.cumulative(), asks.cumulative()),
+        };
+        let max_quantity = bid_levels.iter().chain(&ask_levels)
+            .filter(|(price, _)| in_view(price))
+            .map(|(_, quantity)| *quantity)
+            .fold(0.0, f64::max);
+
+        let num_labels = 12; 
+        let step = (y_max - y_min) / num_labels as f64;
+
+        let font_size = (12.0 * self.dpi).round();
+        context.set_font_size(font_size);
+        context.set_fill_style("rgba(200, 200, 200, 0.8)");
+
+        let max_quantity_str = format!("{:.1}", max_quantity);
+        let text_width = context.measure_text(&max_quantity_str);
+        context.fill_text(&max_quantity_str, self.width - text_width - 6.0, 20.0);
+        
+        for i in 0..=num_labels {
+            let y_value = y_min + step * i as f64;
+            let y = self.height - ((y_value - y_min) / (y_max - y_min)) * self.height;
+
+            if let Some(y_price) = Price::from_f64(y_value, tick_size) {
+                context.fill_text(&y_price.format(tick_size), 2.0*self.dpi, y);
+            }
+        }
+
+        let height_per_line = (self.height / num_possible_lines).round();
+
+        let (bid_color, ask_color) = match self.depth_mode {
+            DepthMode::Levels => ("rgba(81, 205, 160, 1)", "rgba(192, 80, 77, 1)"),
+            DepthMode::Cumulative => ("rgba(81, 205, 160, 0.6)", "rgba(192, 80, 77, 0.6)"),
+        };
+        let bar_width = |quantity: f64| (quantity / (max_quantity + max_quantity/4.0)) * (self.width - (self.width/12.0));
+        let price_y = |price: &Price| self.height - ((price.to_f64(tick_size) - y_min) / (y_max - y_min)) * self.height;
+        // bids step down the screen from the best price, asks up
+        for (levels, color, direction) in [(&bid_levels, bid_color, 1.0), (&ask_levels, ask_color, -1.0)] {
+            context.set_fill_style(color);
+            let levels: Vec<(f64, f64)> = levels.iter()
+                .filter(|(price, _)| in_view(price))
+                .map(|(price, quantity)| (price_y(price), bar_width(*quantity)))
+                .collect();
+            match self.depth_mode {
+                DepthMode::Levels => {
+                    for (y, x) in levels {
+                        context.fill_rect(self.dpi*60.0, y - height_per_line / 2.0, x, height_per_line);
+                    }
+                },
+                DepthMode::Cumulative => {
+                    let (first, last) = match (levels.first(), levels.last()) {
+                        (Some(first), Some(last)) => (first.0, last.0),
+                        _ => continue,
+                    };
+                    let near_edge = |y: f64| y - direction * height_per_line / 2.0;
+                    let far_edge = last + direction * height_per_line / 2.0;
+                    // each level's total holds until the next level out
+                    let mut points = vec![(self.dpi*60.0, near_edge(first))];
+                    for (i, (y, x)) in levels.iter().enumerate() {
+                        let step_end = levels.get(i + 1).map_or(far_edge, |(next_y, _)| near_edge(*next_y));
+                        points.push((self.dpi*60.0 + x, near_edge(*y)));
+                        points.push((self.dpi*60.0 + x, step_end));
+                    }
+                    points.push((self.dpi*60.0, far_edge));
+                    context.fill_polygon(&points);
+                },
+            }
+        }
+
+        context.set_font_size(font_size);
+        for (i, band) in liquidity.iter().rev().enumerate() {
+            let readout = format!("±{}%  {:.1} / {:.1}  {:+.0}%", band.percent, band.bids, band.asks, band.imbalance() * 100.0);
+            let text_width = context.measure_text(&readout);
+            context.set_fill_style(if band.bids >= band.asks { "rgba(81, 205, 160, 0.9)" } else { "rgba(192, 80, 77, 0.9)" });
+            context.fill_text(&readout, self.width - text_width - 6.0, self.height - 6.0*self.dpi - i as f64 * (font_size + 4.0*self.dpi));
+        }
+        if let Some((_last_time, kline)) = klines.last() {
+            context.set_font_size(font_size);
+            let y = ((kline.close.to_f64(tick_size) - y_min) / (y_max - y_min)) * self.height;
+            let y_value_str = kline.close.format(tick_size);
+
+            if kline.open < kline.close {
+                context.set_fill_style("rgba(81, 205, 160, 1)");
+            } else {
+                context.set_fill_style("rgba(192, 80, 77, 1)");
+            }
+            let rect_y = self.height - y - 15.0*self.dpi;
+            context.fill_rect(1.5*self.dpi, rect_y, 55.0*self.dpi, 30.0*self.dpi); 
+
+            context.set_fill_style("black");
+            context.fill_text(&y_value_str, 3.0*self.dpi, self.height - y);
+
+            let time_left = if kline.close_time < last_depth_update {
+                0
+            } else {
+                (kline.close_time - last_depth_update) / 1000
+            };
+            let time_left_str = format!("{:02}:{:02}", time_left / 60, time_left % 60);
+            context.set_font_size((font_size/1.4).round());
+            context.fill_text(&time_left_str, 3.0*self.dpi, self.height - y + (12.0*self.dpi));
+        }
+    } 
+
+    pub fn render_top_of_book(&mut self, y_min: f64, y_max: f64, top_of_book: &TopOfBook, tick_size: TickSize) {
+        let context = &mut self.surface;
+        let left = self.dpi*60.0;
+        draw_top_of_book_lines(context, left, self.width, self.height, self.dpi, y_min, y_max, top_of_book, tick_size);
+
+        let font_size = (12.0 * self.dpi).round();
+        context.set_font_size(font_size);
+        context.set_fill_style("rgba(200, 200, 200, 0.8)");
+        let spread = format!("spread {}t  {:.2}bps", top_of_book.spread_ticks(), top_of_book.spread_bps());
+        context.fill_text(&spread, left, 20.0);
+        let microprice = format!("micro {:.*}", tick_size.decimals() as usize + 1, top_of_book.microprice(tick_size));
+        context.fill_text(&microprice, left, 20.0 + font_size + 4.0*self.dpi);
+    }
+}
+
+/// Clock intervals time labels snap to, the shortest that keeps them apart is used.
+const TIME_LABEL_STEPS: [u64; 11] = [
+    MINUTE_IN_MS, 3 * MINUTE_IN_MS, 5 * MINUTE_IN_MS, 15 * MINUTE_IN_MS, 30 * MINUTE_IN_MS,
+    HOUR_IN_MS, 2 * HOUR_IN_MS, 4 * HOUR_IN_MS, 6 * HOUR_IN_MS, 12 * HOUR_IN_MS, DAY_IN_MS,
+];
+/// Minimum distance between time labels, at a dpi of 1.
+const TIME_LABEL_SPACING: f64 = 60.0;
+
+/// Interval between time labels for klines `kline_width` px apart: a round clock interval
+/// that's a whole number of klines, labels land on its multiples.
+fn time_label_step(timeframe: Timeframe, kline_width: f64, min_spacing: f64) -> u64 {
+    let interval = timeframe.as_ms();
+    TIME_LABEL_STEPS.iter().copied()
+        .filter(|step| step % interval == 0)
+        .find(|step| (step / interval) as f64 * kline_width >= min_spacing)
+        .unwrap_or(DAY_IN_MS)
+}
+
+const BUY_IMBALANCE_COLOR: &str = "rgba(0, 255, 180, 1)";
+const SELL_IMBALANCE_COLOR: &str = "rgba(255, 60, 60, 1)";
+
+pub struct CanvasMain<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) x_zoom: f64,
+    pub(crate) timeframe: Timeframe,
+    pub(crate) stats_rows: Vec<StatsRow>,
+}
+impl<S: Surface> CanvasMain<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            x_zoom: 30.0,
+            timeframe: Timeframe::M1,
+            stats_rows: StatsRow::ALL.to_vec(),
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(&mut self, y_min: f64, y_max: f64, klines: &Vec<(&u64, &Kline)>, heatmap: &[&BookSnapshot], footprints: Vec<(u64, Footprint)>, tick_size: TickSize, num_possible_lines: f64) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+        
+        if let Some((last_kline_open, _)) = klines.iter().last() {
+            let interval = self.timeframe.as_ms() as f64;
+            let zoom_scale = self.x_zoom * interval;
+            let time_difference: f64 = **last_kline_open as f64 + interval - zoom_scale;
+            let rect_width: f64 = (self.width / self.x_zoom)/2.0;
+            let label_step = time_label_step(self.timeframe, rect_width * 2.0, TIME_LABEL_SPACING * self.dpi);
+
+            if let (Some(low), Some(high)) = (Price::from_f64(y_min, tick_size), Price::from_f64(y_max, tick_size)) {
+                let visible_prices = low..=high;
+                let max_quantity = heatmap.iter()
+                    .flat_map(|snapshot| snapshot.bids_within(&visible_prices).iter().chain(snapshot.asks_within(&visible_prices)))
+                    .map(|(_, quantity)| *quantity)
+                    .fold(0.0, f64::max);
+                if max_quantity > 0.0 {
+                    let column_width = (interval / SNAPSHOTS_PER_KLINE as f64 / zoom_scale) * self.width;
+                    let height_per_line = (self.height / num_possible_lines).round();
+
+                    for snapshot in heatmap {
+                        let x = ((snapshot.time as f64 - time_difference) / zoom_scale) * self.width;
+                        let sides = [(snapshot.bids_within(&visible_prices), "81, 205, 160"), (snapshot.asks_within(&visible_prices), "192, 80, 77")];
+                        for (levels, rgb) in sides {
+                            for (price, quantity) in levels {
+                                // square root so thinner levels stay visible next to the largest walls
+                                let intensity = (quantity / max_quantity).sqrt();
+                                if intensity < 0.1 {
+                                    continue;
+                                }
+                                let y = self.height - self.height * (price.to_f64(tick_size) - y_min) / (y_max - y_min);
+                                context.set_fill_style(&format!("rgba({}, {:.2})", rgb, intensity * 0.5));
+                                context.fill_rect(x, y - height_per_line / 2.0, column_width, height_per_line);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let max_quantity = footprints.iter().map(|(_, footprint)| &footprint.trades).flat_map(|trade_groups| {
+                trade_groups.buys.iter().chain(trade_groups.sells.iter()).map(|(_, quantity)| *quantity)
+            }).fold(0.0, f64::max);
+
+            let height_per_line = (self.height / num_possible_lines).round();
+            let font_size = (height_per_line/2.6).round();
+            let mut stats_columns = Vec::new();
+    
+            for (_, kline) in klines.iter() {
+                let x: f64 = ((kline.open_time as f64 - time_difference) / zoom_scale) * self.width;
+
+                let y_open = self.height * (kline.open.to_f64(tick_size) - y_min) / (y_max - y_min);
+                let y_close = self.height * (kline.close.to_f64(tick_size) - y_min) / (y_max - y_min);
+                let y_high = self.height * (kline.high.to_f64(tick_size) - y_min) / (y_max - y_min);
+                let y_low = self.height * (kline.low.to_f64(tick_size) - y_min) / (y_max - y_min);
+
+                context.set_font_size(font_size);
+                if let Some((_, footprint)) = footprints.iter().find(|(time, _)| *time == kline.open_time) {
+                    let trade_groups = &footprint.trades;
+                    let imbalances = &footprint.imbalances;
+                    stats_columns.push((x + rect_width, footprint.stats));
+                    let mut texts = Vec::new(); 
+
+                    if let Some(profile) = &footprint.profile {
+                        let price_y = |price: Price| self.height - self.height * (price.to_f64(tick_size) - y_min) / (y_max - y_min);
+
+                        context.set_fill_style("rgba(255, 215, 0, 0.15)");
+                        context.fill_rect(x, price_y(profile.poc) - height_per_line / 2.0, rect_width*2.0, height_per_line);
+
+                        let va_top = price_y(profile.value_area_high) - height_per_line / 2.0;
+                        let va_bottom = price_y(profile.value_area_low) + height_per_line / 2.0;
+                        let bracket_x = x + 2.0*self.dpi;
+                        context.set_stroke_style("rgba(255, 215, 0, 0.6)");
+                        context.set_line_width(self.dpi);
+                        context.line(bracket_x, va_top, bracket_x, va_bottom);
+                        context.line(bracket_x, va_top, bracket_x + 4.0*self.dpi, va_top);
+                        context.line(bracket_x, va_bottom, bracket_x + 4.0*self.dpi, va_bottom);
+                    }
+
+                    for (price, quantity) in &trade_groups.buys { 
+                        context.set_fill_style(if imbalances.side_at(*price, ImbalanceSide::Buy) { BUY_IMBALANCE_COLOR } else { "rgba(81, 205, 160, 1)" });
+                        let price = price.to_f64(tick_size);
+                        let y_trade = self.height * (price - y_min) / (y_max - y_min);
+                        let scaled_quantity = rect_width * quantity / max_quantity;
+
+                        let y_top = self.height - y_trade - height_per_line / 2.0;
+
+                        context.fill_rect(x + rect_width + 4.0, y_top, scaled_quantity, height_per_line);
+                        
+                        if font_size > (6.0*self.dpi)&& rect_width > 60.0 {
+                            let quantity_str = format!("{:.3}", quantity);
+                            texts.push((quantity_str, x + rect_width + 6.0, (y_top + height_per_line / 2.0 + font_size / 3.0))); 
+                        }
+                    }
+                    context.set_fill_style("white");
+                    for (quantity_str, x, y) in texts.drain(..) {
+                        context.fill_text(&quantity_str, x, y);
+                    }
+
+                    for (price, quantity) in &trade_groups.sells {
+                        context.set_fill_style(if imbalances.side_at(*price, ImbalanceSide::Sell) { SELL_IMBALANCE_COLOR } else { "rgba(192, 80, 77, 1)" });
+                        let price = price.to_f64(tick_size);
+                        let y_trade = self.height * (price - y_min) / (y_max - y_min);
+                        let scaled_quantity = rect_width * quantity / max_quantity;
+                    
+                        let y_top = self.height - y_trade - height_per_line / 2.0;
+
+                        context.fill_rect(x + rect_width - 4.0, y_top, -scaled_quantity, height_per_line);
+                        
+                        if font_size > (6.0*self.dpi)&& rect_width > 60.0 {
+                            let quantity_str = format!("{:.3}", quantity);
+                            let text_width = context.measure_text(&quantity_str);
+                            texts.push((quantity_str, x + rect_width - 6.0 - text_width, (y_top + height_per_line / 2.0 + font_size / 3.0))); 
+                        }
+                    }
+                    context.set_fill_style("white");
+                    for (quantity_str, x, y) in texts {
+                        context.fill_text(&quantity_str, x, y);
+                    }
+
+                    // stacked imbalances extend to the right edge as potential support/resistance
+                    context.set_line_width(2.0*self.dpi);
+                    for stacked in &imbalances.stacked {
+                        let mid_price = (stacked.low.to_f64(tick_size) + stacked.high.to_f64(tick_size)) / 2.0;
+                        let y = self.height - self.height * (mid_price - y_min) / (y_max - y_min);
+                        context.set_stroke_style(match stacked.side {
+                            ImbalanceSide::Buy => "rgba(0, 255, 180, 0.5)",
+                            ImbalanceSide::Sell => "rgba(255, 60, 60, 0.5)",
+                        });
+                        context.line(x + (rect_width*2.0), y, self.width, y);
+                    }
+                } else {
+                    context.set_stroke_style("rgba(200, 200, 200, 0.5)");
+                    context.line(x, self.height - y_high, x + (rect_width*2.0), self.height - y_high);
+                    context.line(x, self.height - y_low, x + (rect_width*2.0), self.height - y_low);
+                }
+                context.set_stroke_style(if kline.open < kline.close { "rgba(50, 200, 50, 1)" } else { "rgba(200, 50, 50, 1)" });
+                context.set_line_width(rect_width/44.0);
+                context.line(x + rect_width, self.height - y_open, x + rect_width, self.height - y_close);
+
+                // time labels from kline.open_time
+                let text_height = 20.0 + 1.0 * 1.0; // font size + padding + margin
+                if kline.open_time % label_step == 0 {
+                    context.set_font_size(12.0*self.dpi);
+                    context.set_fill_style("rgba(200, 200, 200, 0.8)");
+                    let hour = (kline.open_time / 3600000) % 24;
+                    let minute = (kline.open_time / 60000) % 60;
+                    let text_width = context.measure_text(&format!("{:02}:{:02}", hour, minute));
+                    context.fill_text(&format!("{:02}:{:02}", hour, minute), x + rect_width - text_width / 2.0, self.height - ((text_height/2.0)*self.dpi));
+                }        
+            }
+
+            if !self.stats_rows.is_empty() && !stats_columns.is_empty() {
+                let row_height = 14.0*self.dpi;
+                let table_top = self.height - 24.0*self.dpi - row_height * self.stats_rows.len() as f64;
+
+                context.set_fill_style("rgba(0, 0, 0, 0.6)");
+                context.fill_rect(0.0, table_top, self.width, row_height * self.stats_rows.len() as f64);
+                context.set_font_size(10.0*self.dpi);
+
+                for (i, row) in self.stats_rows.iter().enumerate() {
+                    let y = table_top + row_height * (i as f64 + 1.0) - 3.0*self.dpi;
+
+                    for (center_x, stats) in &stats_columns {
+                        let value = row.value(stats);
+                        let text = format_stat(*row, value);
+                        let text_width = context.measure_text(&text);
+                        if text_width > rect_width*2.0 - 4.0*self.dpi {
+                            continue;
+                        }
+                        context.set_fill_style(match row.is_signed() {
+                            true if value > 0.0 => "rgba(81, 205, 160, 1)",
+                            true if value < 0.0 => "rgba(192, 80, 77, 1)",
+                            _ => "rgba(200, 200, 200, 0.8)",
+                        });
+                        context.fill_text(&text, center_x - text_width / 2.0, y);
+                    }
+                    context.set_fill_style("rgba(200, 200, 200, 0.5)");
+                    context.fill_text(row.label(), 2.0*self.dpi, y);
+                }
+            }
+        }
+    }
+
+    /// Horizontal buy/sell profile along the right edge, POC and value area levels emphasized.
+    pub fn render_volume_profile(&mut self, y_min: f64, y_max: f64, profile: &VolumeProfile, stats: Option<ProfileStats>, tick_size: TickSize, num_possible_lines: f64) {
+        let context = &mut self.surface;
+        let max_volume = profile.max_volume();
+        if max_volume <= 0.0 {
+            return;
+        }
+        let max_width = self.width * 0.15;
+        let height_per_line = (self.height / num_possible_lines).round();
+
+        for (price, (buy, sell)) in &profile.levels {
+            let y = self.height - self.height * (price.to_f64(tick_size) - y_min) / (y_max - y_min);
+            let y_top = y - height_per_line / 2.0;
+            let in_value_area = stats.is_some_and(|stats| *price >= stats.value_area_low && *price <= stats.value_area_high);
+            let alpha = if in_value_area { 0.5 } else { 0.2 };
+
+            let sell_width = max_width * sell / max_volume;
+            let buy_width = max_width * buy / max_volume;
+            context.set_fill_style(&format!("rgba(192, 80, 77, {})", alpha));
+            context.fill_rect(self.width - sell_width, y_top, sell_width, height_per_line);
+            context.set_fill_style(&format!("rgba(81, 205, 160, {})", alpha));
+            context.fill_rect(self.width - sell_width - buy_width, y_top, buy_width, height_per_line);
+        }
+        if let Some(stats) = stats {
+            let y = self.height - self.height * (stats.poc.to_f64(tick_size) - y_min) / (y_max - y_min);
+            context.set_stroke_style("rgba(255, 215, 0, 0.8)");
+            context.set_line_width(self.dpi);
+            context.line(self.width - max_width, y, self.width, y);
+        }
+    }
+
+    pub fn render_top_of_book(&mut self, y_min: f64, y_max: f64, top_of_book: &TopOfBook, tick_size: TickSize) {
+        draw_top_of_book_lines(&mut self.surface, 0.0, self.width, self.height, self.dpi, y_min, y_max, top_of_book, tick_size);
+    }
+
+    /// Trades as bubbles at their price and time within the candle, sized relative to the largest in view.
+    pub fn render_large_trades(&mut self, y_min: f64, y_max: f64, last_kline_open: u64, trades: &[(u64, Trade)], tick_size: TickSize) {
+        let context = &mut self.surface;
+        let max_quantity = trades.iter().map(|(_, trade)| trade.quantity).fold(0.0, f64::max);
+        if max_quantity <= 0.0 {
+            return;
+        }
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let time_difference = last_kline_open as f64 + interval - zoom_scale;
+        let rect_width = (self.width / self.x_zoom) / 2.0;
+        let max_radius = rect_width.clamp(4.0 * self.dpi, 30.0 * self.dpi);
+
+        for (open_time, trade) in trades {
+            let x = ((*open_time as f64 - time_difference) / zoom_scale) * self.width
+                + (trade.time.saturating_sub(*open_time) as f64 / interval).min(1.0) * rect_width * 2.0;
+            let y = self.height - self.height * (trade.price.to_f64(tick_size) - y_min) / (y_max - y_min);
+            let radius = (max_radius * (trade.quantity / max_quantity).sqrt()).max(2.0 * self.dpi);
+            context.set_fill_style(if trade.is_buyer_maker { "rgba(192, 80, 77, 0.45)" } else { "rgba(81, 205, 160, 0.45)" });
+            context.arc(x, y, radius);
+        }
+    }
+
+    /// Liquidations as circles at their price, sized by quantity relative to the largest in view.
+    pub fn render_liquidations(&mut self, y_min: f64, y_max: f64, last_kline_open: u64, liquidations: &[(u64, Liquidation)], tick_size: TickSize) {
+        let context = &mut self.surface;
+        let max_quantity = liquidations.iter().map(|(_, liquidation)| liquidation.quantity).fold(0.0, f64::max);
+        if max_quantity <= 0.0 {
+            return;
+        }
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let time_difference = last_kline_open as f64 + interval - zoom_scale;
+        let rect_width = (self.width / self.x_zoom) / 2.0;
+        let max_radius = (rect_width / 2.0).clamp(3.0 * self.dpi, 20.0 * self.dpi);
+
+        for (open_time, liquidation) in liquidations {
+            let x = ((*open_time as f64 - time_difference) / zoom_scale) * self.width + rect_width;
+            if x < 0.0 {
+                continue;
+            }
+            let y = self.height - self.height * (liquidation.price.to_f64(tick_size) - y_min) / (y_max - y_min);
+            let radius = (max_radius * (liquidation.quantity / max_quantity).sqrt()).max(2.0 * self.dpi);
+            context.set_fill_style(liquidation_color(liquidation.side));
+            context.arc(x, y, radius);
+        }
+    }
+
+    /// Average price open positions were added at, against the candles it diverges from.
+    pub fn render_oi_weighted_price(&mut self, y_min: f64, y_max: f64, last_kline_open: u64, points: &[(u64, OiPoint)]) {
+        let context = &mut self.surface;
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let time_difference = last_kline_open as f64 + interval - zoom_scale;
+        let rect_width = (self.width / self.x_zoom) / 2.0;
+
+        context.set_stroke_style("rgba(100, 180, 255, 0.8)");
+        context.set_line_width(1.5 * self.dpi);
+        let mut previous: Option<(f64, f64)> = None;
+        for (open_time, weighted_price) in points.iter().filter_map(|(open_time, point)| Some((open_time, point.weighted_price?))) {
+            let x = ((*open_time as f64 - time_difference) / zoom_scale) * self.width + rect_width;
+            let y = self.height - self.height * (weighted_price - y_min) / (y_max - y_min);
+            if let Some((previous_x, previous_y)) = previous {
+                context.line(previous_x, previous_y, x, y);
+            }
+            previous = Some((x, y));
+        }
+    }
+}
+pub struct CanvasIndicatorVolume<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) x_zoom: f64,
+    pub(crate) timeframe: Timeframe,
+}
+impl<S: Surface> CanvasIndicatorVolume<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            x_zoom: 30.0,
+            timeframe: Timeframe::M1,
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+
+    pub fn render(&mut self, klines: &Vec<(&u64, &Kline)>) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+        
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let rect_width: f64 = (self.width / self.x_zoom)/2.0;
+
+        match klines.iter().last() {
+            Some((last_kline_open, _)) => {
+                let max_volume = klines.iter().map(|(_, kline)| f64::max(kline.buy_volume, kline.sell_volume)).fold(0.0, f64::max);
+                let time_difference = **last_kline_open as f64 + interval - zoom_scale;
+
+                let font_size = (12.0 * self.dpi).round();
+                context.set_font_size(font_size);
+                for (_, kline) in klines.iter() {
+                    let x = ((kline.open_time as f64 - time_difference) / zoom_scale) * self.width;
+                
+                    let buy_height = self.height * (kline.buy_volume / max_volume);
+                    let sell_height = self.height * (kline.sell_volume / max_volume);
+                
+                    context.set_fill_style("rgba(81, 205, 160, 1)");
+                    context.fill_rect(x + rect_width, self.height - buy_height, rect_width - 10.0, buy_height);
+                
+                    context.set_fill_style("rgba(192, 80, 77, 1)");
+                    context.fill_rect(x + 10.0, self.height - sell_height, rect_width - 10.0, sell_height);
+
+                    if self.x_zoom < 18.0 && rect_width > 60.0{
+                        let text_height = font_size + 2.0 * 2.0; // font size + padding + margin            
+                        context.set_fill_style("black");
+                
+                        if buy_height > text_height {
+                            context.fill_text(&format!("{:.2}", kline.buy_volume), x + rect_width + 6.0, self.height - buy_height + text_height);
+                        }
+                        if sell_height > text_height {
+                            context.fill_text(&format!("{:.2}", kline.sell_volume), x + 14.0, self.height - sell_height + text_height);
+                        }
+                    }
+                }
+            },
+            None => {
+                log("No klines");
+            }
+        }
+    }
+}
+pub struct CanvasIndiCVD<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) x_zoom: f64,
+    pub(crate) timeframe: Timeframe,
+    pub(crate) oi_mode: OiMode,
+}
+impl<S: Surface> CanvasIndiCVD<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            x_zoom: 30.0,
+            timeframe: Timeframe::M1,
+            oi_mode: OiMode::Points,
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+
+    pub fn render(&mut self, candles: &[(u64, CvdCandle)], oi_points: &[(u64, OiPoint)]) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+    
+        let interval = self.timeframe.as_ms();
+        let zoom_scale = self.x_zoom * interval as f64;
+        
+        match candles.last() {
+            Some((last_kline_open, _)) => {
+                let max_cvd = candles.iter().map(|(_, candle)| candle.high).fold(f64::MIN, f64::max);
+                let min_cvd = candles.iter().map(|(_, candle)| candle.low).fold(f64::MAX, f64::min);
+
+                let time_difference = *last_kline_open as f64 + interval as f64 - zoom_scale;
+                let rect_width: f64 = (self.width / self.x_zoom)/2.0;
+
+                let padding_ratio = 0.1; 
+                let padded_height = self.height * (1.0 - padding_ratio);
+                let padding = self.height * padding_ratio / 2.0;
+                let cvd_y = |cvd: f64| if max_cvd == min_cvd {
+                    self.height - (padded_height / 2.0 + padding)
+                } else {
+                    self.height - (padded_height * (cvd - min_cvd) / (max_cvd - min_cvd) + padding)
+                };
+
+                if min_cvd < 0.0 && max_cvd > 0.0 {
+                    context.set_stroke_style("rgba(200, 200, 200, 0.2)");
+                    context.set_line_width(self.dpi);
+                    context.line(0.0, cvd_y(0.0), self.width, cvd_y(0.0));
+                }
+                context.set_line_width((rect_width/44.0).max(1.0));
+                for (open_time, candle) in candles {
+                    let x = ((*open_time as f64 - time_difference) / zoom_scale) * self.width;
+                    let (color, wick_color) = match (candle.close >= candle.open, candle.partial) {
+                        (true, false) => ("rgba(81, 205, 160, 1)", "rgba(81, 205, 160, 0.6)"),
+                        (false, false) => ("rgba(192, 80, 77, 1)", "rgba(192, 80, 77, 0.6)"),
+                        (true, true) => ("rgba(81, 205, 160, 0.35)", "rgba(81, 205, 160, 0.2)"),
+                        (false, true) => ("rgba(192, 80, 77, 0.35)", "rgba(192, 80, 77, 0.2)"),
+                    };
+                    context.set_stroke_style(wick_color);
+                    context.line(x + rect_width, cvd_y(candle.high), x + rect_width, cvd_y(candle.low));
+
+                    let body_top = cvd_y(candle.open.max(candle.close));
+                    let body_height = (cvd_y(candle.open.min(candle.close)) - body_top).max(1.0);
+                    context.set_fill_style(color);
+                    context.fill_rect(x + rect_width / 2.0, body_top, rect_width, body_height);
+                }
+
+                let font_size = (12.0 * self.dpi).round();
+                context.set_font_size(font_size);
+                if candles.iter().any(|(_, candle)| candle.partial) {
+                    context.set_fill_style("rgba(200, 200, 200, 0.6)");
+                    context.fill_text("partial period", 4.0, font_size + 4.0);
+                }
+                match self.oi_mode {
+                    OiMode::Points if !oi_points.is_empty() => {
+                        let max_oi = oi_points.iter().map(|(_, point)| point.open_interest).fold(0.0, f64::max);
+                        let min_oi = oi_points.iter().map(|(_, point)| point.open_interest).fold(f64::MAX, f64::min);
+                        let oi_y = |oi: f64| if max_oi == min_oi {
+                            self.height - (padded_height / 2.0 + padding)
+                        } else {
+                            self.height - (padded_height * (oi - min_oi) / (max_oi - min_oi) + padding)
+                        };
+
+                        for (time, point) in oi_points {
+                            let x = ((*time as f64 - time_difference) / zoom_scale) * self.width + rect_width;
+                            let y = oi_y(point.open_interest);
+                            context.set_fill_style("white");
+                            context.arc(x, y, 2.0*self.dpi);
+
+                            if self.x_zoom < 18.0 && point.change != 0.0 {
+                                let diff = format!("{:+}", point.change.round());
+                                context.set_fill_style("rgba(200, 200, 200, 0.8)");
+                                let text_width = context.measure_text(&diff);
+                                context.fill_text(&diff, x - text_width - 12.0, y + (3.0*self.dpi));
+                            }
+                        }
+                        Self::draw_oi_axis(context, self.width, font_size, &[(max_oi, oi_y(max_oi)), (min_oi, oi_y(min_oi))]);
+                    },
+                    OiMode::Histogram if !oi_points.is_empty() => {
+                        let max_change = oi_points.iter().map(|(_, point)| point.change.abs()).fold(0.0, f64::max);
+                        if max_change > 0.0 {
+                            let zero_y = self.height / 2.0;
+                            let bar_height = |change: f64| padded_height / 2.0 * change / max_change;
+
+                            for (time, point) in oi_points {
+                                let x = ((*time as f64 - time_difference) / zoom_scale) * self.width;
+                                let height = bar_height(point.change);
+                                context.set_fill_style(if point.change >= 0.0 { "rgba(255, 255, 255, 0.35)" } else { "rgba(255, 165, 0, 0.35)" });
+                                context.fill_rect(x + rect_width / 2.0, zero_y - height.max(0.0), rect_width, height.abs());
+                            }
+                            Self::draw_oi_axis(context, self.width, font_size, &[(max_change, zero_y - bar_height(max_change)), (-max_change, zero_y + bar_height(max_change))]);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            None => {
+                log("No klines");
+            }
+        }
+    }
+
+    /// Labels of the OI scale on the right edge, kept apart from the CVD scale.
+    fn draw_oi_axis(context: &mut S, width: f64, font_size: f64, labels: &[(f64, f64)]) {
+        context.set_fill_style("rgba(200, 200, 200, 0.6)");
+        for (value, y) in labels {
+            let label = format!("{:.0}", value);
+            let text_width = context.measure_text(&label);
+            context.fill_text(&label, width - text_width - 4.0, y + font_size / 3.0);
+        }
+    }
+}
+pub struct CanvasIndiFunding<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) x_zoom: f64,
+    pub(crate) timeframe: Timeframe,
+}
+impl<S: Surface> CanvasIndiFunding<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            x_zoom: 30.0,
+            timeframe: Timeframe::M1,
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+
+    /// `premiums` are per kline in percent, `funding_history` settled rates by funding time.
+    pub fn render(&mut self, last_kline_open: u64, premiums: &[(u64, f64)], funding_history: &[(u64, f64)], current: Option<MarkPriceUpdate>) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let time_difference = last_kline_open as f64 + interval - zoom_scale;
+        let rect_width: f64 = (self.width / self.x_zoom)/2.0;
+        let time_x = |time: u64| ((time as f64 - time_difference) / zoom_scale) * self.width;
+
+        let padded_half = self.height * 0.45;
+        let zero_y = self.height / 2.0;
+        context.set_stroke_style("rgba(200, 200, 200, 0.2)");
+        context.set_line_width(self.dpi);
+        context.line(0.0, zero_y, self.width, zero_y);
+
+        let max_premium = premiums.iter().map(|(_, premium)| premium.abs()).fold(0.0, f64::max);
+        if max_premium > 0.0 {
+            for (open_time, premium) in premiums {
+                let height = padded_half * premium / max_premium;
+                context.set_fill_style(if *premium >= 0.0 { "rgba(81, 205, 160, 0.6)" } else { "rgba(192, 80, 77, 0.6)" });
+                context.fill_rect(time_x(*open_time) + rect_width / 2.0, zero_y - height.max(0.0), rect_width, height.abs());
+            }
+        }
+
+        let max_rate = funding_history.iter().map(|(_, rate)| rate.abs())
+            .chain(current.map(|update| update.funding_rate.abs()))
+            .fold(0.0, f64::max);
+        if max_rate > 0.0 {
+            let rate_y = |rate: f64| zero_y - padded_half * rate / max_rate;
+            context.set_stroke_style("rgba(180, 140, 255, 0.9)");
+            context.set_line_width(1.5 * self.dpi);
+            // a settled rate applied over the period leading up to its funding time
+            for window in funding_history.windows(2) {
+                let (start, _) = window[0];
+                let (end, rate) = window[1];
+                let (start_x, end_x) = (time_x(start), time_x(end));
+                context.line(start_x, rate_y(window[0].1), start_x, rate_y(rate));
+                context.line(start_x, rate_y(rate), end_x, rate_y(rate));
+            }
+            // the rate that settles next, carried to the right edge
+            if let Some(update) = current {
+                context.set_stroke_style("rgba(180, 140, 255, 0.5)");
+                let start_x = match funding_history.last() {
+                    Some(&(last_funding, last_rate)) => {
+                        let start_x = time_x(last_funding);
+                        context.line(start_x, rate_y(last_rate), start_x, rate_y(update.funding_rate));
+                        start_x
+                    },
+                    None => 0.0,
+                };
+                context.line(start_x, rate_y(update.funding_rate), self.width, rate_y(update.funding_rate));
+            }
+        }
+
+        if let Some(update) = current {
+            let font_size = (12.0 * self.dpi).round();
+            context.set_font_size(font_size);
+            context.set_fill_style("rgba(200, 200, 200, 0.8)");
+            let mut label = format!(
+                "funding {:.4}%  next in {}",
+                update.funding_rate * 100.0,
+                format_countdown(update.next_funding_time.saturating_sub(update.time)),
+            );
+            if let Some((_, premium)) = premiums.last() {
+                label.push_str(&format!("  premium {:+.4}%", premium));
+            }
+            context.fill_text(&label, 4.0 * self.dpi, font_size + 2.0 * self.dpi);
+        }
+    }
+}
+pub struct CanvasIndiLiquidations<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    pub(crate) x_zoom: f64,
+    pub(crate) timeframe: Timeframe,
+}
+impl<S: Surface> CanvasIndiLiquidations<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            x_zoom: 30.0,
+            timeframe: Timeframe::M1,
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+
+    /// Short liquidations above the zero line, long liquidations below.
+    pub fn render(&mut self, last_kline_open: u64, totals: &[(u64, LiquidationTotals)]) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+
+        let interval = self.timeframe.as_ms() as f64;
+        let zoom_scale = self.x_zoom * interval;
+        let time_difference = last_kline_open as f64 + interval - zoom_scale;
+        let rect_width: f64 = (self.width / self.x_zoom)/2.0;
+
+        let zero_y = self.height / 2.0;
+        context.set_stroke_style("rgba(200, 200, 200, 0.2)");
+        context.set_line_width(self.dpi);
+        context.line(0.0, zero_y, self.width, zero_y);
+
+        let max_quantity = totals.iter().map(|(_, totals)| totals.long.max(totals.short)).fold(0.0, f64::max);
+        if max_quantity <= 0.0 {
+            return;
+        }
+        let padded_half = self.height * 0.45;
+        let font_size = (12.0 * self.dpi).round();
+        context.set_font_size(font_size);
+        for (open_time, totals) in totals {
+            let x = ((*open_time as f64 - time_difference) / zoom_scale) * self.width;
+            let short_height = padded_half * totals.short / max_quantity;
+            let long_height = padded_half * totals.long / max_quantity;
+
+            context.set_fill_style(liquidation_color(LiquidationSide::Short));
+            context.fill_rect(x + rect_width / 2.0, zero_y - short_height, rect_width, short_height);
+            context.set_fill_style(liquidation_color(LiquidationSide::Long));
+            context.fill_rect(x + rect_width / 2.0, zero_y, rect_width, long_height);
+
+            if self.x_zoom < 18.0 {
+                context.set_fill_style("rgba(200, 200, 200, 0.8)");
+                if totals.short > 0.0 {
+                    context.fill_text(&format!("{:.2}", totals.short), x + rect_width / 2.0, zero_y - short_height - 2.0 * self.dpi);
+                }
+                if totals.long > 0.0 {
+                    context.fill_text(&format!("{:.2}", totals.long), x + rect_width / 2.0, zero_y + long_height + font_size);
+                }
+            }
+        }
+    }
+}
+/// Trades kept for the tape, enough to fill it even with aggregation on.
+pub(crate) const MAX_TAPE_TRADES: usize = 2000;
+
+pub struct CanvasTape<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    trades: VecDeque<Trade>,
+    pub(crate) config: TapeConfig,
+}
+impl<S: Surface> CanvasTape<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            trades: VecDeque::new(),
+            config: TapeConfig::default(),
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+    pub fn reset(&mut self) {
+        self.trades.clear();
+    }
+
+    pub fn push(&mut self, trades: &[Trade]) {
+        self.trades.extend(trades);
+        while self.trades.len() > MAX_TAPE_TRADES {
+            self.trades.pop_front();
+        }
+    }
+
+    /// Newest prints at the top: time, price and size, highlighted by size.
+    pub fn render(&mut self, tick_size: TickSize) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+
+        let font_size = (11.0 * self.dpi).round();
+        let row_height = font_size + 6.0 * self.dpi;
+        let rows = (self.height / row_height) as usize;
+        let prints: Vec<_> = prints(&self.trades, &self.config).into_iter().take(rows).collect();
+
+        let highlight_size = if self.config.highlight_size > 0.0 {
+            self.config.highlight_size
+        } else {
+            prints.iter().map(|print| print.quantity).fold(0.0, f64::max)
+        };
+        context.set_font_size(font_size);
+        for (i, print) in prints.iter().enumerate() {
+            let y = i as f64 * row_height;
+            let strength = if highlight_size > 0.0 { (print.quantity / highlight_size).min(1.0) } else { 0.0 };
+            let rgb = if print.is_buyer_maker { "192, 80, 77" } else { "81, 205, 160" };
+
+            context.set_fill_style(&format!("rgba({}, {:.2})", rgb, 0.05 + strength * 0.55));
+            context.fill_rect(0.0, y, self.width, row_height - self.dpi);
+
+            context.set_fill_style(if strength >= 1.0 { "white" } else { "rgba(200, 200, 200, 0.9)" });
+            let text_y = y + row_height - 4.0 * self.dpi;
+            context.fill_text(&format_time_of_day(print.time), 4.0 * self.dpi, text_y);
+
+            let price = print.price.format(tick_size);
+            let price_width = context.measure_text(&price);
+            context.fill_text(&price, self.width * 0.62 - price_width, text_y);
+
+            let size = if print.trade_count > 1 {
+                format!("{:.3} ({})", print.quantity, print.trade_count)
+            } else {
+                format!("{:.3}", print.quantity)
+            };
+            let size_width = context.measure_text(&size);
+            context.fill_text(&size, self.width - size_width - 4.0 * self.dpi, text_y);
+        }
+    }
+}
+pub struct CanvasBubbleTrades<S = WebSurface> {
+    surface: S,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    dpi: f64,
+    trades: Vec<Trade>,
+    sell_trade_counts: BTreeMap<u64, usize>,
+    buy_trade_counts: BTreeMap<u64, usize>,
+}
+impl<S: Surface> CanvasBubbleTrades<S> {
+    pub fn new(surface: S, width: f64, height: f64) -> Self {
+        let dpi = surface.dpi();
+        Self {
+            surface,
+            width,
+            height,
+            dpi,
+            trades: Vec::new(),
+            sell_trade_counts: BTreeMap::new(),
+            buy_trade_counts: BTreeMap::new(),
+        }
+    }
+    pub fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.width = new_width;
+        self.height = new_height;
+        self.dpi = self.surface.dpi();
+    }
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    pub fn into_surface(self) -> S {
+        self.surface
+    }
+    pub fn reset(&mut self) {
+        self.trades.clear();
+        self.sell_trade_counts.clear();
+        self.buy_trade_counts.clear();
+    }
+
+    pub fn render(&mut self, trades_buffer: &Vec<Trade>, last_update: u64) {
+        let context = &mut self.surface;
+        context.clear(self.width, self.height);
+
+        let padding_percentage = 0.25; 
+        let padded_height = self.height * (1.0 - padding_percentage);
+        let thirty_seconds_ago = last_update.saturating_sub(30 * 1000);
+
+        // Trade counts
+        self.buy_trade_counts.retain(|&time, _| time >= thirty_seconds_ago);
+        self.sell_trade_counts.retain(|&time, _| time >= thirty_seconds_ago);
+
+        let sell_count = trades_buffer.iter()
+            .filter(|trade| trade.is_buyer_maker)
+            .count();
+        self.sell_trade_counts.insert(last_update, sell_count);
+        let buy_count = trades_buffer.iter()
+            .filter(|trade| !trade.is_buyer_maker)
+            .count();
+        self.buy_trade_counts.insert(last_update, buy_count);
+
+        let max_trade_count = self.sell_trade_counts.iter().chain(self.buy_trade_counts.iter())
+            .map(|(_, &count)| count)
+            .fold(0, usize::max);
+        let y_scale = padded_height / (2 * max_trade_count) as f64;
+
+        context.set_stroke_style("rgba(200, 50, 50, 0.4)");
+        let mut previous_point: Option<(f64, f64)> = None;
+        for (&time, &count) in self.sell_trade_counts.iter() {
+            let x = ((time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
+            let y = self.height * padding_percentage / 2.0 + padded_height / 2.0 + count as f64 * y_scale;
+            if let Some((prev_x, prev_y)) = previous_point {
+                context.line(prev_x, prev_y, x, y);
+            }
+            previous_point = Some((x, y));
+        }
+        context.set_stroke_style("rgba(50, 200, 50, 0.4)");
+        previous_point = None;
+        for (&time, &count) in self.buy_trade_counts.iter() {
+            let x = ((time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
+            let y = self.height * padding_percentage / 2.0 + padded_height / 2.0 - count as f64 * y_scale;
+            if let Some((prev_x, prev_y)) = previous_point {
+                context.line(prev_x, prev_y, x, y);
+            }
+            previous_point = Some((x, y));
+        }
+
+        // Bubble trades
+        self.trades.retain(|trade| trade.time >= thirty_seconds_ago);
+
+        for trade in trades_buffer {
+            self.trades.push(*trade);
+        }
+        
+        let max_quantity = self.trades.iter().map(|trade| trade.quantity).fold(0.0, f64::max);
+        let max_radius = 18.0*self.dpi;
+        let y_min = self.trades.iter().map(|trade| trade.price.ticks()).min().unwrap_or(0) as f64;
+        let y_max = self.trades.iter().map(|trade| trade.price.ticks()).max().unwrap_or(0) as f64;
+
+        let sell_trades: Vec<_> = self.trades.iter().filter(|trade| trade.is_buyer_maker).collect();
+        let buy_trades: Vec<_> = self.trades.iter().filter(|trade| !trade.is_buyer_maker).collect();
+
+        context.set_fill_style("rgba(192, 80, 77, 1)");
+        for trade in &sell_trades {
+            let radius = ((trade.quantity / max_quantity) * 20.0 * self.dpi).min(max_radius);    
+            if radius > 1.0 {
+                let x = ((trade.time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
+                let y = ((trade.price.ticks() as f64 - y_min) / (y_max - y_min)) * padded_height + self.height * padding_percentage / 2.0;
+
+                context.arc(x, self.height - y, radius);
+            }
+        }
+        context.set_fill_style("rgba(81, 205, 160, 1)");
+        for trade in &buy_trades {
+            let radius = ((trade.quantity / max_quantity) * 20.0 * self.dpi).min(max_radius);
+            if radius > 1.0 {
+                let x = ((trade.time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
+                let y = ((trade.price.ticks() as f64 - y_min) / (y_max - y_min)) * padded_height + self.height * padding_percentage / 2.0;
+
+                context.arc(x, self.height - y, radius);
+            }
+        }
+    }
+}
+
+fn format_stat(row: StatsRow, value: f64) -> String {
+    match row {
+        StatsRow::Trades => format!("{}", value as usize),
+        StatsRow::DeltaPercent => format!("{:.1}%", value),
+        _ if value.abs() >= 1000.0 => format!("{:.1}k", value / 1000.0),
+        _ => format!("{:.2}", value),
+    }
+}
+
+fn format_countdown(ms: u64) -> String {
+    let seconds = ms / 1000;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn liquidation_color(side: LiquidationSide) -> &'static str {
+    match side {
+        LiquidationSide::Long => "rgba(255, 120, 40, 0.75)",
+        LiquidationSide::Short => "rgba(60, 170, 255, 0.75)",
+    }
+}
+
+/// Best bid, best ask and the microprice between them as horizontal lines from `left` to `right`.
+#[allow(clippy::too_many_arguments)]
+fn draw_top_of_book_lines<S: Surface>(context: &mut S, left: f64, right: f64, height: f64, dpi: f64, y_min: f64, y_max: f64, top_of_book: &TopOfBook, tick_size: TickSize) {
+    let price_y = |price: f64| height - height * (price - y_min) / (y_max - y_min);
+    context.set_line_width(dpi);
+
+    context.set_stroke_style("rgba(81, 205, 160, 0.6)");
+    let bid_y = price_y(top_of_book.best_bid.to_f64(tick_size));
+    context.line(left, bid_y, right, bid_y);
+
+    context.set_stroke_style("rgba(192, 80, 77, 0.6)");
+    let ask_y = price_y(top_of_book.best_ask.to_f64(tick_size));
+    context.line(left, ask_y, right, ask_y);
+
+    context.set_stroke_style("rgba(255, 255, 255, 0.4)");
+    let micro_y = price_y(top_of_book.microprice(tick_size));
+    context.line(left, micro_y, right, micro_y);
+}
+
+/// `HH:MM:SS.mmm` in UTC.
+fn format_time_of_day(time: u64) -> String {
+    let ms_of_day = time % DAY_IN_MS;
+    let seconds = ms_of_day / 1000;
+    format!("{:02}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, ms_of_day % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::footprint::FootprintConfig;
+    use crate::orderbook::Side;
+    use crate::surface::{DrawCommand, RecordingSurface};
+    use crate::TradeGroups;
+
+    fn tick_size() -> TickSize {
+        TickSize::parse("0.1").unwrap()
+    }
+    fn price(s: &str) -> Price {
+        Price::parse(s, tick_size()).unwrap()
+    }
+    fn kline(open_time: u64, open: &str, high: &str, low: &str, close: &str) -> Kline {
+        Kline {
+            open_time,
+            open: price(open),
+            high: price(high),
+            low: price(low),
+            close: price(close),
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            cum_volume_delta: 0.0,
+            close_time: open_time + 59_999,
+        }
+    }
+    /// Coordinates to 1/1000 px, so expectations can be written as exact values.
+    fn round(value: f64) -> f64 {
+        (value * 1000.0).round() / 1000.0
+    }
+    fn lines(commands: &[DrawCommand]) -> Vec<(f64, f64, f64, f64, &str)> {
+        commands.iter().filter_map(|command| match command {
+            DrawCommand::Line { x1, y1, x2, y2, color, .. } => Some((round(*x1), round(*y1), round(*x2), round(*y2), color.as_str())),
+            _ => None,
+        }).collect()
+    }
+    fn rects(commands: &[DrawCommand]) -> Vec<(f64, f64, f64, f64, &str)> {
+        commands.iter().filter_map(|command| match command {
+            DrawCommand::FillRect { x, y, width, height, color } => Some((round(*x), round(*y), round(*width), round(*height), color.as_str())),
+            _ => None,
+        }).collect()
+    }
+    fn texts(commands: &[DrawCommand]) -> Vec<&str> {
+        commands.iter().filter_map(|command| match command {
+            DrawCommand::Text { text, .. } => Some(text.as_str()),
+            _ => None,
+        }).collect()
+    }
+
+    // 600x400 with the default zoom of 30 klines: the last kline opens at x = 580 and each
+    // is 20 wide. Prices 90..110 map to 400..0, 20 px per price unit.
+    #[test]
+    fn main_draws_klines() {
+        let mut canvas = CanvasMain::new(RecordingSurface::default(), 600.0, 400.0);
+        canvas.stats_rows.clear();
+        let (first, second) = (kline(0, "100", "104", "95", "102"), kline(60_000, "102", "103", "97", "98"));
+        let klines = vec![(&first.open_time, &first), (&second.open_time, &second)];
+        canvas.render(90.0, 110.0, &klines, &[], Vec::new(), tick_size(), 40.0);
+
+        let commands = &canvas.surface().commands;
+        assert_eq!(commands[0], DrawCommand::Clear { width: 600.0, height: 400.0 });
+        let lines = lines(commands);
+        assert_eq!(lines, vec![
+            // high and low wicks, then the body
+            (560.0, 120.0, 580.0, 120.0, "rgba(200, 200, 200, 0.5)"),
+            (560.0, 300.0, 580.0, 300.0, "rgba(200, 200, 200, 0.5)"),
+            (570.0, 200.0, 570.0, 160.0, "rgba(50, 200, 50, 1)"),
+            (580.0, 140.0, 600.0, 140.0, "rgba(200, 200, 200, 0.5)"),
+            (580.0, 260.0, 600.0, 260.0, "rgba(200, 200, 200, 0.5)"),
+            (590.0, 160.0, 590.0, 240.0, "rgba(200, 50, 50, 1)"),
+        ]);
+        // 20 px per kline, so labels are every 3 minutes
+        assert_eq!(texts(commands), vec!["00:00"]);
+    }
+
+    #[test]
+    fn time_labels_snap_to_clock_intervals() {
+        // far enough apart to label every kline
+        assert_eq!(time_label_step(Timeframe::M1, 60.0, 60.0), MINUTE_IN_MS);
+        assert_eq!(time_label_step(Timeframe::M1, 20.0, 60.0), 3 * MINUTE_IN_MS);
+        assert_eq!(time_label_step(Timeframe::M1, 7.0, 60.0), 15 * MINUTE_IN_MS);
+        // steps have to be whole numbers of klines, 3m klines skip 5m
+        assert_eq!(time_label_step(Timeframe::M3, 20.0, 60.0), 15 * MINUTE_IN_MS);
+        assert_eq!(time_label_step(Timeframe::H1, 20.0, 60.0), 4 * HOUR_IN_MS);
+        assert_eq!(time_label_step(Timeframe::H4, 1.0, 60.0), DAY_IN_MS);
+    }
+
+    #[test]
+    fn main_draws_footprint() {
+        let mut canvas = CanvasMain::new(RecordingSurface::default(), 600.0, 400.0);
+        canvas.stats_rows = vec![StatsRow::Delta];
+        let kline = kline(60_000, "100", "100", "99.5", "99.5");
+        let klines = vec![(&kline.open_time, &kline)];
+        let trade_groups = TradeGroups::from_trades(vec![
+            Trade { price: price("100"), quantity: 1.0, time: 60_001, is_buyer_maker: false },
+            Trade { price: price("99.5"), quantity: 2.0, time: 60_002, is_buyer_maker: true },
+        ]);
+        let footprint = Footprint::new(&trade_groups, 5, &FootprintConfig::default());
+        canvas.render(90.0, 110.0, &klines, &[], vec![(60_000, footprint)], tick_size(), 40.0);
+
+        let commands = &canvas.surface().commands;
+        let rects = rects(commands);
+        // buys grow right of the candle's center at 590, sells left, scaled to the largest level
+        assert!(rects.contains(&(594.0, 195.0, 5.0, 10.0, "rgba(81, 205, 160, 1)")));
+        assert!(rects.iter().any(|&(x, y, width, height, _)| (x, y, width, height) == (586.0, 205.0, -10.0, 10.0)));
+        // POC highlight on the sell level
+        assert!(rects.contains(&(580.0, 205.0, 20.0, 10.0, "rgba(255, 215, 0, 0.15)")));
+        assert!(!lines(commands).iter().any(|line| line.4 == "rgba(200, 200, 200, 0.5)"), "no wicks with a footprint");
+        assert!(texts(commands).contains(&"delta"));
+    }
+
+    #[test]
+    fn main_draws_oi_weighted_price_near_epoch() {
+        let mut canvas = CanvasMain::new(RecordingSurface::default(), 600.0, 400.0);
+        let points = vec![
+            (0, OiPoint { open_interest: 10.0, change: 0.0, weighted_price: Some(100.0) }),
+            (60_000, OiPoint { open_interest: 12.0, change: 2.0, weighted_price: Some(101.0) }),
+        ];
+        canvas.render_oi_weighted_price(90.0, 110.0, 60_000, &points);
+        assert_eq!(lines(&canvas.surface().commands), vec![(570.0, 200.0, 590.0, 180.0, "rgba(100, 180, 255, 0.8)")]);
+    }
+
+    #[test]
+    fn main_skips_liquidations_left_of_view() {
+        let mut canvas = CanvasMain::new(RecordingSurface::default(), 600.0, 400.0);
+        let liquidation = |at: &str, quantity: f64| Liquidation { side: LiquidationSide::Long, price: price(at), quantity, time: 0 };
+        // the kline at 0 ends just left of the 30 in view
+        let liquidations = vec![(0, liquidation("100", 4.0)), (30 * 60_000, liquidation("101", 1.0))];
+        canvas.render_liquidations(90.0, 110.0, 30 * 60_000, &liquidations, tick_size());
+
+        let arcs: Vec<_> = canvas.surface().commands.iter().filter_map(|command| match command {
+            DrawCommand::Arc { x, y, .. } => Some((round(*x), round(*y))),
+            _ => None,
+        }).collect();
+        assert_eq!(arcs, vec![(590.0, 180.0)]);
+    }
+
+    // Near the epoch the zoomed out window starts before time zero.
+    #[test]
+    fn cvd_draws_candles_near_epoch() {
+        let mut canvas = CanvasIndiCVD::new(RecordingSurface::default(), 600.0, 400.0);
+        let candles = vec![
+            (0, CvdCandle { open: 0.0, high: 2.0, low: 0.0, close: 2.0, partial: false }),
+            (60_000, CvdCandle { open: 2.0, high: 2.0, low: 1.0, close: 1.0, partial: true }),
+        ];
+        let oi_points = vec![(60_000, OiPoint { open_interest: 10.0, change: 0.0, weighted_price: None })];
+        canvas.render(&candles, &oi_points);
+
+        let commands = &canvas.surface().commands;
+        // cvd 0..2 maps to 380..20
+        assert_eq!(rects(commands), vec![
+            (565.0, 20.0, 10.0, 360.0, "rgba(81, 205, 160, 1)"),
+            (585.0, 20.0, 10.0, 180.0, "rgba(192, 80, 77, 0.35)"),
+        ]);
+        assert!(texts(commands).contains(&"partial period"));
+        assert!(commands.iter().any(|command| matches!(command, DrawCommand::Arc { x, .. } if round(*x) == 590.0)));
+    }
+
+    // Wide enough at a zoom of 10 for 70 px half klines, which get their volumes written on.
+    #[test]
+    fn volume_draws_buy_and_sell_bars() {
+        let mut canvas = CanvasIndicatorVolume::new(RecordingSurface::default(), 1400.0, 400.0);
+        canvas.x_zoom = 10.0;
+        canvas.render(&Vec::new());
+        assert_eq!(canvas.surface().commands, vec![DrawCommand::Clear { width: 1400.0, height: 400.0 }]);
+
+        let first = Kline { buy_volume: 2.0, sell_volume: 1.0, ..kline(0, "100", "100", "100", "100") };
+        let second = Kline { buy_volume: 1.0, sell_volume: 4.0, ..kline(60_000, "100", "100", "100", "100") };
+        canvas.render(&vec![(&first.open_time, &first), (&second.open_time, &second)]);
+        let commands = &canvas.surface().commands;
+        // scaled to the largest volume of either side, buys on the right half
+        assert_eq!(rects(commands), vec![
+            (1190.0, 200.0, 60.0, 200.0, "rgba(81, 205, 160, 1)"),
+            (1130.0, 300.0, 60.0, 100.0, "rgba(192, 80, 77, 1)"),
+            (1330.0, 300.0, 60.0, 100.0, "rgba(81, 205, 160, 1)"),
+            (1270.0, 0.0, 60.0, 400.0, "rgba(192, 80, 77, 1)"),
+        ]);
+        assert_eq!(texts(commands), vec!["2.00", "1.00", "1.00", "4.00"]);
+    }
+
+    #[test]
+    fn bubbles_cover_the_last_thirty_seconds() {
+        let trade = |price: &str, quantity: f64, time: u64, is_buyer_maker: bool| Trade { price: self::price(price), quantity, time, is_buyer_maker };
+        let arcs = |commands: &[DrawCommand]| -> Vec<(f64, f64, f64, String)> {
+            commands.iter().filter_map(|command| match command {
+                DrawCommand::Arc { x, y, radius, color } => Some((round(*x), round(*y), round(*radius), color.clone())),
+                _ => None,
+            }).collect()
+        };
+        let mut canvas = CanvasBubbleTrades::new(RecordingSurface::default(), 300.0, 400.0);
+
+        // less than thirty seconds after the epoch
+        canvas.render(&vec![trade("100", 1.0, 4_000, true), trade("101", 2.0, 10_000, false)], 10_000);
+        assert_eq!(arcs(&canvas.surface().commands), vec![
+            (40.0, 350.0, 10.0, "rgba(192, 80, 77, 1)".to_string()),
+            (100.0, 50.0, 18.0, "rgba(81, 205, 160, 1)".to_string()),
+        ]);
+
+        canvas.render(&Vec::new(), 40_000);
+        let commands = &canvas.surface().commands;
+        // trade counts from the previous update to this one, sells below the middle
+        assert_eq!(lines(commands), vec![
+            (0.0, 350.0, 300.0, 200.0, "rgba(200, 50, 50, 0.4)"),
+            (0.0, 50.0, 300.0, 200.0, "rgba(50, 200, 50, 0.4)"),
+        ]);
+        let arcs = arcs(commands);
+        assert_eq!(arcs.len(), 1, "the sell is more than thirty seconds old");
+        assert_eq!((arcs[0].0, arcs[0].2), (0.0, 18.0));
+    }
+
+    #[test]
+    fn orderbook_draws_levels() {
+        let mut bids = PriceLevels::new(Side::Bid);
+        bids.update(price("99.9"), 1.0);
+        bids.update(price("99.8"), 2.0);
+        bids.update(price("50"), 100.0);
+        let mut asks = PriceLevels::new(Side::Ask);
+        asks.update(price("100"), 4.0);
+
+        let mut canvas = CanvasOrderbook::new(RecordingSurface::default(), 240.0, 400.0);
+        canvas.render(90.0, 110.0, &bids, &asks, &[], &Vec::new(), 0, tick_size(), 200.0);
+        let commands = &canvas.surface().commands;
+        // out of view levels don't count towards the scale
+        assert!(texts(commands).contains(&"4.0"));
+        // bar length is quantity over 1.25x the largest, of 220 px
+        assert_eq!(rects(commands), vec![
+            (60.0, 203.0, 88.0, 2.0, "rgba(81, 205, 160, 1)"),
+            (60.0, 201.0, 44.0, 2.0, "rgba(81, 205, 160, 1)"),
+            (60.0, 199.0, 176.0, 2.0, "rgba(192, 80, 77, 1)"),
+        ]);
+
+        canvas.depth_mode = DepthMode::Cumulative;
+        canvas.render(90.0, 110.0, &bids, &asks, &[], &Vec::new(), 0, tick_size(), 200.0);
+        let surface = canvas.into_surface();
+        let polygons: Vec<(Vec<(f64, f64)>, &str)> = surface.commands.iter().filter_map(|command| match command {
+            DrawCommand::Polygon { points, color } => Some((points.iter().map(|&(x, y)| (round(x), round(y))).collect(), color.as_str())),
+            _ => None,
+        }).collect();
+        assert_eq!(polygons, vec![
+            // 1 at the best bid, then 1 + 2 below it
+            (vec![(60.0, 201.0), (104.0, 201.0), (104.0, 203.0), (192.0, 203.0), (192.0, 205.0), (60.0, 205.0)], "rgba(81, 205, 160, 0.6)"),
+            (vec![(60.0, 201.0), (236.0, 201.0), (236.0, 199.0), (60.0, 199.0)], "rgba(192, 80, 77, 0.6)"),
+        ]);
+        assert!(!rects(&surface.commands).iter().any(|rect| rect.4 == "rgba(81, 205, 160, 0.6)"), "no bar per level");
+    }
+}
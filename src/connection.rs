@@ -0,0 +1,267 @@
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, RwLock};
+
+use wasm_bindgen::{JsCast, prelude::*};
+use web_sys::{MessageEvent, WebSocket, window};
+
+use crate::canvas::{CanvasBubbleTrades, CanvasTape};
+use crate::market::MarketState;
+use crate::replay::{RecordKind, Recording};
+use crate::utils::now_ms;
+use crate::{call_js_callback, log};
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    /// The stream dropped and a reconnect is scheduled.
+    Reconnecting,
+}
+
+/// Exponential reconnect delays, doubling from `base_ms` up to `max_ms`.
+#[derive(Debug)]
+pub struct Backoff {
+    base_ms: u32,
+    max_ms: u32,
+    attempt: u32,
+}
+impl Backoff {
+    pub fn new(base_ms: u32, max_ms: u32) -> Self {
+        Self { base_ms, max_ms, attempt: 0 }
+    }
+    pub fn next_delay(&mut self) -> u32 {
+        let delay = self.base_ms.saturating_mul(1 << self.attempt.min(16)).min(self.max_ms);
+        self.attempt += 1;
+        delay
+    }
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(1000, 30_000)
+    }
+}
+
+/// Everything a stream connection needs to outlive a single `WebSocket`, so a dropped
+/// connection can be reopened from its own close handler.
+pub(crate) struct StreamSession {
+    pub symbol: String,
+    pub market: Arc<RwLock<MarketState>>,
+    pub canvas_bubble: Rc<RefCell<CanvasBubbleTrades>>,
+    pub canvas_tape: Rc<RefCell<CanvasTape>>,
+    pub recorder: Rc<RefCell<Option<Recording>>>,
+    pub resync_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    pub reconnect_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    pub state: Rc<Cell<ConnectionState>>,
+    pub websocket: Rc<RefCell<Option<WebSocket>>>,
+    /// Bumped whenever the stream is replaced, so handlers of an older session stand down.
+    pub generation: Rc<Cell<u32>>,
+    pub backoff: RefCell<Backoff>,
+    /// Handlers of the current socket, dropped along with the session or on reconnect.
+    pub handlers: RefCell<Option<SocketHandlers>>,
+}
+
+/// Closures set as a socket's handlers. They only hold a weak reference to the session, so
+/// keeping them in it doesn't keep it alive.
+pub(crate) struct SocketHandlers {
+    _onopen: Closure<dyn FnMut()>,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onerror: Closure<dyn FnMut()>,
+    _onclose: Closure<dyn FnMut()>,
+}
+
+/// Opens the stream for `session` and keeps it open until the session's generation changes
+/// or the session is dropped.
+pub(crate) fn connect(session: Rc<StreamSession>, generation: u32, is_reconnect: bool) {
+    // the socket that dropped, if any, is done with its handlers
+    let previous = session.websocket.borrow_mut().take();
+    if let Some(ws) = previous {
+        close(&ws);
+    }
+    session.handlers.borrow_mut().take();
+
+    let (stream_url, subscribe_messages) = match session.market.read() {
+        Ok(market) => {
+            let timeframe = market.timeframe();
+            (market.connector().stream_url(&session.symbol, timeframe), market.connector().subscribe_messages(&session.symbol, timeframe))
+        },
+        Err(e) => {
+            log(&format!("Failed to acquire lock on market state: {}", e));
+            return;
+        }
+    };
+    let ws = match WebSocket::new(&stream_url) {
+        Ok(ws) => ws,
+        Err(e) => {
+            log(&format!("Failed to open websocket: {:?}", e));
+            schedule_reconnect(session, generation);
+            return;
+        }
+    };
+    session.state.set(ConnectionState::Connecting);
+
+    let weak_session = Rc::downgrade(&session);
+    let ws_clone = ws.clone();
+    let onopen_callback = Closure::wrap(Box::new(move || {
+        let open_session = match current(&weak_session, generation) {
+            Some(session) => session,
+            None => return,
+        };
+        open_session.state.set(ConnectionState::Connected);
+        open_session.backoff.borrow_mut().reset();
+
+        for message in &subscribe_messages {
+            if let Err(e) = ws_clone.send_with_str(message) {
+                log(&format!("Failed to send subscribe message: {:?}", e));
+            }
+        }
+        if is_reconnect {
+            log(&format!("Reconnected to {}, resyncing", open_session.symbol));
+            let gap_start = match open_session.market.write() {
+                Ok(mut market) => market.reset_for_reconnect(),
+                Err(e) => {
+                    log(&format!("Failed to acquire write lock on market state: {}", e));
+                    return;
+                }
+            };
+            call_js_callback(&open_session.resync_callback);
+            if let Some(callback) = open_session.reconnect_callback.borrow().as_ref() {
+                if let Err(e) = callback.call1(&JsValue::NULL, &JsValue::from_f64(gap_start as f64)) {
+                    log(&format!("JS callback failed: {:?}", e));
+                }
+            }
+        }
+    }) as Box<dyn FnMut()>);
+    ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+
+    let weak_session = Rc::downgrade(&session);
+    let onmessage_callback = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let message_session = match weak_session.upgrade() {
+            Some(session) => session,
+            None => return,
+        };
+        if let Ok(data) = event.data().dyn_into::<js_sys::JsString>() {
+            let data_str: String = data.into();
+            if let Some(recording) = message_session.recorder.borrow_mut().as_mut() {
+                recording.push(now_ms(), RecordKind::Stream, &data_str);
+            }
+
+            let effects = match message_session.market.write() {
+                Ok(mut market) => market.apply_message(&data_str),
+                Err(e) => {
+                    log(&format!("market state locked on message: {:?}", e));
+                    return;
+                }
+            };
+            if effects.resync_needed {
+                call_js_callback(&message_session.resync_callback);
+            }
+            for (update_time, trades) in &effects.flushed_trades {
+                message_session.canvas_bubble.borrow_mut().render(trades, *update_time);
+                message_session.canvas_tape.borrow_mut().push(trades);
+            }
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+
+    let onerror_callback = Closure::wrap(Box::new(move || {
+        log("Websocket error");
+    }) as Box<dyn FnMut()>);
+    ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+
+    let weak_session = Rc::downgrade(&session);
+    let onclose_callback = Closure::wrap(Box::new(move || {
+        if let Some(close_session) = current(&weak_session, generation) {
+            log(&format!("Websocket for {} closed", close_session.symbol));
+            schedule_reconnect(close_session, generation);
+        }
+    }) as Box<dyn FnMut()>);
+    ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+
+    *session.handlers.borrow_mut() = Some(SocketHandlers {
+        _onopen: onopen_callback,
+        _onmessage: onmessage_callback,
+        _onerror: onerror_callback,
+        _onclose: onclose_callback,
+    });
+    *session.websocket.borrow_mut() = Some(ws);
+}
+
+/// The session behind a handler, unless it was dropped or replaced by a newer stream.
+fn current(session: &Weak<StreamSession>, generation: u32) -> Option<Rc<StreamSession>> {
+    session.upgrade().filter(|session| session.generation.get() == generation)
+}
+
+fn schedule_reconnect(session: Rc<StreamSession>, generation: u32) {
+    session.state.set(ConnectionState::Reconnecting);
+    let delay = session.backoff.borrow_mut().next_delay();
+    log(&format!("Reconnecting in {} ms", delay));
+
+    // freed once it has run
+    let weak_session = Rc::downgrade(&session);
+    let reconnect = Closure::once_into_js(move || {
+        if let Some(timer_session) = current(&weak_session, generation) {
+            connect(timer_session, generation, true);
+        }
+    });
+    let scheduled = window().map(|w| {
+        w.set_timeout_with_callback_and_timeout_and_arguments_0(reconnect.unchecked_ref(), delay as i32)
+    });
+    match scheduled {
+        Some(Ok(_)) => {},
+        _ => {
+            log("Failed to schedule reconnect");
+            session.state.set(ConnectionState::Disconnected);
+        }
+    }
+}
+
+/// Replaces the websocket's handlers so closing it doesn't trigger a reconnect.
+pub(crate) fn close(ws: &WebSocket) {
+    ws.set_onopen(None);
+    ws.set_onmessage(None);
+    ws.set_onerror(None);
+    ws.set_onclose(None);
+    if let Err(e) = ws.close() {
+        log(&format!("Failed to close websocket: {:?}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::new(1000, 30_000);
+        let delays: Vec<u32> = (0..7).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 16_000, 30_000, 30_000]);
+        // stays at the cap however long it keeps failing
+        for _ in 0..100 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), 30_000);
+    }
+
+    #[test]
+    fn backoff_reset_starts_over() {
+        let mut backoff = Backoff::default();
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), 1000);
+    }
+
+    #[test]
+    fn backoff_saturates_large_delays() {
+        let mut backoff = Backoff::new(u32::MAX / 2, u32::MAX);
+        assert_eq!(backoff.next_delay(), u32::MAX / 2);
+        assert_eq!(backoff.next_delay(), u32::MAX - 1);
+        assert_eq!(backoff.next_delay(), u32::MAX);
+    }
+}
@@ -0,0 +1,347 @@
+use serde_json::Value;
+
+use super::{DepthSnapshot, DepthUpdate, ExchangeConnector, MarketEvent};
+use crate::funding::MarkPriceUpdate;
+use crate::liquidation::{Liquidation, LiquidationSide};
+use crate::{log, Kline, Order, Price, TickSize, Timeframe, Trade};
+
+/// Binance USD-M futures, combined streams over `fstream.binance.com`.
+pub struct BinanceFutures;
+
+impl ExchangeConnector for BinanceFutures {
+    fn stream_url(&self, symbol: &str, timeframe: Timeframe) -> String {
+        format!("wss://fstream.binance.com/stream?streams={}@aggTrade/{}@depth@100ms/{}@kline_{}/{}@markPrice@1s/{}@forceOrder", symbol, symbol, symbol, timeframe.as_str(), symbol, symbol)
+    }
+
+    fn parse_message(&self, data: &str, tick_size: TickSize) -> Vec<MarketEvent> {
+        let v: Value = match serde_json::from_str(data) {
+            Ok(v) => v,
+            Err(e) => {
+                log(&format!("Failed to parse stream message: {}", e));
+                return Vec::new();
+            }
+        };
+        let event = match v["stream"].as_str() {
+            Some(stream) if stream.contains("aggTrade") => parse_trade(&v["data"], tick_size).map(MarketEvent::Trade),
+            Some(stream) if stream.contains("depth") => parse_depth(&v["data"], tick_size).map(MarketEvent::Depth),
+            Some(stream) if stream.contains("kline") => parse_kline(&v["data"]["k"], tick_size).map(MarketEvent::Kline),
+            Some(stream) if stream.contains("markPrice") => parse_mark_price_update(&v["data"]).map(MarketEvent::MarkPrice),
+            Some(stream) if stream.contains("forceOrder") => parse_liquidation(&v["data"]["o"], tick_size).map(MarketEvent::Liquidation),
+            _ => {
+                log(&format!("Unknown stream: {:?}", v));
+                None
+            }
+        };
+        event.into_iter().collect()
+    }
+
+    fn parse_depth_snapshot(&self, data: &str, tick_size: TickSize) -> Option<DepthSnapshot> {
+        match serde_json::from_str::<Value>(data) {
+            Ok(depth) => Some(DepthSnapshot {
+                last_update_id: depth["lastUpdateId"].as_u64().unwrap_or(0),
+                bids: parse_levels(&depth["bids"], tick_size),
+                asks: parse_levels(&depth["asks"], tick_size),
+            }),
+            Err(e) => {
+                log(&format!("Failed to parse depth: {}", e));
+                None
+            }
+        }
+    }
+
+    fn parse_klines(&self, data: &str, tick_size: TickSize) -> Vec<Kline> {
+        match serde_json::from_str::<Vec<Vec<Value>>>(data) {
+            Ok(klines) => klines.iter().filter_map(|kline| {
+                let open_time = kline.first()?.as_u64()?;
+                let open = parse_price(kline.get(1)?, tick_size)?;
+                let high = parse_price(kline.get(2)?, tick_size)?;
+                let low = parse_price(kline.get(3)?, tick_size)?;
+                let close = parse_price(kline.get(4)?, tick_size)?;
+                let volume = parse_str_f64(kline.get(5)?)?;
+                let close_time = kline.get(6)?.as_u64()?;
+                let buy_volume = parse_str_f64(kline.get(9)?)?;
+                Some(Kline {
+                    open_time,
+                    open, high, low, close,
+                    buy_volume,
+                    sell_volume: volume - buy_volume,
+                    cum_volume_delta: 0.0,
+                    close_time,
+                })
+            }).collect(),
+            Err(e) => {
+                log(&format!("Failed to parse klines: {}", e));
+                Vec::new()
+            }
+        }
+    }
+
+    fn parse_funding_history(&self, data: &str) -> Vec<(u64, f64)> {
+        match serde_json::from_str::<Vec<Value>>(data) {
+            Ok(rates) => rates.iter().filter_map(|rate| {
+                Some((rate["fundingTime"].as_u64()?, parse_str_f64(&rate["fundingRate"])?))
+            }).collect(),
+            Err(e) => {
+                log(&format!("Failed to parse funding rates: {}", e));
+                Vec::new()
+            }
+        }
+    }
+
+    fn parse_mark_price(&self, data: &str) -> Option<MarkPriceUpdate> {
+        match serde_json::from_str::<Value>(data) {
+            Ok(premium_index) => Some(MarkPriceUpdate {
+                time: premium_index["time"].as_u64()?,
+                mark_price: parse_str_f64(&premium_index["markPrice"])?,
+                index_price: parse_str_f64(&premium_index["indexPrice"])?,
+                funding_rate: parse_str_f64(&premium_index["lastFundingRate"])?,
+                next_funding_time: premium_index["nextFundingTime"].as_u64()?,
+            }),
+            Err(e) => {
+                log(&format!("Failed to parse premium index: {}", e));
+                None
+            }
+        }
+    }
+}
+
+fn parse_str_f64(v: &Value) -> Option<f64> {
+    v.as_str().and_then(|s| s.parse::<f64>().ok())
+}
+
+fn parse_price(v: &Value, tick_size: TickSize) -> Option<Price> {
+    v.as_str().and_then(|s| Price::parse(s, tick_size))
+}
+
+fn parse_levels(levels: &Value, tick_size: TickSize) -> Vec<Order> {
+    match levels.as_array() {
+        Some(levels) => levels.iter().filter_map(|x| {
+            let price = parse_price(&x[0], tick_size)?;
+            let quantity = parse_str_f64(&x[1])?;
+            Some(Order { price, quantity })
+        }).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn parse_trade(data: &Value, tick_size: TickSize) -> Option<Trade> {
+    Some(Trade {
+        price: parse_price(&data["p"], tick_size)?,
+        quantity: parse_str_f64(&data["q"])?,
+        time: data["T"].as_u64()?,
+        is_buyer_maker: data["m"].as_bool()?,
+    })
+}
+
+fn parse_depth(data: &Value, tick_size: TickSize) -> Option<DepthUpdate> {
+    Some(DepthUpdate {
+        time: data["T"].as_u64()?,
+        first_update_id: data["U"].as_u64()?,
+        final_update_id: data["u"].as_u64()?,
+        prev_final_update_id: data["pu"].as_u64(),
+        bids: parse_levels(&data["b"], tick_size),
+        asks: parse_levels(&data["a"], tick_size),
+    })
+}
+
+fn parse_kline(k: &Value, tick_size: TickSize) -> Option<Kline> {
+    let volume = parse_str_f64(&k["v"])?;
+    let buy_volume = parse_str_f64(&k["V"])?;
+    Some(Kline {
+        open_time: k["t"].as_u64()?,
+        open: parse_price(&k["o"], tick_size)?,
+        high: parse_price(&k["h"], tick_size)?,
+        low: parse_price(&k["l"], tick_size)?,
+        close: parse_price(&k["c"], tick_size)?,
+        buy_volume,
+        sell_volume: volume - buy_volume,
+        cum_volume_delta: 0.0,
+        close_time: k["T"].as_u64()?,
+    })
+}
+
+fn parse_mark_price_update(data: &Value) -> Option<MarkPriceUpdate> {
+    Some(MarkPriceUpdate {
+        time: data["E"].as_u64()?,
+        mark_price: parse_str_f64(&data["p"])?,
+        index_price: parse_str_f64(&data["i"])?,
+        funding_rate: parse_str_f64(&data["r"])?,
+        next_funding_time: data["T"].as_u64()?,
+    })
+}
+
+/// A liquidation is a forced sell for longs and a forced buy for shorts.
+fn parse_liquidation(order: &Value, tick_size: TickSize) -> Option<Liquidation> {
+    let side = match order["S"].as_str()? {
+        "SELL" => LiquidationSide::Long,
+        "BUY" => LiquidationSide::Short,
+        _ => return None,
+    };
+    let price = parse_price(&order["ap"], tick_size)
+        .filter(|price| price.ticks() > 0)
+        .or_else(|| parse_price(&order["p"], tick_size))?;
+    Some(Liquidation {
+        price,
+        quantity: parse_str_f64(&order["z"]).filter(|filled| *filled > 0.0).or_else(|| parse_str_f64(&order["q"]))?,
+        time: order["T"].as_u64()?,
+        side,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_size() -> TickSize {
+        TickSize::parse("0.1").unwrap()
+    }
+    fn price(s: &str) -> Price {
+        Price::parse(s, tick_size()).unwrap()
+    }
+    fn parse_one(data: &str) -> MarketEvent {
+        let mut events = BinanceFutures.parse_message(data, tick_size());
+        assert_eq!(events.len(), 1);
+        events.remove(0)
+    }
+
+    #[test]
+    fn agg_trade() {
+        match parse_one(include_str!("../../tests/fixtures/binance/agg_trade.json")) {
+            MarketEvent::Trade(trade) => {
+                assert_eq!(trade.price, price("67012.5"));
+                assert_eq!(trade.quantity, 0.125);
+                assert_eq!(trade.time, 1718000000120);
+                assert!(trade.is_buyer_maker);
+            },
+            event => panic!("expected a trade, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn depth_update() {
+        match parse_one(include_str!("../../tests/fixtures/binance/depth_update.json")) {
+            MarketEvent::Depth(update) => {
+                assert_eq!(update.time, 1718000000248);
+                assert_eq!(update.first_update_id, 4800000100);
+                assert_eq!(update.final_update_id, 4800000120);
+                assert_eq!(update.prev_final_update_id, Some(4800000099));
+                let bids: Vec<_> = update.bids.iter().map(|order| (order.price, order.quantity)).collect();
+                assert_eq!(bids, vec![(price("67012.4"), 1.502), (price("67012.3"), 0.0)]);
+                assert_eq!(update.asks.len(), 3);
+                assert_eq!(update.asks[2].quantity, 0.0);
+                assert!(update.follows(4800000099));
+                assert!(!update.follows(4800000100));
+            },
+            event => panic!("expected a depth update, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn kline() {
+        match parse_one(include_str!("../../tests/fixtures/binance/kline.json")) {
+            MarketEvent::Kline(kline) => {
+                assert_eq!(kline.open_time, 1717999980000);
+                assert_eq!(kline.close_time, 1718000039999);
+                assert_eq!((kline.open, kline.high, kline.low, kline.close), (price("67000.1"), price("67020"), price("66995.3"), price("67012.5")));
+                assert_eq!(kline.buy_volume, 90.1);
+                assert!((kline.sell_volume - 62.3).abs() < 1e-9);
+                assert_eq!(kline.cum_volume_delta, 0.0);
+            },
+            event => panic!("expected a kline, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn mark_price() {
+        match parse_one(include_str!("../../tests/fixtures/binance/mark_price.json")) {
+            MarketEvent::MarkPrice(update) => assert_eq!(update, MarkPriceUpdate {
+                time: 1718000001000,
+                mark_price: 67010.12345678,
+                index_price: 67001.98765432,
+                funding_rate: 0.0001,
+                next_funding_time: 1718006400000,
+            }),
+            event => panic!("expected a mark price, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn force_order() {
+        match parse_one(include_str!("../../tests/fixtures/binance/force_order.json")) {
+            MarketEvent::Liquidation(liquidation) => assert_eq!(liquidation, Liquidation {
+                price: price("66961.7"),
+                quantity: 0.35,
+                time: 1718000001998,
+                side: LiquidationSide::Long,
+            }),
+            event => panic!("expected a liquidation, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn force_order_falls_back_to_order_price_and_quantity() {
+        let data = r#"{"stream":"btcusdt@forceOrder","data":{"o":{"S":"BUY","q":"1.000","p":"67100.00","ap":"0","z":"0","T":1}}}"#;
+        match parse_one(data) {
+            MarketEvent::Liquidation(liquidation) => {
+                assert_eq!(liquidation.side, LiquidationSide::Short);
+                assert_eq!(liquidation.price, price("67100"));
+                assert_eq!(liquidation.quantity, 1.0);
+            },
+            event => panic!("expected a liquidation, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn malformed_messages_yield_no_events() {
+        let connector = BinanceFutures;
+        assert!(connector.parse_message("not json", tick_size()).is_empty());
+        assert!(connector.parse_message(r#"{"result":null,"id":1}"#, tick_size()).is_empty());
+        assert!(connector.parse_message(r#"{"stream":"btcusdt@bookTicker","data":{}}"#, tick_size()).is_empty());
+        // missing and mistyped fields
+        assert!(connector.parse_message(r#"{"stream":"btcusdt@aggTrade","data":{"p":"1.0","q":"1","m":true}}"#, tick_size()).is_empty());
+        assert!(connector.parse_message(r#"{"stream":"btcusdt@aggTrade","data":{"p":1.0,"q":"1","T":1,"m":true}}"#, tick_size()).is_empty());
+        assert!(connector.parse_message(r#"{"stream":"btcusdt@depth@100ms","data":{"T":1,"U":1,"b":[],"a":[]}}"#, tick_size()).is_empty());
+        assert!(connector.parse_message(r#"{"stream":"btcusdt@forceOrder","data":{"o":{"S":"HOLD","q":"1","p":"1","T":1}}}"#, tick_size()).is_empty());
+    }
+
+    #[test]
+    fn depth_snapshot() {
+        let snapshot = BinanceFutures.parse_depth_snapshot(include_str!("../../tests/fixtures/binance/depth_snapshot.json"), tick_size()).unwrap();
+        assert_eq!(snapshot.last_update_id, 4800000110);
+        let bids: Vec<_> = snapshot.bids.iter().map(|order| (order.price, order.quantity)).collect();
+        assert_eq!(bids, vec![(price("67012.4"), 1.48), (price("67012.3"), 0.25), (price("67011.9"), 3.0)]);
+        assert_eq!(snapshot.asks.len(), 2);
+        assert_eq!(snapshot.asks[1].price, price("67012.8"));
+    }
+
+    #[test]
+    fn depth_snapshot_errors() {
+        assert!(BinanceFutures.parse_depth_snapshot("{", tick_size()).is_none());
+        // unparseable levels are dropped rather than failing the whole snapshot
+        let snapshot = BinanceFutures.parse_depth_snapshot(r#"{"lastUpdateId":5,"bids":[["x","1"],["1.0","2"]],"asks":null}"#, tick_size()).unwrap();
+        assert_eq!(snapshot.bids.len(), 1);
+        assert!(snapshot.asks.is_empty());
+    }
+
+    #[test]
+    fn klines() {
+        let klines = BinanceFutures.parse_klines(include_str!("../../tests/fixtures/binance/klines.json"), tick_size());
+        assert_eq!(klines.len(), 2);
+        assert_eq!(klines[0].open_time, 1717999860000);
+        assert_eq!(klines[0].close_time, 1717999919999);
+        assert_eq!((klines[0].open, klines[0].high, klines[0].low, klines[0].close), (price("66980"), price("67005"), price("66970.2"), price("67000.1")));
+        assert_eq!(klines[0].buy_volume, 120.3);
+        assert!((klines[0].sell_volume - 90.212).abs() < 1e-9);
+        assert_eq!(klines[1].open_time, 1717999920000);
+    }
+
+    #[test]
+    fn klines_errors() {
+        assert!(BinanceFutures.parse_klines("{}", tick_size()).is_empty());
+        // a short row is skipped, the rest still parse
+        let data = r#"[[1,"1.0","1.0"],[60000,"1.0","2.0","0.5","1.5","10",119999,"0",1,"4","0","0"]]"#;
+        let klines = BinanceFutures.parse_klines(data, tick_size());
+        assert_eq!(klines.len(), 1);
+        assert_eq!(klines[0].open_time, 60000);
+    }
+}
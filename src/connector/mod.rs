@@ -0,0 +1,80 @@
+mod binance;
+
+pub use binance::BinanceFutures;
+
+use crate::funding::MarkPriceUpdate;
+use crate::liquidation::Liquidation;
+use crate::{Kline, Order, TickSize, Timeframe, Trade};
+
+/// Incremental depth changes as received from a stream, normalized to `Order` levels.
+/// A level with zero quantity means the level was removed.
+#[derive(Clone, Debug)]
+pub struct DepthUpdate {
+    pub time: u64,
+    pub first_update_id: u64,
+    pub final_update_id: u64,
+    /// Final update id of the previous diff, for exchanges that send it (Binance futures `pu`).
+    pub prev_final_update_id: Option<u64>,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+impl DepthUpdate {
+    /// Whether this diff directly continues a book whose last applied update id is `last_update_id`.
+    pub fn follows(&self, last_update_id: u64) -> bool {
+        match self.prev_final_update_id {
+            Some(prev_final_update_id) => prev_final_update_id == last_update_id,
+            None => self.first_update_id == last_update_id + 1,
+        }
+    }
+}
+
+/// Full order book as returned by an exchange's REST depth endpoint.
+#[derive(Clone, Debug)]
+pub struct DepthSnapshot {
+    pub last_update_id: u64,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+#[derive(Debug)]
+pub enum MarketEvent {
+    Trade(Trade),
+    Depth(DepthUpdate),
+    Kline(Kline),
+    MarkPrice(MarkPriceUpdate),
+    Liquidation(Liquidation),
+}
+
+/// Owns everything exchange specific: where to connect, what to subscribe to,
+/// and how raw payloads map onto the crate's `Trade`/`Order`/`Kline` types.
+///
+/// Parsing works on plain strings so implementations can be exercised against
+/// recorded JSON without a network connection. Prices are converted to ticks of `tick_size`.
+pub trait ExchangeConnector: Send + Sync {
+    fn stream_url(&self, symbol: &str, timeframe: Timeframe) -> String;
+
+    /// Messages sent right after the socket opens, for exchanges that subscribe
+    /// over the connection instead of through the URL.
+    fn subscribe_messages(&self, _symbol: &str, _timeframe: Timeframe) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Normalizes one stream message. Acks, heartbeats and anything unrecognized yield no events.
+    fn parse_message(&self, data: &str, tick_size: TickSize) -> Vec<MarketEvent>;
+
+    fn parse_depth_snapshot(&self, data: &str, tick_size: TickSize) -> Option<DepthSnapshot>;
+
+    /// Parses historical klines. `cum_volume_delta` is left at zero for the caller to accumulate.
+    fn parse_klines(&self, data: &str, tick_size: TickSize) -> Vec<Kline>;
+
+    /// Parses settled funding rates as `(funding_time, rate)`. Exchanges without perpetual
+    /// funding return nothing.
+    fn parse_funding_history(&self, _data: &str) -> Vec<(u64, f64)> {
+        Vec::new()
+    }
+
+    /// Parses the REST counterpart of the mark price stream.
+    fn parse_mark_price(&self, _data: &str) -> Option<MarkPriceUpdate> {
+        None
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::{Kline, TradeGroups, DAY_IN_MS};
+
+const WEEK_IN_MS: u64 = 7 * DAY_IN_MS;
+/// The epoch fell on a Thursday, weeks are aligned to Monday 00:00 UTC.
+const WEEK_OFFSET_MS: u64 = 3 * DAY_IN_MS;
+
+/// Where cumulative delta starts counting from zero.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CvdAnchor {
+    /// Resets at 00:00 UTC.
+    Daily,
+    /// Resets on Monday 00:00 UTC.
+    Weekly,
+    FirstVisible,
+}
+impl CvdAnchor {
+    fn period_start(&self, time: u64, first_visible: u64) -> u64 {
+        match self {
+            CvdAnchor::Daily => time - time % DAY_IN_MS,
+            CvdAnchor::Weekly => time.saturating_sub((time + WEEK_OFFSET_MS) % WEEK_IN_MS),
+            CvdAnchor::FirstVisible => first_visible,
+        }
+    }
+}
+impl std::str::FromStr for CvdAnchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(CvdAnchor::Daily),
+            "weekly" => Ok(CvdAnchor::Weekly),
+            "visible" => Ok(CvdAnchor::FirstVisible),
+            _ => Err(format!("Unsupported CVD anchor: {}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CvdCandle {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// The anchor's period started before the first loaded kline, so this only counts the
+    /// delta since then rather than the anchored value.
+    pub partial: bool,
+}
+
+/// CVD candles for klines opening within `open_times`. Each kline's delta comes from its buy and
+/// sell volume; the wicks come from the intra-bar running delta where trades are loaded.
+pub fn cvd_candles(klines: &BTreeMap<u64, Kline>, trades: &BTreeMap<u64, TradeGroups>, open_times: RangeInclusive<u64>, anchor: CvdAnchor) -> Vec<(u64, CvdCandle)> {
+    let (first, last) = (*open_times.start(), *open_times.end());
+    if first > last {
+        return Vec::new();
+    }
+    let first_loaded = match klines.keys().next() {
+        Some(&open_time) => open_time,
+        None => return Vec::new(),
+    };
+    let mut candles = Vec::new();
+    let mut cvd = 0.0;
+    let mut period: Option<u64> = None;
+
+    for (&open_time, kline) in klines.range(anchor.period_start(first, first)..=last) {
+        let period_start = anchor.period_start(open_time, first);
+        if period != Some(period_start) {
+            period = Some(period_start);
+            cvd = 0.0;
+        }
+        let open = cvd;
+        let close = cvd + kline.buy_volume - kline.sell_volume;
+        let (high, low) = match trades.get(&open_time) {
+            Some(trade_groups) if trade_groups.trade_count() > 0 => (open + trade_groups.delta.max, open + trade_groups.delta.min),
+            _ => (open, open),
+        };
+        cvd = close;
+
+        if open_time >= first {
+            candles.push((open_time, CvdCandle {
+                open,
+                high: high.max(open).max(close),
+                low: low.min(open).min(close),
+                close,
+                partial: anchor != CvdAnchor::FirstVisible && period_start < first_loaded,
+            }));
+        }
+    }
+    candles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Price;
+
+    const MINUTE: u64 = 60_000;
+
+    fn klines(open_times: &[u64]) -> BTreeMap<u64, Kline> {
+        open_times.iter().map(|&open_time| (open_time, Kline {
+            open_time,
+            open: Price::default(),
+            high: Price::default(),
+            low: Price::default(),
+            close: Price::default(),
+            buy_volume: 3.0,
+            sell_volume: 1.0,
+            cum_volume_delta: 0.0,
+            close_time: open_time + MINUTE - 1,
+        })).collect()
+    }
+    fn closes(candles: &[(u64, CvdCandle)]) -> Vec<(u64, f64, bool)> {
+        candles.iter().map(|(open_time, candle)| (*open_time, candle.close, candle.partial)).collect()
+    }
+
+    #[test]
+    fn daily_resets_at_midnight() {
+        let (day_end, next_day) = (DAY_IN_MS - MINUTE, DAY_IN_MS);
+        let klines = klines(&[0, MINUTE, day_end, next_day, next_day + MINUTE]);
+        let candles = cvd_candles(&klines, &BTreeMap::new(), next_day..=next_day + MINUTE, CvdAnchor::Daily);
+        assert_eq!(closes(&candles), vec![(next_day, 2.0, false), (next_day + MINUTE, 4.0, false)]);
+
+        // counts from the start of the day even if it isn't visible
+        let candles = cvd_candles(&klines, &BTreeMap::new(), day_end..=next_day, CvdAnchor::Daily);
+        assert_eq!(closes(&candles), vec![(day_end, 6.0, false), (next_day, 2.0, false)]);
+    }
+
+    #[test]
+    fn period_before_first_loaded_kline_is_partial() {
+        let first = DAY_IN_MS + 10 * MINUTE;
+        let klines = klines(&[first, first + MINUTE, 2 * DAY_IN_MS]);
+        let candles = cvd_candles(&klines, &BTreeMap::new(), first..=2 * DAY_IN_MS, CvdAnchor::Daily);
+        assert_eq!(closes(&candles), vec![(first, 2.0, true), (first + MINUTE, 4.0, true), (2 * DAY_IN_MS, 2.0, false)]);
+
+        let candles = cvd_candles(&klines, &BTreeMap::new(), first + MINUTE..=first + MINUTE, CvdAnchor::Weekly);
+        assert_eq!(closes(&candles), vec![(first + MINUTE, 4.0, true)]);
+        let candles = cvd_candles(&klines, &BTreeMap::new(), first + MINUTE..=first + MINUTE, CvdAnchor::FirstVisible);
+        assert_eq!(closes(&candles), vec![(first + MINUTE, 2.0, false)]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use serde_json::{json, Value};
+
+use crate::market::MarketState;
+use crate::Price;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Header row, then one line per row. Missing values are left empty.
+    Csv,
+    /// Array of objects keyed by column.
+    Json,
+}
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unsupported export format: {}", s)),
+        }
+    }
+}
+
+/// Klines opening within `open_times`.
+pub fn klines(market: &MarketState, open_times: RangeInclusive<u64>, format: ExportFormat) -> String {
+    let columns = ["open_time", "close_time", "open", "high", "low", "close", "buy_volume", "sell_volume", "cum_volume_delta"];
+    if open_times.start() > open_times.end() {
+        return table(&columns, Vec::new(), format);
+    }
+    let tick_size = market.tick_size();
+    let rows = market.klines().range(open_times).map(|(&open_time, kline)| vec![
+        json!(open_time),
+        json!(kline.close_time),
+        json!(kline.open.to_f64(tick_size)),
+        json!(kline.high.to_f64(tick_size)),
+        json!(kline.low.to_f64(tick_size)),
+        json!(kline.close.to_f64(tick_size)),
+        json!(kline.buy_volume),
+        json!(kline.sell_volume),
+        json!(kline.cum_volume_delta),
+    ]).collect();
+    table(&columns, rows, format)
+}
+
+/// Traded volume per price bucket of klines opening within `open_times`, grouped at the
+/// current bucket size as on the chart. Each trade counts towards the multiple of the bucket
+/// size nearest its price, which is the price of the row; rows ascend within a kline.
+pub fn footprint_cells(market: &MarketState, open_times: RangeInclusive<u64>, format: ExportFormat) -> String {
+    let columns = ["open_time", "price", "buy_volume", "sell_volume", "delta"];
+    if open_times.start() > open_times.end() {
+        return table(&columns, Vec::new(), format);
+    }
+    let tick_size = market.tick_size();
+    let mut rows = Vec::new();
+    for (&open_time, trade_groups) in market.footprint().range(open_times) {
+        let grouped = trade_groups.grouped(market.bucket_size());
+        let prices: BTreeSet<Price> = grouped.buys.keys().chain(grouped.sells.keys()).copied().collect();
+        for price in prices {
+            let buy_volume = grouped.buys.get(&price).copied().unwrap_or(0.0);
+            let sell_volume = grouped.sells.get(&price).copied().unwrap_or(0.0);
+            rows.push(vec![
+                json!(open_time),
+                json!(price.to_f64(tick_size)),
+                json!(buy_volume),
+                json!(sell_volume),
+                json!(buy_volume - sell_volume),
+            ]);
+        }
+    }
+    table(&columns, rows, format)
+}
+
+/// Open interest of klines opening within `open_times`, as drawn on the CVD indicator.
+pub fn open_interest(market: &MarketState, open_times: RangeInclusive<u64>, format: ExportFormat) -> String {
+    let columns = ["open_time", "open_interest", "change", "weighted_price"];
+    let rows = market.open_interest_points(open_times).iter().map(|(open_time, point)| vec![
+        json!(open_time),
+        json!(point.open_interest),
+        json!(point.change),
+        json!(point.weighted_price),
+    ]).collect();
+    table(&columns, rows, format)
+}
+
+fn table(columns: &[&str], rows: Vec<Vec<Value>>, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => {
+            let mut out = columns.join(",");
+            out.push('\n');
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|value| match value {
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                }).collect();
+                out.push_str(&cells.join(","));
+                out.push('\n');
+            }
+            out
+        },
+        ExportFormat::Json => {
+            let objects: Vec<Value> = rows.into_iter().map(|row| {
+                Value::Object(columns.iter().map(|column| column.to_string()).zip(row).collect())
+            }).collect();
+            Value::Array(objects).to_string()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TickSize;
+
+    #[test]
+    fn footprint_cells_round_to_the_nearest_bucket() {
+        let mut market = MarketState::default();
+        market.set_tick_size(TickSize::parse("0.1").unwrap());
+        market.set_bucket_size(5);
+        market.apply_message(r#"{"stream":"btcusdt@kline_1m","data":{"k":{"t":60000,"T":119999,"o":"100.0","h":"101.0","l":"99.0","c":"100.0","v":"0","V":"0"}}}"#);
+        for (price, is_buyer_maker) in [("100.2", false), ("100.3", true), ("99.7", false)] {
+            market.apply_message(&format!(r#"{{"stream":"btcusdt@aggTrade","data":{{"p":"{}","q":"1","T":60001,"m":{}}}}}"#, price, is_buyer_maker));
+        }
+        market.apply_message(r#"{"stream":"btcusdt@depth@100ms","data":{"T":60002,"U":1,"u":2,"pu":0,"b":[],"a":[]}}"#);
+
+        let csv = footprint_cells(&market, 0..=60_000, ExportFormat::Csv);
+        assert_eq!(csv, "open_time,price,buy_volume,sell_volume,delta\n60000,99.5,1.0,0.0,1.0\n60000,100.0,1.0,0.0,1.0\n60000,100.5,0.0,1.0,-1.0\n");
+    }
+}
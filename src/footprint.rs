@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+
+use crate::{GroupedTrades, Price, TradeGroups};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImbalanceSide {
+    Buy,
+    Sell,
+}
+impl ImbalanceSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImbalanceSide::Buy => "buy",
+            ImbalanceSide::Sell => "sell",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImbalanceConfig {
+    /// Minimum ratio of one side over the diagonal level of the other side, e.g. 3.0 for 300%.
+    pub ratio: f64,
+    /// Consecutive imbalanced levels on one side needed to form a stacked imbalance.
+    pub stacked_levels: usize,
+}
+impl Default for ImbalanceConfig {
+    fn default() -> Self {
+        Self { ratio: 3.0, stacked_levels: 3 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FootprintConfig {
+    pub imbalances: ImbalanceConfig,
+    /// Share of volume the value area has to contain, 0.7 for 70%.
+    pub value_area: f64,
+}
+impl Default for FootprintConfig {
+    fn default() -> Self {
+        Self { imbalances: ImbalanceConfig::default(), value_area: 0.7 }
+    }
+}
+
+/// A level where aggressive volume on one side outweighs the diagonal level on the other:
+/// buys at `price` against sells one bucket below, sells at `price` against buys one bucket above.
+#[derive(Copy, Clone, Debug)]
+pub struct Imbalance {
+    pub price: Price,
+    pub side: ImbalanceSide,
+    pub quantity: f64,
+    pub opposite_quantity: f64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StackedImbalance {
+    pub side: ImbalanceSide,
+    pub low: Price,
+    pub high: Price,
+    pub levels: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Imbalances {
+    /// Sorted by side, then price ascending.
+    pub levels: Vec<Imbalance>,
+    pub stacked: Vec<StackedImbalance>,
+}
+impl Imbalances {
+    pub fn side_at(&self, price: Price, side: ImbalanceSide) -> bool {
+        self.levels.iter().any(|imbalance| imbalance.price == price && imbalance.side == side)
+    }
+}
+
+/// Footprint of one candle at the current bucket size, with what was detected on it.
+#[derive(Debug)]
+pub struct Footprint {
+    pub trades: GroupedTrades,
+    pub imbalances: Imbalances,
+    pub profile: Option<ProfileStats>,
+    pub stats: CandleStats,
+}
+impl Footprint {
+    pub fn new(trade_groups: &TradeGroups, bucket_ticks: i64, config: &FootprintConfig) -> Self {
+        let trades = trade_groups.grouped(bucket_ticks);
+        let imbalances = detect_imbalances(&trades, bucket_ticks, &config.imbalances);
+        let mut profile = VolumeProfile::default();
+        profile.add(&trades);
+        let profile = profile.stats(config.value_area);
+        Self { trades, imbalances, profile, stats: CandleStats::new(trade_groups) }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CandleStats {
+    pub delta: f64,
+    /// Delta as a percentage of volume.
+    pub delta_percent: f64,
+    pub volume: f64,
+    pub trade_count: usize,
+    pub max_delta: f64,
+    pub min_delta: f64,
+}
+impl CandleStats {
+    pub fn new(trade_groups: &TradeGroups) -> Self {
+        let volume = trade_groups.volume();
+        let delta = trade_groups.delta.close;
+        Self {
+            delta,
+            delta_percent: if volume > 0.0 { delta / volume * 100.0 } else { 0.0 },
+            volume,
+            trade_count: trade_groups.trade_count(),
+            max_delta: trade_groups.delta.max,
+            min_delta: trade_groups.delta.min,
+        }
+    }
+}
+
+/// Rows of the statistics table drawn under the footprint candles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StatsRow {
+    Delta,
+    DeltaPercent,
+    Volume,
+    Trades,
+    MaxDelta,
+    MinDelta,
+}
+impl StatsRow {
+    pub const ALL: [StatsRow; 6] = [StatsRow::Delta, StatsRow::DeltaPercent, StatsRow::Volume, StatsRow::Trades, StatsRow::MaxDelta, StatsRow::MinDelta];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatsRow::Delta => "delta",
+            StatsRow::DeltaPercent => "delta %",
+            StatsRow::Volume => "volume",
+            StatsRow::Trades => "trades",
+            StatsRow::MaxDelta => "max delta",
+            StatsRow::MinDelta => "min delta",
+        }
+    }
+    pub fn value(&self, stats: &CandleStats) -> f64 {
+        match self {
+            StatsRow::Delta => stats.delta,
+            StatsRow::DeltaPercent => stats.delta_percent,
+            StatsRow::Volume => stats.volume,
+            StatsRow::Trades => stats.trade_count as f64,
+            StatsRow::MaxDelta => stats.max_delta,
+            StatsRow::MinDelta => stats.min_delta,
+        }
+    }
+    /// Whether the value is signed and colored by its sign.
+    pub fn is_signed(&self) -> bool {
+        !matches!(self, StatsRow::Volume | StatsRow::Trades)
+    }
+}
+impl std::str::FromStr for StatsRow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delta" => Ok(StatsRow::Delta),
+            "delta_percent" => Ok(StatsRow::DeltaPercent),
+            "volume" => Ok(StatsRow::Volume),
+            "trades" => Ok(StatsRow::Trades),
+            "max_delta" => Ok(StatsRow::MaxDelta),
+            "min_delta" => Ok(StatsRow::MinDelta),
+            _ => Err(format!("Unsupported stats row: {}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ProfileStats {
+    /// Point of control, the level with the most volume.
+    pub poc: Price,
+    pub value_area_high: Price,
+    pub value_area_low: Price,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+}
+
+/// Klines the volume profile on the main chart aggregates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProfileRange {
+    Off,
+    Visible,
+    /// The UTC day of the latest kline.
+    Session,
+}
+impl std::str::FromStr for ProfileRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ProfileRange::Off),
+            "visible" => Ok(ProfileRange::Visible),
+            "session" => Ok(ProfileRange::Session),
+            _ => Err(format!("Unsupported volume profile range: {}", s)),
+        }
+    }
+}
+
+/// Buy and sell volume per price level, accumulated from one or more footprints.
+#[derive(Clone, Debug, Default)]
+pub struct VolumeProfile {
+    pub levels: BTreeMap<Price, (f64, f64)>,
+}
+impl VolumeProfile {
+    pub fn add(&mut self, trades: &GroupedTrades) {
+        for (&price, &quantity) in &trades.buys {
+            self.levels.entry(price).or_insert((0.0, 0.0)).0 += quantity;
+        }
+        for (&price, &quantity) in &trades.sells {
+            self.levels.entry(price).or_insert((0.0, 0.0)).1 += quantity;
+        }
+    }
+
+    pub fn max_volume(&self) -> f64 {
+        self.levels.values().map(|(buy, sell)| buy + sell).fold(0.0, f64::max)
+    }
+
+    /// POC and the value area grown from it, one level at a time towards the side with more
+    /// volume, until it holds `value_area` of the total. Ties go to the higher price.
+    pub fn stats(&self, value_area: f64) -> Option<ProfileStats> {
+        let levels: Vec<(Price, f64)> = self.levels.iter().map(|(&price, (buy, sell))| (price, buy + sell)).collect();
+        let (poc_index, _) = levels.iter().enumerate()
+            .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))?;
+
+        let total: f64 = levels.iter().map(|(_, volume)| volume).sum();
+        let target = total * value_area.clamp(0.0, 1.0);
+        let (mut low, mut high) = (poc_index, poc_index);
+        let mut accumulated = levels[poc_index].1;
+
+        while accumulated < target && (low > 0 || high + 1 < levels.len()) {
+            let above = levels.get(high + 1).map(|(_, volume)| *volume);
+            let below = low.checked_sub(1).map(|i| levels[i].1);
+            match (above, below) {
+                (Some(above), Some(below)) if below > above => {
+                    low -= 1;
+                    accumulated += below;
+                },
+                (Some(above), _) => {
+                    high += 1;
+                    accumulated += above;
+                },
+                (None, Some(below)) => {
+                    low -= 1;
+                    accumulated += below;
+                },
+                (None, None) => break,
+            }
+        }
+        Some(ProfileStats {
+            poc: levels[poc_index].0,
+            value_area_high: levels[high].0,
+            value_area_low: levels[low].0,
+            buy_volume: self.levels.values().map(|(buy, _)| buy).sum(),
+            sell_volume: self.levels.values().map(|(_, sell)| sell).sum(),
+        })
+    }
+}
+
+/// `trades` has to be grouped at `bucket_ticks`. A level facing no volume on the diagonal,
+/// such as the lowest buy or highest sell level, has nothing to outweigh and isn't imbalanced.
+pub fn detect_imbalances(trades: &GroupedTrades, bucket_ticks: i64, config: &ImbalanceConfig) -> Imbalances {
+    let mut buys: Vec<Imbalance> = trades.buys.iter().filter_map(|(&price, &quantity)| {
+        let opposite_quantity = trades.sells.get(&price.offset(-bucket_ticks)).copied().unwrap_or(0.0);
+        is_imbalanced(quantity, opposite_quantity, config.ratio)
+            .then_some(Imbalance { price, side: ImbalanceSide::Buy, quantity, opposite_quantity })
+    }).collect();
+    let mut sells: Vec<Imbalance> = trades.sells.iter().filter_map(|(&price, &quantity)| {
+        let opposite_quantity = trades.buys.get(&price.offset(bucket_ticks)).copied().unwrap_or(0.0);
+        is_imbalanced(quantity, opposite_quantity, config.ratio)
+            .then_some(Imbalance { price, side: ImbalanceSide::Sell, quantity, opposite_quantity })
+    }).collect();
+    buys.sort_by_key(|imbalance| imbalance.price);
+    sells.sort_by_key(|imbalance| imbalance.price);
+
+    let mut stacked = find_stacked(&buys, bucket_ticks, config.stacked_levels);
+    stacked.extend(find_stacked(&sells, bucket_ticks, config.stacked_levels));
+
+    buys.extend(sells);
+    Imbalances { levels: buys, stacked }
+}
+
+fn is_imbalanced(quantity: f64, opposite_quantity: f64, ratio: f64) -> bool {
+    opposite_quantity > 0.0 && quantity >= opposite_quantity * ratio
+}
+
+/// `imbalances` must be of one side and sorted by price.
+fn find_stacked(imbalances: &[Imbalance], bucket_ticks: i64, min_levels: usize) -> Vec<StackedImbalance> {
+    let mut stacked = Vec::new();
+    let mut run: Option<StackedImbalance> = None;
+
+    for imbalance in imbalances {
+        run = match run {
+            Some(mut current) if imbalance.price == current.high.offset(bucket_ticks) => {
+                current.high = imbalance.price;
+                current.levels += 1;
+                Some(current)
+            },
+            previous => {
+                if let Some(previous) = previous.filter(|run| run.levels >= min_levels) {
+                    stacked.push(previous);
+                }
+                Some(StackedImbalance { side: imbalance.side, low: imbalance.price, high: imbalance.price, levels: 1 })
+            },
+        };
+    }
+    if let Some(last) = run.filter(|run| run.levels >= min_levels) {
+        stacked.push(last);
+    }
+    stacked
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn grouped(buys: &[(i64, f64)], sells: &[(i64, f64)]) -> GroupedTrades {
+        let levels = |levels: &[(i64, f64)]| levels.iter().map(|&(ticks, quantity)| (Price::from_ticks(ticks), quantity)).collect::<HashMap<_, _>>();
+        GroupedTrades { buys: levels(buys), sells: levels(sells) }
+    }
+    fn imbalanced(imbalances: &Imbalances) -> Vec<(i64, ImbalanceSide)> {
+        imbalances.levels.iter().map(|imbalance| (imbalance.price.ticks(), imbalance.side)).collect()
+    }
+
+    #[test]
+    fn compares_diagonal_levels() {
+        // buys at 15 against sells at 10, sells at 10 against buys at 15
+        let trades = grouped(&[(10, 1.0), (15, 9.0), (20, 2.0)], &[(10, 3.0), (15, 1.0), (20, 1.0)]);
+        let imbalances = detect_imbalances(&trades, 5, &ImbalanceConfig::default());
+        assert_eq!(imbalanced(&imbalances), vec![(15, ImbalanceSide::Buy)]);
+        assert_eq!(imbalances.levels[0].opposite_quantity, 3.0);
+    }
+
+    #[test]
+    fn levels_without_diagonal_volume_are_not_imbalanced() {
+        // the lowest buy and the highest sell have nothing on their diagonal
+        let trades = grouped(&[(10, 5.0), (15, 1.0)], &[(10, 1.0), (15, 5.0)]);
+        let imbalances = detect_imbalances(&trades, 5, &ImbalanceConfig::default());
+        assert!(imbalances.levels.is_empty());
+    }
+
+    #[test]
+    fn stacks_consecutive_levels() {
+        let trades = grouped(&[(15, 3.0), (20, 3.0), (25, 3.0), (35, 3.0)], &[(10, 1.0), (15, 1.0), (20, 1.0), (30, 1.0)]);
+        let imbalances = detect_imbalances(&trades, 5, &ImbalanceConfig::default());
+        assert_eq!(imbalances.stacked.len(), 1);
+        let stacked = imbalances.stacked[0];
+        assert_eq!((stacked.side, stacked.low.ticks(), stacked.high.ticks(), stacked.levels), (ImbalanceSide::Buy, 15, 25, 3));
+    }
+
+    fn profile(buys: &[(i64, f64)], sells: &[(i64, f64)]) -> VolumeProfile {
+        let mut profile = VolumeProfile::default();
+        profile.add(&grouped(buys, sells));
+        profile
+    }
+    fn value_area(stats: &ProfileStats) -> (i64, i64, i64) {
+        (stats.poc.ticks(), stats.value_area_low.ticks(), stats.value_area_high.ticks())
+    }
+
+    #[test]
+    fn value_area_grows_towards_more_volume() {
+        // 1, 3, 8, 4, 1 of 17, 70% is reached with 20 and 25
+        let profile = profile(&[(10, 1.0), (15, 2.0), (20, 5.0), (25, 2.0), (30, 1.0)], &[(15, 1.0), (20, 3.0), (25, 2.0)]);
+        let stats = profile.stats(0.7).unwrap();
+        assert_eq!(value_area(&stats), (20, 20, 25));
+        assert_eq!((stats.buy_volume, stats.sell_volume), (11.0, 6.0));
+        assert_eq!(value_area(&profile.stats(1.0).unwrap()), (20, 10, 30));
+    }
+
+    #[test]
+    fn ties_go_to_the_higher_level() {
+        let stats = profile(&[(10, 2.0), (15, 5.0), (20, 2.0)], &[]).stats(0.7).unwrap();
+        assert_eq!(value_area(&stats), (15, 15, 20));
+
+        let stats = profile(&[(10, 4.0), (15, 4.0)], &[]).stats(0.5).unwrap();
+        assert_eq!(value_area(&stats), (15, 15, 15));
+    }
+
+    #[test]
+    fn single_level_is_the_whole_profile() {
+        let stats = profile(&[(10, 1.0)], &[(10, 2.0)]).stats(0.7).unwrap();
+        assert_eq!(value_area(&stats), (10, 10, 10));
+        assert_eq!((stats.buy_volume, stats.sell_volume), (1.0, 2.0));
+        assert!(VolumeProfile::default().stats(0.7).is_none());
+    }
+}
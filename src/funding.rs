@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::{Kline, TickSize};
+
+/// Mark price and funding of a perpetual, from the mark price stream or the premium index endpoint.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MarkPriceUpdate {
+    pub time: u64,
+    pub mark_price: f64,
+    pub index_price: f64,
+    /// Rate that settles at `next_funding_time`, as a fraction (0.0001 for 0.01%).
+    pub funding_rate: f64,
+    pub next_funding_time: u64,
+}
+
+/// Settled funding rates and the mark price per kline.
+#[derive(Clone, Debug, Default)]
+pub struct FundingState {
+    /// Settled rates keyed by funding time.
+    history: BTreeMap<u64, f64>,
+    /// Last mark price seen within each kline, keyed by open time.
+    mark_prices: BTreeMap<u64, f64>,
+    current: Option<MarkPriceUpdate>,
+}
+impl FundingState {
+    pub fn current(&self) -> Option<MarkPriceUpdate> {
+        self.current
+    }
+    pub fn history(&self) -> &BTreeMap<u64, f64> {
+        &self.history
+    }
+
+    pub fn insert_history(&mut self, funding_time: u64, rate: f64) {
+        self.history.insert(funding_time, rate);
+    }
+
+    /// Once the funding time moves on, the rate of the previous update is what settled.
+    pub fn apply_mark_price(&mut self, update: MarkPriceUpdate, kline_open: u64) {
+        if let Some(previous) = self.current {
+            if previous.next_funding_time != 0 && update.next_funding_time > previous.next_funding_time {
+                self.history.insert(previous.next_funding_time, previous.funding_rate);
+            }
+        }
+        self.mark_prices.insert(kline_open, update.mark_price);
+        self.current = Some(update);
+    }
+
+    /// Premium of the mark over the last price of klines opening within `open_times`, in percent.
+    pub fn premiums(&self, klines: &BTreeMap<u64, Kline>, tick_size: TickSize, open_times: RangeInclusive<u64>) -> Vec<(u64, f64)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        self.mark_prices.range(open_times).filter_map(|(&open_time, &mark_price)| {
+            let close = klines.get(&open_time)?.close.to_f64(tick_size);
+            (close > 0.0).then(|| (open_time, (mark_price - close) / close * 100.0))
+        }).collect()
+    }
+
+    /// Settled rates between `from` and `to`, led by the last one before `from` so the
+    /// rate in effect at the left edge is known.
+    pub fn history_range(&self, from: u64, to: u64) -> Vec<(u64, f64)> {
+        if from > to {
+            return Vec::new();
+        }
+        let previous = self.history.range(..from).next_back();
+        previous.into_iter().chain(self.history.range(from..=to))
+            .map(|(&time, &rate)| (time, rate))
+            .collect()
+    }
+
+    /// Copy holding what was known at `time`.
+    pub fn until(&self, time: u64) -> Self {
+        Self {
+            history: self.history.range(..=time).map(|(&funding_time, &rate)| (funding_time, rate)).collect(),
+            mark_prices: self.mark_prices.range(..=time).map(|(&open_time, &mark_price)| (open_time, mark_price)).collect(),
+            current: self.current.filter(|current| current.time <= time),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.mark_prices.clear();
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Price;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn update(time: u64, mark_price: f64, funding_rate: f64, next_funding_time: u64) -> MarkPriceUpdate {
+        MarkPriceUpdate { time, mark_price, index_price: mark_price, funding_rate, next_funding_time }
+    }
+
+    #[test]
+    fn rate_settles_when_the_funding_time_moves_on() {
+        let mut funding = FundingState::default();
+        funding.apply_mark_price(update(1_000, 100.0, 0.0001, 8 * HOUR), 0);
+        funding.apply_mark_price(update(2_000, 100.0, 0.0002, 8 * HOUR), 0);
+        assert!(funding.history().is_empty(), "nothing settled yet");
+
+        // the last rate before the rollover is the one that settled
+        funding.apply_mark_price(update(8 * HOUR + 1_000, 100.0, -0.0001, 16 * HOUR), 8 * HOUR);
+        assert_eq!(funding.history().iter().map(|(&time, &rate)| (time, rate)).collect::<Vec<_>>(), vec![(8 * HOUR, 0.0002)]);
+        assert_eq!(funding.current().map(|current| current.funding_rate), Some(-0.0001));
+
+        // an out of order update for an earlier funding time settles nothing
+        funding.apply_mark_price(update(8 * HOUR + 2_000, 100.0, 0.0003, 8 * HOUR), 8 * HOUR);
+        assert_eq!(funding.history().len(), 1);
+    }
+
+    #[test]
+    fn unknown_funding_time_settles_nothing() {
+        let mut funding = FundingState::default();
+        funding.apply_mark_price(update(1_000, 100.0, 0.0001, 0), 0);
+        funding.apply_mark_price(update(2_000, 100.0, 0.0001, 8 * HOUR), 0);
+        assert!(funding.history().is_empty());
+    }
+
+    #[test]
+    fn history_range_leads_with_the_rate_in_effect() {
+        let mut funding = FundingState::default();
+        for (time, rate) in [(0, 0.1), (8 * HOUR, 0.2), (16 * HOUR, 0.3)] {
+            funding.insert_history(time, rate);
+        }
+        assert_eq!(funding.history_range(HOUR, 9 * HOUR), vec![(0, 0.1), (8 * HOUR, 0.2)]);
+        assert_eq!(funding.history_range(16 * HOUR, 20 * HOUR), vec![(8 * HOUR, 0.2), (16 * HOUR, 0.3)]);
+        assert!(funding.history_range(2, 1).is_empty());
+        assert_eq!(funding.until(8 * HOUR).history().len(), 2);
+    }
+
+    #[test]
+    fn premium_is_the_last_mark_over_the_close() {
+        let tick_size = TickSize::parse("0.1").unwrap();
+        let kline = |open_time: u64, close: &str| (open_time, Kline {
+            open_time,
+            open: Price::default(),
+            high: Price::default(),
+            low: Price::default(),
+            close: Price::parse(close, tick_size).unwrap(),
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            cum_volume_delta: 0.0,
+            close_time: open_time + 59_999,
+        });
+        let klines: BTreeMap<u64, Kline> = [kline(0, "100"), kline(60_000, "200"), kline(120_000, "0")].into_iter().collect();
+
+        let mut funding = FundingState::default();
+        funding.apply_mark_price(update(1_000, 100.0, 0.0, 0), 0);
+        funding.apply_mark_price(update(2_000, 101.0, 0.0, 0), 0);
+        funding.apply_mark_price(update(61_000, 199.0, 0.0, 0), 60_000);
+        funding.apply_mark_price(update(121_000, 1.0, 0.0, 0), 120_000);
+        funding.apply_mark_price(update(181_000, 1.0, 0.0, 0), 180_000);
+
+        // klines without a close or loaded data have no premium
+        let premiums = funding.premiums(&klines, tick_size, 0..=180_000);
+        assert_eq!(premiums.len(), 2);
+        assert_eq!(premiums[0].0, 0);
+        assert!((premiums[0].1 - 1.0).abs() < 1e-9);
+        assert!((premiums[1].1 + 0.5).abs() < 1e-9);
+    }
+}
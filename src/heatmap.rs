@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use crate::orderbook::{group_orders, PriceLevels};
+use crate::{Price, MINUTE_IN_MS};
+
+/// Snapshots taken per kline, so a column of the heatmap is a fraction of a candle wide.
+pub const SNAPSHOTS_PER_KLINE: u64 = 10;
+
+/// The book at one point in time, grouped at the bucket size it was recorded with.
+#[derive(Clone, Debug)]
+pub struct BookSnapshot {
+    pub time: u64,
+    /// Ascending by price.
+    pub bids: Vec<(Price, f64)>,
+    /// Ascending by price.
+    pub asks: Vec<(Price, f64)>,
+}
+impl BookSnapshot {
+    pub fn new(time: u64, bids: &PriceLevels, asks: &PriceLevels, bucket_ticks: i64) -> Self {
+        Self { time, bids: group(bids, bucket_ticks), asks: group(asks, bucket_ticks) }
+    }
+
+    pub fn bids_within(&self, prices: &RangeInclusive<Price>) -> &[(Price, f64)] {
+        within(&self.bids, prices)
+    }
+    pub fn asks_within(&self, prices: &RangeInclusive<Price>) -> &[(Price, f64)] {
+        within(&self.asks, prices)
+    }
+}
+
+/// Ring buffer of book snapshots, taken at most once per `interval_ms` and dropped once
+/// older than the retention window.
+#[derive(Clone, Debug)]
+pub struct DepthHistory {
+    snapshots: VecDeque<BookSnapshot>,
+    interval_ms: u64,
+    retention_ms: u64,
+}
+impl Default for DepthHistory {
+    fn default() -> Self {
+        Self { snapshots: VecDeque::new(), interval_ms: MINUTE_IN_MS / SNAPSHOTS_PER_KLINE, retention_ms: 30 * MINUTE_IN_MS }
+    }
+}
+impl DepthHistory {
+    pub fn interval_ms(&self) -> u64 {
+        self.interval_ms
+    }
+    pub fn retention_ms(&self) -> u64 {
+        self.retention_ms
+    }
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn set_interval(&mut self, interval_ms: u64) {
+        self.interval_ms = interval_ms.max(1);
+    }
+    /// A retention of zero stops recording and drops what was recorded.
+    pub fn set_retention(&mut self, retention_ms: u64) {
+        self.retention_ms = retention_ms;
+        if retention_ms == 0 {
+            self.snapshots.clear();
+        } else if let Some(latest) = self.snapshots.back().map(|snapshot| snapshot.time) {
+            self.prune(latest);
+        }
+    }
+
+    /// Whether a snapshot taken at `time` would be recorded.
+    pub fn is_due(&self, time: u64) -> bool {
+        self.retention_ms > 0 && self.snapshots.back().is_none_or(|last| time >= last.time + self.interval_ms)
+    }
+
+    pub fn push(&mut self, snapshot: BookSnapshot) {
+        let time = snapshot.time;
+        self.snapshots.push_back(snapshot);
+        self.prune(time);
+    }
+
+    pub fn latest(&self) -> Option<&BookSnapshot> {
+        self.snapshots.back()
+    }
+    /// Copy holding the snapshots taken up to `time`.
+    pub fn until(&self, time: u64) -> Self {
+        let end = self.snapshots.partition_point(|snapshot| snapshot.time <= time);
+        Self { snapshots: self.snapshots.range(..end).cloned().collect(), ..*self }
+    }
+
+    /// Snapshots taken within `times`.
+    pub fn range(&self, times: RangeInclusive<u64>) -> impl Iterator<Item = &BookSnapshot> + '_ {
+        let start = self.snapshots.partition_point(|snapshot| snapshot.time < *times.start());
+        self.snapshots.range(start..).take_while(move |snapshot| snapshot.time <= *times.end())
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    fn prune(&mut self, latest: u64) {
+        while self.snapshots.front().is_some_and(|snapshot| snapshot.time + self.retention_ms < latest) {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+fn group(levels: &PriceLevels, bucket_ticks: i64) -> Vec<(Price, f64)> {
+    group_orders(levels, Price::MIN..=Price::MAX, bucket_ticks).iter().collect()
+}
+
+fn within<'a>(levels: &'a [(Price, f64)], prices: &RangeInclusive<Price>) -> &'a [(Price, f64)] {
+    let start = levels.partition_point(|(price, _)| price < prices.start());
+    let end = levels.partition_point(|(price, _)| price <= prices.end());
+    &levels[start..end.max(start)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(time: u64) -> BookSnapshot {
+        BookSnapshot { time, bids: Vec::new(), asks: Vec::new() }
+    }
+    fn times(history: &DepthHistory) -> Vec<u64> {
+        history.range(0..=u64::MAX).map(|snapshot| snapshot.time).collect()
+    }
+
+    #[test]
+    fn snapshots_are_due_once_per_interval() {
+        let mut history = DepthHistory::default();
+        history.set_interval(100);
+        assert!(history.is_due(0));
+        history.push(snapshot(1_000));
+        assert!(!history.is_due(1_099));
+        assert!(history.is_due(1_100));
+    }
+
+    #[test]
+    fn snapshots_older_than_the_retention_are_evicted() {
+        let mut history = DepthHistory::default();
+        history.set_retention(1_000);
+        for time in [0, 500, 1_000, 1_200] {
+            history.push(snapshot(time));
+        }
+        // kept while exactly at the edge of the window
+        assert_eq!(times(&history), vec![500, 1_000, 1_200]);
+        history.push(snapshot(2_100));
+        assert_eq!(times(&history), vec![1_200, 2_100]);
+
+        // a shorter retention applies right away
+        history.set_retention(500);
+        assert_eq!(times(&history), vec![2_100]);
+        assert_eq!(history.latest().map(|snapshot| snapshot.time), Some(2_100));
+    }
+
+    #[test]
+    fn zero_retention_stops_recording() {
+        let mut history = DepthHistory::default();
+        history.push(snapshot(0));
+        history.set_retention(0);
+        assert!(history.is_empty());
+        assert!(!history.is_due(MINUTE_IN_MS));
+
+        history.set_retention(MINUTE_IN_MS);
+        history.push(snapshot(MINUTE_IN_MS));
+        assert_eq!(history.len(), 1);
+        history.clear();
+        assert!(history.is_empty());
+        assert!(history.is_due(MINUTE_IN_MS));
+    }
+}
@@ -0,0 +1,62 @@
+/// Trades used to derive a percentile threshold, counted back from the latest.
+pub const RECENT_TRADES: usize = 10_000;
+
+/// Minimum size for a trade to be marked on the chart.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LargeTradeThreshold {
+    Off,
+    /// In base asset.
+    Absolute(f64),
+    /// Percentile of the size of the last `RECENT_TRADES` trades, 99.0 marks the top 1%.
+    Percentile(f64),
+}
+impl LargeTradeThreshold {
+    /// Minimum quantity given the sizes of recent trades, `None` when nothing should be marked.
+    pub fn min_quantity(&self, mut recent_quantities: Vec<f64>) -> Option<f64> {
+        match *self {
+            LargeTradeThreshold::Off => None,
+            LargeTradeThreshold::Absolute(quantity) => Some(quantity),
+            LargeTradeThreshold::Percentile(percentile) => {
+                if recent_quantities.is_empty() {
+                    return None;
+                }
+                let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * (recent_quantities.len() - 1) as f64).round() as usize;
+                let (_, quantity, _) = recent_quantities.select_nth_unstable_by(rank, f64::total_cmp);
+                Some(*quantity)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_of_recent_sizes() {
+        let quantities: Vec<f64> = (1..=101).rev().map(f64::from).collect();
+        assert_eq!(LargeTradeThreshold::Percentile(99.0).min_quantity(quantities.clone()), Some(100.0));
+        assert_eq!(LargeTradeThreshold::Percentile(50.0).min_quantity(quantities.clone()), Some(51.0));
+        // out of range percentiles are clamped
+        assert_eq!(LargeTradeThreshold::Percentile(150.0).min_quantity(quantities.clone()), Some(101.0));
+        assert_eq!(LargeTradeThreshold::Percentile(-1.0).min_quantity(quantities), Some(1.0));
+    }
+
+    #[test]
+    fn percentile_of_no_trades_marks_nothing() {
+        assert_eq!(LargeTradeThreshold::Percentile(99.0).min_quantity(Vec::new()), None);
+    }
+
+    #[test]
+    fn percentile_of_a_single_trade_is_its_size() {
+        assert_eq!(LargeTradeThreshold::Percentile(99.5).min_quantity(vec![2.5]), Some(2.5));
+        assert_eq!(LargeTradeThreshold::Percentile(0.0).min_quantity(vec![2.5]), Some(2.5));
+    }
+
+    #[test]
+    fn absolute_size_ignores_recent_trades() {
+        assert_eq!(LargeTradeThreshold::Absolute(5.0).min_quantity(vec![100.0, 200.0]), Some(5.0));
+        assert_eq!(LargeTradeThreshold::Absolute(5.0).min_quantity(Vec::new()), Some(5.0));
+        assert_eq!(LargeTradeThreshold::Off.min_quantity(vec![100.0]), None);
+    }
+}
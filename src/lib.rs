@@ -1,118 +1,277 @@
 mod utils;
-
-use std::collections::{HashMap, BTreeMap};
+pub mod bar_replay;
+pub mod canvas;
+pub mod connection;
+pub mod connector;
+pub mod cvd;
+pub mod export;
+pub mod footprint;
+pub mod funding;
+pub mod heatmap;
+pub mod large_trades;
+pub mod liquidation;
+pub mod market;
+pub mod open_interest;
+pub mod orderbook;
+mod price;
+pub mod replay;
+pub mod surface;
+pub mod tape;
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, RwLock};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use serde::Deserialize;
 
-use wasm_bindgen::{JsCast, prelude::*};
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, MessageEvent, WebSocket, window};
-use serde_json::Value;
+use wasm_bindgen::prelude::*;
+use web_sys::{HtmlCanvasElement, WebSocket};
+
+use bar_replay::{BarReplay, BarStep};
+use canvas::{CanvasBubbleTrades, CanvasIndiCVD, CanvasIndiFunding, CanvasIndiLiquidations, CanvasIndicatorVolume, CanvasMain, CanvasOrderbook, CanvasTape, MAX_TAPE_TRADES};
+use connection::{Backoff, StreamSession};
+pub use connection::ConnectionState;
+use cvd::CvdAnchor;
+use export::ExportFormat;
+use footprint::{FootprintConfig, ProfileRange, StatsRow};
+use large_trades::LargeTradeThreshold;
+use market::MarketState;
+use open_interest::OiMode;
+use orderbook::{group_orders, liquidity_bands, DepthMode};
+pub use orderbook::BookSyncState;
+pub use price::{Price, TickSize};
+use replay::{DataSource, RecordKind, RecordedMessage, Recording, Replay, ReplaySpeed};
+use surface::WebSurface;
+use utils::{log, now_ms};
 
 extern crate js_sys;
 
 extern crate console_error_panic_hook;
 
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-}
-#[derive(Copy, Clone, Deserialize, Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Trade {
-    price: f64,
-    quantity: f64,
-    time: u64,
-    is_buyer_maker: bool,
+    pub price: Price,
+    pub quantity: f64,
+    pub time: u64,
+    pub is_buyer_maker: bool,
 }
 #[derive(Clone, Debug)]
 pub struct Order {
-    price: f64,
-    quantity: f64,
+    pub price: Price,
+    pub quantity: f64,
 }
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Kline {
-    open_time: u64,
-    open: f64,
-    high: f64,
-    low: f64,
-    close: f64,
-    buy_volume: f64,
-    sell_volume: f64,
-    cum_volume_delta: f64,
-    close_time: u64,
+    pub open_time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+    pub cum_volume_delta: f64,
+    pub close_time: u64,
 }
-#[derive(Debug)]
+/// Trades of one kline in the order they happened, with the delta they built up.
+#[derive(Clone, Debug, Default)]
 pub struct TradeGroups {
-    buy_trades: Vec<(f64, f64)>,
-    sell_trades: Vec<(f64, f64)>,
+    pub trades: Vec<Trade>,
+    pub delta: RunningDelta,
 }
 #[derive(Debug)]
 pub struct GroupedTrades {
-    buys: HashMap<i64, f64>,
-    sells: HashMap<i64, f64>,
+    pub buys: HashMap<Price, f64>,
+    pub sells: HashMap<Price, f64>,
+}
+/// Buy minus sell volume within a kline, trade by trade. Starts at zero on the kline's open.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RunningDelta {
+    pub close: f64,
+    pub max: f64,
+    pub min: f64,
+}
+impl RunningDelta {
+    fn add(&mut self, trade: &Trade) {
+        self.close += if trade.is_buyer_maker { -trade.quantity } else { trade.quantity };
+        self.max = self.max.max(self.close);
+        self.min = self.min.min(self.close);
+    }
+}
+impl TradeGroups {
+    pub fn from_trades(mut trades: Vec<Trade>) -> Self {
+        trades.sort_by_key(|trade| trade.time);
+        let mut delta = RunningDelta::default();
+        for trade in &trades {
+            delta.add(trade);
+        }
+        Self { trades, delta }
+    }
+    /// Appends `trade`. One older than the last trade is put in place and the delta is replayed.
+    pub fn push(&mut self, trade: &Trade) {
+        match self.trades.last() {
+            Some(last) if trade.time < last.time => {
+                let index = self.trades.partition_point(|t| t.time <= trade.time);
+                self.trades.insert(index, *trade);
+                self.delta = RunningDelta::default();
+                for trade in &self.trades {
+                    self.delta.add(trade);
+                }
+            },
+            _ => {
+                self.trades.push(*trade);
+                self.delta.add(trade);
+            },
+        }
+    }
+    pub fn trade_count(&self) -> usize {
+        self.trades.len()
+    }
+    pub fn volume(&self) -> f64 {
+        self.trades.iter().map(|trade| trade.quantity).sum()
+    }
+    /// Sums quantities per price bucket of `bucket_ticks`.
+    pub fn grouped(&self, bucket_ticks: i64) -> GroupedTrades {
+        let mut buys: HashMap<Price, f64> = HashMap::new();
+        let mut sells: HashMap<Price, f64> = HashMap::new();
+
+        for trade in &self.trades {
+            let side = if trade.is_buyer_maker { &mut sells } else { &mut buys };
+            *side.entry(trade.price.bucket(bucket_ticks)).or_insert(0.0) += trade.quantity;
+        }
+        GroupedTrades { buys, sells }
+    }
 }
 
 const MINUTE_IN_MS: u64 = 60 * 1000;
-
-macro_rules! try_clear {
-    ($lock:expr, $name:expr) => {
-        match $lock.try_write() {
-            Ok(mut data) => data.clear(),
-            Err(_) => log(format!("Failed to acquire write lock on {} during clear_datasets", $name).as_str()),
+const HOUR_IN_MS: u64 = 60 * MINUTE_IN_MS;
+const DAY_IN_MS: u64 = 24 * HOUR_IN_MS;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timeframe {
+    M1,
+    M3,
+    M5,
+    M15,
+    H1,
+    H4,
+}
+impl Timeframe {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Timeframe::M1 => "1m",
+            Timeframe::M3 => "3m",
+            Timeframe::M5 => "5m",
+            Timeframe::M15 => "15m",
+            Timeframe::H1 => "1h",
+            Timeframe::H4 => "4h",
         }
-    };
+    }
+    pub fn as_ms(&self) -> u64 {
+        match self {
+            Timeframe::M1 => MINUTE_IN_MS,
+            Timeframe::M3 => 3 * MINUTE_IN_MS,
+            Timeframe::M5 => 5 * MINUTE_IN_MS,
+            Timeframe::M15 => 15 * MINUTE_IN_MS,
+            Timeframe::H1 => HOUR_IN_MS,
+            Timeframe::H4 => 4 * HOUR_IN_MS,
+        }
+    }
+    /// Open time of the kline that `time` falls into.
+    pub fn kline_open(&self, time: u64) -> u64 {
+        time - time % self.as_ms()
+    }
+}
+impl std::str::FromStr for Timeframe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1m" => Ok(Timeframe::M1),
+            "3m" => Ok(Timeframe::M3),
+            "5m" => Ok(Timeframe::M5),
+            "15m" => Ok(Timeframe::M15),
+            "1h" => Ok(Timeframe::H1),
+            "4h" => Ok(Timeframe::H4),
+            _ => Err(format!("Unsupported timeframe: {}", s)),
+        }
+    }
 }
 
 #[wasm_bindgen]
 pub struct CanvasManager {
-    klines_ohlcv: Arc<RwLock<BTreeMap<u64, Kline>>>,
-    klines_trades: Arc<RwLock<BTreeMap<u64, TradeGroups>>>,
-    orderbook_manager: OrderbookManager,
-    oi_datapoints: Arc<RwLock<Vec<(u64, f64)>>>,
+    market: Arc<RwLock<MarketState>>,
     canvas_main: CanvasMain,
     canvas_orderbook: CanvasOrderbook,
     canvas_indicator_volume: CanvasIndicatorVolume,
     canvas_bubble: Rc<RefCell<CanvasBubbleTrades>>,
     canvas_indi_cvd: CanvasIndiCVD,
+    canvas_indi_funding: CanvasIndiFunding,
+    canvas_indi_liquidations: CanvasIndiLiquidations,
+    canvas_tape: Rc<RefCell<CanvasTape>>,
     autoscale: bool,
     pan_x_offset: f64,
     pan_y_offset: f64,
     x_zoom: f64,
     y_zoom: f64,
-    bucket_size: Arc<RwLock<f64>>,
-    last_depth_update: Rc<RefCell<u64>>,
-    websocket: Option<WebSocket>,
-    tick_size: Rc<RefCell<f64>>,   
+    websocket: Rc<RefCell<Option<WebSocket>>>,
+    /// Owns the live stream's handlers, dropping it frees them.
+    stream_session: Option<Rc<StreamSession>>,
+    connection_state: Rc<Cell<ConnectionState>>,
+    stream_generation: Rc<Cell<u32>>,
+    resync_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    reconnect_callback: Rc<RefCell<Option<js_sys::Function>>>,
     fixed_y_max: f64,
     fixed_y_min: f64,
+    footprint_config: FootprintConfig,
+    profile_range: ProfileRange,
+    cvd_anchor: CvdAnchor,
+    oi_weighted_price: bool,
+    /// Liquidations smaller than this are left out of markers and totals.
+    min_liquidation_size: f64,
+    large_trade_threshold: LargeTradeThreshold,
+    recorder: Rc<RefCell<Option<Recording>>>,
+    replay: Option<Replay>,
+    bar_replay: Option<BarReplay>,
+    data_source: DataSource,
 }
 #[wasm_bindgen]
 impl CanvasManager {
-    pub fn new(canvas1: HtmlCanvasElement, canvas2: HtmlCanvasElement, canvas3: HtmlCanvasElement, canvas4: HtmlCanvasElement, canvas5: HtmlCanvasElement) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(canvas1: HtmlCanvasElement, canvas2: HtmlCanvasElement, canvas3: HtmlCanvasElement, canvas4: HtmlCanvasElement, canvas5: HtmlCanvasElement, canvas6: HtmlCanvasElement, canvas7: HtmlCanvasElement, canvas8: HtmlCanvasElement) -> Self {
         utils::set_panic_hook();
         Self {
-            klines_ohlcv: Arc::new(RwLock::new(BTreeMap::new())),
-            klines_trades: Arc::new(RwLock::new(BTreeMap::new())),
-            orderbook_manager: OrderbookManager::new(),
-            oi_datapoints: Arc::new(RwLock::new(Vec::new())),
-            canvas_main: CanvasMain::new(canvas1).expect("Failed to create CanvasMain"),
-            canvas_orderbook: CanvasOrderbook::new(canvas2).expect("Failed to create CanvasOrderbook"),
-            canvas_indicator_volume: CanvasIndicatorVolume::new(canvas3).expect("Failed to create CanvasIndicatorVolume"),
-            canvas_bubble: Rc::new(RefCell::new(CanvasBubbleTrades::new(canvas4).expect("Failed to create CanvasBubbleTrades"))),
-            canvas_indi_cvd: CanvasIndiCVD::new(canvas5).expect("Failed to create CanvasIndiCVD"),
+            market: Arc::new(RwLock::new(MarketState::default())),
+            canvas_main: CanvasMain::new(web_surface(&canvas1, "CanvasMain"), canvas1.width() as f64, canvas1.height() as f64),
+            canvas_orderbook: CanvasOrderbook::new(web_surface(&canvas2, "CanvasOrderbook"), canvas2.width() as f64, canvas2.height() as f64),
+            canvas_indicator_volume: CanvasIndicatorVolume::new(web_surface(&canvas3, "CanvasIndicatorVolume"), canvas3.width() as f64, canvas3.height() as f64),
+            canvas_bubble: Rc::new(RefCell::new(CanvasBubbleTrades::new(web_surface(&canvas4, "CanvasBubbleTrades"), canvas4.width() as f64, canvas4.height() as f64))),
+            canvas_indi_cvd: CanvasIndiCVD::new(web_surface(&canvas5, "CanvasIndiCVD"), canvas5.width() as f64, canvas5.height() as f64),
+            canvas_indi_funding: CanvasIndiFunding::new(web_surface(&canvas6, "CanvasIndiFunding"), canvas6.width() as f64, canvas6.height() as f64),
+            canvas_indi_liquidations: CanvasIndiLiquidations::new(web_surface(&canvas7, "CanvasIndiLiquidations"), canvas7.width() as f64, canvas7.height() as f64),
+            canvas_tape: Rc::new(RefCell::new(CanvasTape::new(web_surface(&canvas8, "CanvasTape"), canvas8.width() as f64, canvas8.height() as f64))),
             autoscale: true,
             pan_x_offset: 0.0,
             pan_y_offset: 0.0,
             x_zoom: 30.0,
             y_zoom: 10.0,
-            bucket_size: Arc::new(RwLock::new(5.0)),
-            last_depth_update: Rc::new(RefCell::new(0)),
-            websocket: None,
-            tick_size: Rc::new(RefCell::new(0.1)),
+            websocket: Rc::new(RefCell::new(None)),
+            stream_session: None,
+            connection_state: Rc::new(Cell::new(ConnectionState::Disconnected)),
+            stream_generation: Rc::new(Cell::new(0)),
+            resync_callback: Rc::new(RefCell::new(None)),
+            reconnect_callback: Rc::new(RefCell::new(None)),
             fixed_y_max: 0.0,
             fixed_y_min: 0.0,
+            footprint_config: FootprintConfig::default(),
+            profile_range: ProfileRange::Visible,
+            cvd_anchor: CvdAnchor::Daily,
+            oi_weighted_price: false,
+            min_liquidation_size: 0.0,
+            large_trade_threshold: LargeTradeThreshold::Percentile(99.5),
+            recorder: Rc::new(RefCell::new(None)),
+            replay: None,
+            bar_replay: None,
+            data_source: DataSource::Empty,
         }
     }
     
@@ -121,337 +280,188 @@ impl CanvasManager {
     }
     
     pub async fn start_websocket(&mut self, symbol: &str) {
-        if let Some(ws) = &self.websocket {
+        self.replay = None;
+        self.bar_replay = None;
+        let generation = self.stream_generation.get().wrapping_add(1);
+        self.stream_generation.set(generation);
+
+        let previous = self.websocket.borrow_mut().take();
+        let had_stream = previous.is_some();
+        if let Some(ws) = previous {
             log("Closing existing websocket");
-            ws.close().unwrap();
+            connection::close(&ws);
+        }
+        // a replay leaves no socket behind, but its data can't be streamed on top of
+        if had_stream || self.data_source.is_stale_for(symbol) {
             self.clear_datasets();
-            *self.tick_size.borrow_mut() = 0.1;
         }
-        let mut trades_buffer: Vec<Trade> = Vec::new();
-
-        let klines_trades = Arc::clone(&self.klines_trades);
-        let klines_ohlcv = Arc::clone(&self.klines_ohlcv);
-
-        let bids = Arc::clone(&self.orderbook_manager.bids);
-        let asks = Arc::clone(&self.orderbook_manager.asks);
-        //let last_update_id = Arc::clone(&self.orderbook_manager.last_update_id);
-        let last_depth_update = Rc::clone(&self.last_depth_update);
-
-        let mut next_kline_trades = Vec::new();
-        let mut current_kline_open: u64 = 0;
-        let mut current_kline_close: u64 = 0;
-
+        self.data_source = DataSource::Live(symbol.to_string());
         log(format!("Starting websocket for {}", symbol).as_str());
-        let canvas_bubble = Rc::clone(&self.canvas_bubble);
 
-        let ws = WebSocket::new(&format!("wss://fstream.binance.com/stream?streams={}@aggTrade/{}@depth@100ms/{}@kline_1m", symbol, symbol, symbol)).unwrap();
-
-        let onmessage_callback = Closure::wrap(Box::new(move |event: MessageEvent| {
-            if let Ok(data) = event.data().dyn_into::<js_sys::JsString>() {
-                let data_str: String = data.into();
-                let v: Value = serde_json::from_str(&data_str).unwrap();
-
-                match v["stream"].as_str() {
-                    Some(stream) if stream.contains("aggTrade") => {
-                        if let (Some(price), Some(quantity), Some(time), Some(is_buyer_maker)) = 
-                        (v["data"]["p"].as_str(), v["data"]["q"].as_str(), v["data"]["T"].as_u64(), v["data"]["m"].as_bool()) {
-                            if let (Ok(price), Ok(quantity)) = (price.parse::<f64>(), quantity.parse::<f64>()) {
-                                let trade = Trade { price, quantity, time, is_buyer_maker };
-                                trades_buffer.push(trade);
-                            }
-                        }
-                    },
-                    Some(stream) if stream.contains("depth") => {
-                        if let Some(bids_array) = v["data"]["b"].as_array() {
-                            if let Ok(mut bids_borrowed) = bids.write() {
-                                let min_price = bids_borrowed.iter().map(|x| x.price).fold(f64::INFINITY, |a, b| a.min(b));
-                                let bids_array: Vec<&Value> = bids_array.iter().filter(|x| {
-                                    if let Some(price) = x[0].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        price >= min_price
-                                    } else {
-                                        false
-                                    }
-                                }).collect();
-                                for bid in bids_array {
-                                    let price = match bid[0].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        Some(price) => price,
-                                        None => continue,
-                                    };
-                                    let quantity = match bid[1].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        Some(quantity) => quantity,
-                                        None => continue,
-                                    };
-                                    if quantity == 0.0 {
-                                        bids_borrowed.retain(|x| x.price != price);
-                                    } else if let Some(bid) = bids_borrowed.iter_mut().find(|x| x.price == price) {
-                                        bid.quantity = quantity;
-                                    } else {
-                                        bids_borrowed.push(Order { price, quantity });
-                                    }
-                                }
-                            } else {
-                                log(&format!("bids locked on render"));
-                            }
-                        }
-                        if let Some(asks_array) = v["data"]["a"].as_array() {
-                            if let Ok(mut asks_borrowed) = asks.write() {
-                                let max_price = asks_borrowed.iter().map(|x| x.price).fold(f64::NEG_INFINITY, |a, b| a.max(b));
-                                let asks_array: Vec<&Value> = asks_array.iter().filter(|x| {
-                                    if let Some(price) = x[0].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        price <= max_price
-                                    } else {
-                                        false
-                                    }
-                                }).collect();
-                                for ask in asks_array {
-                                    let price = match ask[0].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        Some(price) => price,
-                                        None => continue,
-                                    };
-                                    let quantity = match ask[1].as_str().and_then(|s| s.parse::<f64>().ok()) {
-                                        Some(quantity) => quantity,
-                                        None => continue,
-                                    };
-                                    if quantity == 0.0 {
-                                        asks_borrowed.retain(|x| x.price != price);
-                                    } else if let Some(ask) = asks_borrowed.iter_mut().find(|x| x.price == price) {
-                                        ask.quantity = quantity;
-                                    } else {
-                                        asks_borrowed.push(Order { price, quantity });
-                                    }
-                                }
-                            } else {
-                                log(&format!("asks locked on render"));
-                            }
-                        }
-                        if let Some(update_time) = v["data"]["T"].as_u64() {
-                            if current_kline_open != 0 {
-                                match klines_trades.write() {
-                                    Ok(mut klines_trades) => {
-                                        let mut canvas_bubble = canvas_bubble.borrow_mut();
-                                        canvas_bubble.render(&trades_buffer, update_time);
-
-                                        let trade_groups = klines_trades.entry(current_kline_open).or_insert(TradeGroups { buy_trades: Vec::new(), sell_trades: Vec::new() });
-                                        for trade in trades_buffer.drain(..) {
-                                            if trade.time >= current_kline_open && trade.time < current_kline_close {
-                                                if trade.is_buyer_maker {
-                                                    trade_groups.sell_trades.push((trade.price, trade.quantity));
-                                                } else {
-                                                    trade_groups.buy_trades.push((trade.price, trade.quantity));
-                                                }
-                                            } else if trade.time >= current_kline_close {
-                                                next_kline_trades.push(trade);
-                                            }
-                                        }
-                                        if !next_kline_trades.is_empty() {
-                                            next_kline_trades.retain(|trade| {
-                                                if trade.time >= current_kline_close {
-                                                    let trade_groups = klines_trades.entry(current_kline_close + 1).or_insert(TradeGroups { buy_trades: Vec::new(), sell_trades: Vec::new() });
-                                                    if trade.is_buyer_maker {
-                                                        trade_groups.sell_trades.push((trade.price, trade.quantity));
-                                                    } else {
-                                                        trade_groups.buy_trades.push((trade.price, trade.quantity));
-                                                    }
-                                                    false
-                                                } else {
-                                                    true
-                                                }
-                                            });
-                                        }
-                                    },
-                                    Err(poisoned) => {
-                                        log(&format!("klines_trades locked on render: {:?}", poisoned));
-                                    }
-                                }
-                                *last_depth_update.borrow_mut() = update_time;
-                            }
-                        }                    
-                    },
-                    Some(stream) if stream.contains("kline") => {
-                        if let Some(kline_data) = v["data"]["k"].as_object() {
-                            let open_time = kline_data["t"].as_u64();
-                            let open = kline_data["o"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let high = kline_data["h"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let low = kline_data["l"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let close = kline_data["c"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let volume = kline_data["v"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let buy_volume = kline_data["V"].as_str().and_then(|s| s.parse::<f64>().ok());
-                            let sell_volume = match (volume, buy_volume) {
-                                (Some(volume), Some(buy_volume)) => Some(volume - buy_volume),
-                                _ => None,
-                            };
-                            let close_time = kline_data["T"].as_u64();
-                    
-                            if let (
-                                Some(open_time), 
-                                Some(open), Some(high), Some(low), Some(close), 
-                                Some(buy_volume), Some(sell_volume), 
-                                Some(close_time)) = (open_time, open, high, low, close, buy_volume, sell_volume, close_time) {
-                            
-                                let mut last_kline_cvd = 0.0;
-                                let mut last_open_time = 0;
-                                let mut last_buy_volume = 0.0;
-                                let mut last_sell_volume = 0.0;
-                                match klines_ohlcv.read() {
-                                    Ok(klines_ohlcv) => {
-                                        if let Some((last_open_time_val, last_kline)) = klines_ohlcv.iter().last() {
-                                            last_kline_cvd = last_kline.cum_volume_delta;
-                                            last_open_time = *last_open_time_val;
-                                            last_buy_volume = last_kline.buy_volume;
-                                            last_sell_volume = last_kline.sell_volume;
-                                        }
-                                    },
-                                    Err(e) => {
-                                        log(&format!("Failed to acquire lock on klines_ohlcv during render: {}", e));
-                                    }
-                                };
-                                let cum_volume_delta = if last_open_time == open_time {
-                                    last_kline_cvd - (last_buy_volume - last_sell_volume) + (buy_volume - sell_volume)
-                                } else {
-                                    last_kline_cvd + buy_volume - sell_volume
-                                };
-                            
-                                let kline = Kline {
-                                    open_time, 
-                                    open, high, low, close,
-                                    buy_volume, sell_volume,
-                                    cum_volume_delta,
-                                    close_time,
-                                };
-                                if let Ok(mut klines_ohlcv) = klines_ohlcv.write() {
-                                    klines_ohlcv.insert(open_time, kline);  
-                                }
-                                current_kline_open = open_time;
-                                current_kline_close = close_time;
-                            }
-                        }
-                    },
-                    _ => {
-                       log(&format!("Unknown stream: {:?}", v));
-                    }
-                }
-            }
-        }) as Box<dyn FnMut(MessageEvent)>);
-
-        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        onmessage_callback.forget();
-
-        self.websocket = Some(ws);
+        let session = Rc::new(StreamSession {
+            symbol: symbol.to_string(),
+            market: Arc::clone(&self.market),
+            canvas_bubble: Rc::clone(&self.canvas_bubble),
+            canvas_tape: Rc::clone(&self.canvas_tape),
+            recorder: Rc::clone(&self.recorder),
+            resync_callback: Rc::clone(&self.resync_callback),
+            reconnect_callback: Rc::clone(&self.reconnect_callback),
+            state: Rc::clone(&self.connection_state),
+            websocket: Rc::clone(&self.websocket),
+            generation: Rc::clone(&self.stream_generation),
+            backoff: RefCell::new(Backoff::default()),
+            handlers: RefCell::new(None),
+        });
+        self.stream_session = Some(Rc::clone(&session));
+        connection::connect(session, generation, false);
     }   
+    /// `callback` is invoked with the open time of the last kline received before the stream
+    /// dropped, once it is back up. Klines and footprint trades from that time on should be
+    /// refetched and passed to `gather_klines`/`gather_hist_trades`.
+    pub fn set_reconnect_callback(&mut self, callback: js_sys::Function) {
+        *self.reconnect_callback.borrow_mut() = Some(callback);
+    }
+    pub fn get_connection_state(&self) -> ConnectionState {
+        self.connection_state.get()
+    }
 
     pub fn render_start(&mut self) {
-        match self.klines_ohlcv.try_read() {
-            Ok(klines_borrowed) => {
-                let last_kline_open: u64 = match klines_borrowed.iter().last() {
-                    Some((last_kline_open, _)) => *last_kline_open,
-                    None => return, 
-                };
-                let zoom_scale: f64 = self.x_zoom * MINUTE_IN_MS as f64;
-                let time_difference: f64 = last_kline_open as f64 + MINUTE_IN_MS as f64 - zoom_scale;
-
-                let left_x: f64 = 0.0 - self.pan_x_offset;
-                let right_x: f64 = self.canvas_main.width - self.pan_x_offset;
+        if let Some(replay) = self.replay.as_mut() {
+            apply_recorded(&self.market, &self.canvas_bubble, &self.canvas_tape, replay.advance(now_ms()));
+        }
+        if let Some(bar_replay) = self.bar_replay.take() {
+            self.render_market(bar_replay.view());
+            self.bar_replay = Some(bar_replay);
+            return;
+        }
+        let market = Arc::clone(&self.market);
+        match market.try_read() {
+            Ok(market) => self.render_market(&market),
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during render: {}", e));
+            }
+        };
+    }
 
-                let visible_klines: Vec<_> = klines_borrowed.iter().filter(|&(open_time, _)| {
-                    let x: f64 = ((*open_time as f64) - time_difference) / zoom_scale * self.canvas_main.width;
-                    x >= left_x as f64 && x <= right_x as f64
-                }).collect();
-                                                
-                let avg_body_length: f64 = visible_klines.iter()
-                    .take(visible_klines.len() - 1) 
-                    .map(|(_, kline)| (kline.close - kline.open).abs())
-                    .sum::<f64>() / (visible_klines.len() - 1) as f64;
+    fn render_market(&mut self, market: &MarketState) {
+        self.canvas_tape.borrow_mut().render(market.tick_size());
 
-                let mut y_max: f64;
-                let mut y_min: f64;
-                
-                if self.autoscale | (self.fixed_y_max == 0.0 && self.fixed_y_min == 0.0) {
-                    y_max = visible_klines.iter().map(|(_, kline)| kline.high).fold(0.0, f64::max) + avg_body_length;
-                    y_min = visible_klines.iter().map(|(_, kline)| kline.low).fold(f64::MAX, f64::min) - avg_body_length; 
-                
-                    self.fixed_y_max = y_max;
-                    self.fixed_y_min = y_min;
-                } else {
-                    y_max = self.fixed_y_max;
-                    y_min = self.fixed_y_min;
+        let klines_borrowed = market.klines();
+        let last_kline_open: u64 = match klines_borrowed.iter().last() {
+            Some((last_kline_open, _)) => *last_kline_open,
+            None => return, 
+        };
+        let interval = market.timeframe().as_ms() as f64;
+        let zoom_scale: f64 = self.x_zoom * interval;
+        let time_difference: f64 = last_kline_open as f64 + interval - zoom_scale;
+
+        let left_x: f64 = 0.0 - self.pan_x_offset;
+        let right_x: f64 = self.canvas_main.width - self.pan_x_offset;
+
+        let visible_klines: Vec<_> = klines_borrowed.iter().filter(|&(open_time, _)| {
+            let x: f64 = ((*open_time as f64) - time_difference) / zoom_scale * self.canvas_main.width;
+            x >= left_x && x <= right_x
+        }).collect();
+        let visible_times = match (visible_klines.first(), visible_klines.last()) {
+            (Some((first, _)), Some((last, _))) => **first..=**last,
+            _ => return,
+        };
+                                        
+        let tick_size = market.tick_size();
+        let avg_body_length: f64 = visible_klines.iter()
+            .take(visible_klines.len() - 1) 
+            .map(|(_, kline)| (kline.close.to_f64(tick_size) - kline.open.to_f64(tick_size)).abs())
+            .sum::<f64>() / (visible_klines.len() - 1) as f64;
+
+        let mut y_max: f64;
+        let mut y_min: f64;
+        
+        if self.autoscale | (self.fixed_y_max == 0.0 && self.fixed_y_min == 0.0) {
+            y_max = visible_klines.iter().map(|(_, kline)| kline.high.to_f64(tick_size)).fold(0.0, f64::max) + avg_body_length;
+            y_min = visible_klines.iter().map(|(_, kline)| kline.low.to_f64(tick_size)).fold(f64::MAX, f64::min) - avg_body_length; 
+        
+            self.fixed_y_max = y_max;
+            self.fixed_y_min = y_min;
+        } else {
+            y_max = self.fixed_y_max;
+            y_min = self.fixed_y_min;
 
-                    let range = self.fixed_y_max - self.fixed_y_min;
-                
-                    y_max += range * (self.pan_y_offset / self.canvas_main.height) + range * (self.y_zoom / 100.0);
-                    y_min += range * (self.pan_y_offset / self.canvas_main.height) - range * (self.y_zoom / 100.0);
-                } 
-                    
-                self.canvas_indicator_volume.render(&visible_klines);
-                
-                match self.oi_datapoints.try_read() {
-                    Ok(oi_datapoints_borrowed) => {
-                        let visible_oi_datapoints: Vec<_> = oi_datapoints_borrowed.iter().filter(|&(time, _)| {
-                            let x: f64 = ((*time as f64) - time_difference) / zoom_scale * self.canvas_main.width;
-                            x >= left_x as f64 && x <= right_x as f64
-                        }).collect();
-                        self.canvas_indi_cvd.render(&visible_klines, &visible_oi_datapoints);
-                    },
-                    Err(e) => {
-                        log(&format!("Failed to acquire lock on oi_datapoints during render: {}", e));
-                    }
+            let range = self.fixed_y_max - self.fixed_y_min;
+        
+            y_max += range * (self.pan_y_offset / self.canvas_main.height) + range * (self.y_zoom / 100.0);
+            y_min += range * (self.pan_y_offset / self.canvas_main.height) - range * (self.y_zoom / 100.0);
+        } 
+            
+        self.canvas_indicator_volume.render(&visible_klines);
+        
+        let visible_oi_points = market.open_interest_points(visible_times.clone());
+        let cvd_candles = market.cvd_candles(visible_times.clone(), self.cvd_anchor);
+        self.canvas_indi_cvd.render(&cvd_candles, &visible_oi_points);
+
+        let funding = market.funding();
+        let premiums = funding.premiums(klines_borrowed, tick_size, visible_times.clone());
+        let funding_history = funding.history_range(*visible_times.start(), last_kline_open + market.timeframe().as_ms());
+        self.canvas_indi_funding.render(last_kline_open, &premiums, &funding_history, funding.current());
+
+        let liquidation_totals = market.liquidation_totals(visible_times.clone(), self.min_liquidation_size);
+        self.canvas_indi_liquidations.render(last_kline_open, &liquidation_totals);
+
+        let bucket_size = market.bucket_size();
+        let orderbook_manager = market.orderbook();
+        match (orderbook_manager.bids.try_read(), orderbook_manager.asks.try_read()) {
+            (Ok(bids_borrowed), Ok(asks_borrowed)) => {
+                let num_possible_lines = (y_max - y_min) / (bucket_size as f64 * tick_size.as_f64());
+
+                let visible_prices = match (Price::from_f64(y_min, tick_size), Price::from_f64(y_max, tick_size)) {
+                    (Some(low), Some(high)) => low..=high,
+                    _ => return,
+                };
+                // cumulative sums have to start at the best price, even when it's out of view
+                let (bid_prices, ask_prices) = match self.canvas_orderbook.depth_mode {
+                    DepthMode::Levels => (visible_prices.clone(), visible_prices.clone()),
+                    DepthMode::Cumulative => (*visible_prices.start()..=Price::MAX, Price::MIN..=*visible_prices.end()),
+                };
+                let grouped_bids = group_orders(&bids_borrowed, bid_prices, bucket_size);
+                let grouped_asks = group_orders(&asks_borrowed, ask_prices, bucket_size);
+                let liquidity = liquidity_bands(&bids_borrowed, &asks_borrowed);
+
+                self.canvas_orderbook.render(y_min, y_max, &grouped_bids, &grouped_asks, &liquidity, &visible_klines, market.last_depth_update(), tick_size, num_possible_lines);
+                let top_of_book = market.top_of_book();
+                if let Some(top_of_book) = &top_of_book {
+                    self.canvas_orderbook.render_top_of_book(y_min, y_max, top_of_book, tick_size);
                 }
 
-                let bucket_size = self.bucket_size.read().unwrap();
-                match (self.orderbook_manager.bids.try_read(), self.orderbook_manager.asks.try_read()) {
-                    (Ok(bids_borrowed), Ok(asks_borrowed)) => {
-                        let num_possible_lines = (y_max - y_min) / *bucket_size;
-                        let decimals = self.tick_size.borrow().log10().abs() as i32;
-                        let multiplier = 10f64.powi(decimals);
-
-                        let filtered_bids = bids_borrowed.iter().filter(|order| order.price >= y_min && order.price <= y_max).collect::<Vec<_>>();
-                        let filtered_asks = asks_borrowed.iter().filter(|order| order.price >= y_min && order.price <= y_max).collect::<Vec<_>>();
-
-                        let grouped_bids = group_orders(*bucket_size, filtered_bids, multiplier);
-                        let grouped_asks = group_orders(*bucket_size, filtered_asks, multiplier);
-
-                        self.canvas_orderbook.render(y_min, y_max, grouped_bids, grouped_asks, &visible_klines, &self.last_depth_update, decimals, num_possible_lines);
+                let profile_times = match self.profile_range {
+                    ProfileRange::Off => None,
+                    ProfileRange::Visible => Some(visible_times.clone()),
+                    ProfileRange::Session => Some(last_kline_open - last_kline_open % DAY_IN_MS..=last_kline_open),
+                };
+                let footprints = market.footprints(visible_times.clone(), &self.footprint_config);
+                let heatmap: Vec<_> = market.depth_history().range(*visible_times.start()..=last_kline_open + market.timeframe().as_ms()).collect();
+                self.canvas_main.render(y_min, y_max, &visible_klines, &heatmap, footprints, tick_size, num_possible_lines);
+
+                if let Some(profile_times) = profile_times {
+                    let profile = market.volume_profile(profile_times);
+                    let stats = profile.stats(self.footprint_config.value_area);
+                    self.canvas_main.render_volume_profile(y_min, y_max, &profile, stats, tick_size, num_possible_lines);
+                }
+                if let Some(top_of_book) = &top_of_book {
+                    self.canvas_main.render_top_of_book(y_min, y_max, top_of_book, tick_size);
+                }
+                let large_trades = market.large_trades(visible_times.clone(), self.large_trade_threshold);
+                self.canvas_main.render_large_trades(y_min, y_max, last_kline_open, &large_trades, tick_size);
+                let liquidations = market.liquidations(visible_times, self.min_liquidation_size);
+                self.canvas_main.render_liquidations(y_min, y_max, last_kline_open, &liquidations, tick_size);
 
-                        match self.klines_trades.try_read() {
-                            Ok(klines_trades_borrowed) => {    
-                                let mut grouped_trades: Vec<(u64, GroupedTrades)> = Vec::new();
-                        
-                                for (open_time, trade_groups) in klines_trades_borrowed.iter() {
-                                    let x: f64 = ((*open_time as f64) - time_difference) / zoom_scale * self.canvas_main.width;
-                                    if x >= left_x as f64 && x <= right_x as f64 {
-                                        let mut buys: HashMap<i64, f64> = HashMap::new();
-                                        let mut sells: HashMap<i64, f64> = HashMap::new();
-                        
-                                        for (price, quantity) in &trade_groups.buy_trades {
-                                            let bucket = ((price / *bucket_size).round() * *bucket_size * multiplier) as i64;
-                                            let entry = buys.entry(bucket).or_insert(0.0);
-                                            *entry += quantity;
-                                        }
-                                        for (price, quantity) in &trade_groups.sell_trades {
-                                            let bucket = ((price / *bucket_size).round() * *bucket_size * multiplier) as i64;
-                                            let entry = sells.entry(bucket).or_insert(0.0);
-                                            *entry += quantity;
-                                        }
-                        
-                                        grouped_trades.push((*open_time, GroupedTrades { buys, sells }));
-                                    }
-                                }
-                                self.canvas_main.render(y_min, y_max, &visible_klines, grouped_trades, multiplier, num_possible_lines);
-                            },
-                            Err(e) => {
-                                log(&format!("Failed to acquire lock on klines_trades during render: {}", e));
-                            }
-                        }
-                    },
-                    (Err(e), _) => {
-                        log(&format!("Failed to acquire lock on bids during render: {}", e));
-                    },
-                    (_, Err(e)) => {
-                        log(&format!("Failed to acquire lock on asks during render: {}", e));
-                    }
+                if self.oi_weighted_price {
+                    self.canvas_main.render_oi_weighted_price(y_min, y_max, last_kline_open, &visible_oi_points);
                 }
             },
-            Err(e) => {
-                log(&format!("Failed to acquire lock on klines during render: {}", e));
+            (Err(e), _) => {
+                log(&format!("Failed to acquire lock on bids during render: {}", e));
+            },
+            (_, Err(e)) => {
+                log(&format!("Failed to acquire lock on asks during render: {}", e));
             }
         }
     }
@@ -467,16 +477,47 @@ impl CanvasManager {
         let factor = if x > 0.0 { 0.9 } else { 1.1 };
         self.x_zoom *= factor;
         self.x_zoom = self.x_zoom.round(); 
-        if self.x_zoom < 3.0 {
-            self.x_zoom = 3.0;
-        }
-        if self.x_zoom > 40.0 {
-            self.x_zoom = 40.0;
-        }
+        self.x_zoom = self.x_zoom.clamp(3.0, 40.0);
         self.canvas_main.x_zoom = self.x_zoom;
         self.canvas_indi_cvd.x_zoom = self.x_zoom;
+        self.canvas_indi_funding.x_zoom = self.x_zoom;
+        self.canvas_indi_liquidations.x_zoom = self.x_zoom;
         self.canvas_indicator_volume.x_zoom = self.x_zoom;
     }
+    /// Switches the kline interval. Loaded data belongs to the previous interval and is dropped,
+    /// so streams and history have to be restarted afterwards through `initialize_ws`/`gather_klines`.
+    pub fn set_timeframe(&mut self, timeframe: &str) {
+        match timeframe.parse::<Timeframe>() {
+            Ok(timeframe) => {
+                match self.market.write() {
+                    Ok(mut market) => {
+                        if !market.set_timeframe(timeframe) {
+                            return;
+                        }
+                    },
+                    Err(e) => {
+                        log(&format!("Failed to acquire write lock on market state: {}", e));
+                        return;
+                    }
+                }
+                self.canvas_main.timeframe = timeframe;
+                self.canvas_indi_cvd.timeframe = timeframe;
+                self.canvas_indi_funding.timeframe = timeframe;
+                self.canvas_indi_liquidations.timeframe = timeframe;
+                self.canvas_indicator_volume.timeframe = timeframe;
+                self.canvas_bubble.borrow_mut().reset();
+                self.canvas_tape.borrow_mut().reset();
+                log(&format!("Setting timeframe to: {}", timeframe.as_str()));
+            },
+            Err(e) => log(&e),
+        }
+    }
+    pub fn get_timeframe_ms(&self) -> u64 {
+        match self.market.read() {
+            Ok(market) => market.timeframe().as_ms(),
+            Err(_) => Timeframe::M1.as_ms(),
+        }
+    }
     pub fn zoom_y(&mut self, y: f64) {
         self.y_zoom += y*0.02;
     }
@@ -486,93 +527,83 @@ impl CanvasManager {
         self.canvas_indicator_volume.resize(new_widths[2], new_heights[2]);
         self.canvas_bubble.borrow_mut().resize(new_widths[3], new_heights[3]);
         self.canvas_indi_cvd.resize(new_widths[4], new_heights[4]);
+        self.canvas_indi_funding.resize(new_widths[5], new_heights[5]);
+        self.canvas_indi_liquidations.resize(new_widths[6], new_heights[6]);
+        self.canvas_tape.borrow_mut().resize(new_widths[7], new_heights[7]);
     }
 
     pub fn gather_depth(&mut self, depth: JsValue) {
-        self.orderbook_manager.fetch_depth(depth);
+        if let Some(depth_str) = depth.as_string() {
+            self.record(RecordKind::Depth, &depth_str);
+            let resync_needed = match self.market.write() {
+                Ok(mut market) => market.apply_depth_snapshot(&depth_str),
+                Err(e) => {
+                    log(&format!("Failed to acquire write lock on market state: {}", e));
+                    false
+                }
+            };
+            if resync_needed {
+                call_js_callback(&self.resync_callback);
+            }
+        }
+    }
+    /// `callback` is invoked whenever the local book loses sync and a fresh depth snapshot
+    /// should be fetched and passed to `gather_depth`.
+    pub fn set_resync_callback(&mut self, callback: js_sys::Function) {
+        *self.resync_callback.borrow_mut() = Some(callback);
+    }
+    pub fn get_book_sync_state(&self) -> BookSyncState {
+        match self.market.read() {
+            Ok(market) => market.book_sync_state(),
+            Err(_) => BookSyncState::OutOfSync,
+        }
     }
     pub fn gather_oi(&mut self, oi: JsValue) {
         if let Some(oi_str) = oi.as_string() {
-            match serde_json::from_str::<serde_json::Value>(&oi_str) {
-                Ok(oi_obj) => {
-                    if let (Some(time), Some(open_interest_str)) = (oi_obj["time"].as_u64(), oi_obj["openInterest"].as_str()) {
-                        if let Ok(open_interest) = open_interest_str.parse::<f64>() {
-                            match self.oi_datapoints.try_write() {
-                                Ok(mut oi_datapoints) => oi_datapoints.push((time, open_interest)),
-                                Err(_) => println!("Failed to acquire write lock on oi_datapoints"),
-                            };
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Failed to parse oi: {}", e);
-                }
+            match self.market.try_write() {
+                Ok(mut market) => market.apply_oi(&oi_str),
+                Err(_) => log("Failed to acquire write lock on oi_datapoints"),
             }
         }
     }
     pub fn gather_hist_oi(&mut self, hist_ois: JsValue) {
         if let Some(hist_ois_str) = hist_ois.as_string() {
-            match serde_json::from_str::<Vec<serde_json::Value>>(&hist_ois_str) {
-                Ok(hist_ois) => {
-                    for hist_oi in hist_ois {
-                        if let (Some(time), Some(open_interest_str)) = (hist_oi["timestamp"].as_u64(), hist_oi["sumOpenInterest"].as_str()) {
-                            if let Ok(open_interest) = open_interest_str.parse::<f64>() {
-                                match self.oi_datapoints.try_write() {
-                                    Ok(mut oi_datapoints) => oi_datapoints.push((time, open_interest)),
-                                    Err(_) => println!("Failed to acquire write lock on oi_datapoints"),
-                                };
-                            }
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Failed to parse hist_ois: {}", e);
-                }
+            match self.market.try_write() {
+                Ok(mut market) => market.apply_hist_oi(&hist_ois_str),
+                Err(_) => log("Failed to acquire write lock on oi_datapoints"),
             }
         }
     }       
+    /// Settled funding rates, e.g. from Binance `/fapi/v1/fundingRate`.
+    pub fn gather_funding(&mut self, funding: JsValue) {
+        if let Some(funding_str) = funding.as_string() {
+            match self.market.write() {
+                Ok(mut market) => market.apply_funding(&funding_str),
+                Err(e) => log(&format!("Failed to acquire write lock on market state: {}", e)),
+            }
+        }
+    }
+    /// Current mark price and funding, e.g. from Binance `/fapi/v1/premiumIndex?symbol=`.
+    /// The `markPrice@1s` stream keeps it updated afterwards.
+    pub fn gather_mark_price(&mut self, mark_price: JsValue) {
+        if let Some(mark_price_str) = mark_price.as_string() {
+            match self.market.write() {
+                Ok(mut market) => market.apply_mark_price(&mark_price_str),
+                Err(e) => log(&format!("Failed to acquire write lock on market state: {}", e)),
+            }
+        }
+    }
     pub fn gather_klines(&mut self, klines: JsValue) {
         if let Some(klines_str) = klines.as_string() {
-            match serde_json::from_str::<Vec<Vec<serde_json::Value>>>(&klines_str) {
-                Ok(klines) => {
-                    if let Ok(mut klines_ohlcv) = self.klines_ohlcv.try_write() {
-                        let mut cum_volume_delta = 0.0;
-                        for kline in klines {
-                            if let (
-                                Some(open_time), 
-                                Some(open_str), Some(high_str), Some(low_str), Some(close_str), 
-                                Some(volume_str), Some(close_time), Some(buy_volume_str)) = 
-                                (
-                                    kline[0].as_u64(), 
-                                    kline[1].as_str(), kline[2].as_str(), kline[3].as_str(), kline[4].as_str(), 
-                                    kline[5].as_str(), kline[6].as_u64(), kline[9].as_str()
-                                ) {
-                                if let (Ok(open), Ok(high), Ok(low), Ok(close), Ok(volume), Ok(buy_volume)) = 
-                                    (
-                                        open_str.parse::<f64>(), high_str.parse::<f64>(), low_str.parse::<f64>(), close_str.parse::<f64>(), 
-                                        volume_str.parse::<f64>(), buy_volume_str.parse::<f64>()
-                                    ) {
-                                    let sell_volume = volume - buy_volume;
-                                    cum_volume_delta += buy_volume - sell_volume;
-                                    let kline = Kline {
-                                        open_time, 
-                                        open, high, low, close, 
-                                        buy_volume, sell_volume, 
-                                        cum_volume_delta,
-                                        close_time,
-                                    };
-                                    klines_ohlcv.insert(open_time, kline);
-                                }
-                            }
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Failed to parse klines: {}", e);
+            self.record(RecordKind::Klines, &klines_str);
+            if let Ok(mut market) = self.market.try_write() {
+                market.apply_klines(&klines_str);
+                if let Some(bar_replay) = self.bar_replay.as_mut() {
+                    bar_replay.refresh(&market);
                 }
             }
         }
-    }    
+    }
     pub fn gather_hist_trades(&mut self, hist_trades: JsValue, i: String) {
         let i = match i.parse::<u64>() {
             Ok(val) => val,
@@ -582,48 +613,351 @@ impl CanvasManager {
             }
         };
         if let Some(hist_trades_str) = hist_trades.as_string() {
-            match serde_json::from_str::<Vec<Trade>>(&hist_trades_str) {
-                Ok(hist_trades) => {
-                    match self.klines_trades.try_write() {
-                        Ok(mut klines_trades) => {
-                            let mut trade_groups = TradeGroups { buy_trades: Vec::new(), sell_trades: Vec::new() };
-                            for trade in hist_trades {
-                                if trade.is_buyer_maker {
-                                    trade_groups.sell_trades.push((trade.price, trade.quantity));
-                                } else {
-                                    trade_groups.buy_trades.push((trade.price, trade.quantity));
-                                }
-                            }
-                            klines_trades.insert(i, trade_groups);
-                        },
-                        Err(poisoned) => {
-                            log(&format!("klines_trades locked on render: {:?}", poisoned));
-                        }
+            self.record(RecordKind::HistTrades(i), &hist_trades_str);
+            match self.market.try_write() {
+                Ok(mut market) => {
+                    market.apply_hist_trades(&hist_trades_str, i);
+                    if let Some(bar_replay) = self.bar_replay.as_mut() {
+                        bar_replay.refresh(&market);
                     }
                 },
-                Err(e) => {
-                    log(&format!("Failed to parse hist_trades: {}", e));
+                Err(poisoned) => {
+                    log(&format!("klines_trades locked on render: {:?}", poisoned));
                 }
             }
         }
     }
 
+    /// Prices are stored in ticks of `default_tick_size`, so this has to be called before any
+    /// data for the symbol is passed in. A different tick size drops what was loaded or recorded.
     pub fn set_symbol_info(&mut self, default_tick_size: f64, _min_trade_size: f64, user_tick_setting: f64) {
-        if let Ok(mut bucket_size) = self.bucket_size.try_write() {
-            *bucket_size = default_tick_size * user_tick_setting;
-            *self.tick_size.borrow_mut() = default_tick_size;
-            log(&format!("Default bucket size: {}", *bucket_size));
+        let tick_size = match TickSize::from_f64(default_tick_size) {
+            Some(tick_size) => tick_size,
+            None => {
+                log(&format!("Invalid tick size: {}", default_tick_size));
+                return;
+            }
+        };
+        let resync_needed = match self.market.write() {
+            Ok(mut market) => {
+                let resync_needed = market.set_tick_size(tick_size);
+                if let Some(recording) = self.recorder.borrow_mut().as_mut().filter(|recording| recording.tick_size != tick_size) {
+                    // payloads are replayed in the recording's tick size, so it starts over
+                    if !recording.messages.is_empty() {
+                        log(&format!("Tick size changed to {}, dropping {} recorded messages", tick_size, recording.messages.len()));
+                        recording.messages.clear();
+                    }
+                    recording.tick_size = tick_size;
+                }
+                market.set_bucket_size(user_tick_setting.round() as i64);
+                log(&format!("Default bucket size: {} ticks of {}", market.bucket_size(), tick_size));
+                resync_needed
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire write lock on market state: {}", e));
+                false
+            }
+        };
+        if resync_needed {
+            call_js_callback(&self.resync_callback);
         }
     }
     pub fn set_tick_size(&mut self, user_tick_setting: f64) {
-        if let Ok(mut bucket_size) = self.bucket_size.try_write() {
-            *bucket_size = user_tick_setting * *self.tick_size.borrow();
-            log(&format!("Setting bucket size to: {}", *bucket_size));
+        if let Ok(mut market) = self.market.try_write() {
+            market.set_bucket_size(user_tick_setting.round() as i64);
+            log(&format!("Setting bucket size to: {} ticks", market.bucket_size()));
+            if let Some(bar_replay) = self.bar_replay.as_mut() {
+                bar_replay.refresh(&market);
+            }
+        }
+    }
+    /// Diagonal imbalance threshold as a ratio, e.g. 3.0 to highlight levels at 300%.
+    pub fn set_imbalance_ratio(&mut self, ratio: f64) {
+        if ratio > 0.0 {
+            self.footprint_config.imbalances.ratio = ratio;
+        }
+    }
+    pub fn set_stacked_imbalance_levels(&mut self, levels: usize) {
+        self.footprint_config.imbalances.stacked_levels = levels.max(2);
+    }
+    /// Imbalances detected on klines opening within `from..=to`, as a JSON array of
+    /// `{ open_time, imbalances: [{ price, side, quantity, opposite_quantity }], stacked: [{ side, low, high, levels }] }`.
+    pub fn get_imbalances(&self, from: u64, to: u64) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let tick_size = market.tick_size();
+                let klines: Vec<serde_json::Value> = market.footprints(from..=to, &self.footprint_config).iter()
+                    .map(|(open_time, footprint)| serde_json::json!({
+                        "open_time": open_time,
+                        "imbalances": footprint.imbalances.levels.iter().map(|imbalance| serde_json::json!({
+                            "price": imbalance.price.to_f64(tick_size),
+                            "side": imbalance.side.as_str(),
+                            "quantity": imbalance.quantity,
+                            "opposite_quantity": imbalance.opposite_quantity,
+                        })).collect::<Vec<_>>(),
+                        "stacked": footprint.imbalances.stacked.iter().map(|stacked| serde_json::json!({
+                            "side": stacked.side.as_str(),
+                            "low": stacked.low.to_f64(tick_size),
+                            "high": stacked.high.to_f64(tick_size),
+                            "levels": stacked.levels,
+                        })).collect::<Vec<_>>(),
+                    }))
+                    .collect();
+                serde_json::Value::Array(klines).to_string()
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_imbalances: {}", e));
+                "[]".to_string()
+            }
+        }
+    }
+    /// Share of volume in the value area as a percentage, 70 by default.
+    pub fn set_value_area_percent(&mut self, percent: f64) {
+        if percent > 0.0 && percent <= 100.0 {
+            self.footprint_config.value_area = percent / 100.0;
+        }
+    }
+    /// Range the volume profile on the right edge of the main chart covers:
+    /// "visible", "session" for the current UTC day, or "off".
+    pub fn set_volume_profile(&mut self, range: &str) {
+        match range.parse::<ProfileRange>() {
+            Ok(range) => self.profile_range = range,
+            Err(e) => log(&e),
+        }
+    }
+    /// Where the CVD candles start from zero: "daily" (00:00 UTC), "weekly" (Monday 00:00 UTC)
+    /// or "visible" for the first bar in view.
+    pub fn set_cvd_anchor(&mut self, anchor: &str) {
+        match anchor.parse::<CvdAnchor>() {
+            Ok(anchor) => self.cvd_anchor = anchor,
+            Err(e) => log(&e),
+        }
+    }
+    /// Open interest on the CVD indicator: "points", "histogram" of changes, or "off".
+    pub fn set_oi_mode(&mut self, mode: &str) {
+        match mode.parse::<OiMode>() {
+            Ok(mode) => self.canvas_indi_cvd.oi_mode = mode,
+            Err(e) => log(&e),
+        }
+    }
+    pub fn set_oi_weighted_price(&mut self, enabled: bool) {
+        self.oi_weighted_price = enabled;
+    }
+    /// Open interest of klines opening within `from..=to` as a JSON array of
+    /// `{ open_time, open_interest, change, weighted_price }`.
+    pub fn get_open_interest(&self, from: u64, to: u64) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let points: Vec<serde_json::Value> = market.open_interest_points(from..=to).iter()
+                    .map(|(open_time, point)| serde_json::json!({
+                        "open_time": open_time,
+                        "open_interest": point.open_interest,
+                        "change": point.change,
+                        "weighted_price": point.weighted_price,
+                    }))
+                    .collect();
+                serde_json::Value::Array(points).to_string()
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_open_interest: {}", e));
+                "[]".to_string()
+            }
+        }
+    }
+    /// Klines opening within `from..=to` with their volume split and CVD, as "csv" or "json".
+    pub fn export_klines(&self, from: u64, to: u64, format: &str) -> String {
+        self.export(from..=to, format, export::klines)
+    }
+    /// Buy and sell volume per price of klines opening within `from..=to`, at the current
+    /// bucket size, as "csv" or "json".
+    pub fn export_footprint(&self, from: u64, to: u64, format: &str) -> String {
+        self.export(from..=to, format, export::footprint_cells)
+    }
+    /// Open interest of klines opening within `from..=to`, as "csv" or "json".
+    pub fn export_open_interest(&self, from: u64, to: u64, format: &str) -> String {
+        self.export(from..=to, format, export::open_interest)
+    }
+    /// Best bid and ask as of the last depth update as JSON `{ time, best_bid, bid_quantity,
+    /// best_ask, ask_quantity, spread_ticks, spread_bps, mid, microprice }`, or `null` while
+    /// the book isn't synced.
+    pub fn get_top_of_book(&self) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let tick_size = market.tick_size();
+                match market.top_of_book() {
+                    Some(top_of_book) => serde_json::json!({
+                        "time": top_of_book.time,
+                        "best_bid": top_of_book.best_bid.to_f64(tick_size),
+                        "bid_quantity": top_of_book.bid_quantity,
+                        "best_ask": top_of_book.best_ask.to_f64(tick_size),
+                        "ask_quantity": top_of_book.ask_quantity,
+                        "spread_ticks": top_of_book.spread_ticks(),
+                        "spread_bps": top_of_book.spread_bps(),
+                        "mid": top_of_book.mid(tick_size),
+                        "microprice": top_of_book.microprice(tick_size),
+                    }).to_string(),
+                    None => "null".to_string(),
+                }
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_top_of_book: {}", e));
+                "null".to_string()
+            }
+        }
+    }
+    /// "levels" for a bar per price level or "cumulative" for the running depth from the best price.
+    pub fn set_depth_mode(&mut self, mode: &str) {
+        match mode.parse::<DepthMode>() {
+            Ok(mode) => self.canvas_orderbook.depth_mode = mode,
+            Err(e) => log(&e),
+        }
+    }
+    /// Bid and ask liquidity within ±0.5%, ±1% and ±2% of mid as a JSON array of
+    /// `{ percent, bids, asks, imbalance }`, imbalance being `(bids - asks) / (bids + asks)`.
+    pub fn get_book_imbalance(&self) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let orderbook = market.orderbook();
+                match (orderbook.bids.try_read(), orderbook.asks.try_read()) {
+                    (Ok(bids), Ok(asks)) => {
+                        let bands: Vec<serde_json::Value> = liquidity_bands(&bids, &asks).iter()
+                            .map(|band| serde_json::json!({
+                                "percent": band.percent,
+                                "bids": band.bids,
+                                "asks": band.asks,
+                                "imbalance": band.imbalance(),
+                            }))
+                            .collect();
+                        serde_json::Value::Array(bands).to_string()
+                    },
+                    _ => {
+                        log("Failed to acquire lock on the book during get_book_imbalance");
+                        "[]".to_string()
+                    }
+                }
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_book_imbalance: {}", e));
+                "[]".to_string()
+            }
+        }
+    }
+    /// How many minutes of book snapshots the heatmap behind the candles keeps, 0 turns it off.
+    pub fn set_heatmap_retention(&mut self, minutes: u32) {
+        match self.market.write() {
+            Ok(mut market) => market.set_heatmap_retention(minutes as u64 * MINUTE_IN_MS),
+            Err(e) => log(&format!("Failed to acquire write lock on market state: {}", e)),
+        }
+    }
+    /// Shows consecutive same-side trades within `window_ms` of each other as one print on the
+    /// tape, 0 lists every trade.
+    pub fn set_tape_aggregation(&mut self, window_ms: u32) {
+        self.canvas_tape.borrow_mut().config.aggregate_ms = window_ms as u64;
+    }
+    pub fn set_tape_min_size(&mut self, quantity: f64) {
+        self.canvas_tape.borrow_mut().config.min_size = quantity.max(0.0);
+    }
+    /// Prints from this size on are fully highlighted, 0 scales to the largest print shown.
+    pub fn set_tape_highlight_size(&mut self, quantity: f64) {
+        self.canvas_tape.borrow_mut().config.highlight_size = quantity.max(0.0);
+    }
+    /// Marks trades of at least `quantity` on the chart, 0 turns the markers off.
+    pub fn set_large_trade_size(&mut self, quantity: f64) {
+        self.large_trade_threshold = if quantity > 0.0 {
+            LargeTradeThreshold::Absolute(quantity)
+        } else {
+            LargeTradeThreshold::Off
+        };
+    }
+    /// Marks trades above `percentile` (0-100) of recent trade sizes, e.g. 99.5 for the top 0.5%.
+    pub fn set_large_trade_percentile(&mut self, percentile: f64) {
+        self.large_trade_threshold = LargeTradeThreshold::Percentile(percentile.clamp(0.0, 100.0));
+    }
+    /// Minimum quantity a liquidation needs to be drawn and counted, in base asset.
+    pub fn set_min_liquidation_size(&mut self, quantity: f64) {
+        self.min_liquidation_size = quantity.max(0.0);
+    }
+    /// Liquidated quantity per side of klines opening within `from..=to`, as a JSON array of
+    /// `{ open_time, long, short }`.
+    pub fn get_liquidations(&self, from: u64, to: u64) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let totals: Vec<serde_json::Value> = market.liquidation_totals(from..=to, self.min_liquidation_size).iter()
+                    .map(|(open_time, totals)| serde_json::json!({
+                        "open_time": open_time,
+                        "long": totals.long,
+                        "short": totals.short,
+                    }))
+                    .collect();
+                serde_json::Value::Array(totals).to_string()
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_liquidations: {}", e));
+                "[]".to_string()
+            }
+        }
+    }
+    /// Comma separated rows of the statistics table under the footprint, out of
+    /// "delta", "delta_percent", "volume", "trades", "max_delta" and "min_delta". Empty hides it.
+    pub fn set_footprint_stats(&mut self, rows: &str) {
+        let rows: Result<Vec<StatsRow>, String> = rows.split(',')
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .map(str::parse)
+            .collect();
+        match rows {
+            Ok(rows) => self.canvas_main.stats_rows = rows,
+            Err(e) => log(&e),
+        }
+    }
+    /// Intra-bar delta of klines opening within `from..=to`, tracked trade by trade, as a JSON
+    /// array of `{ open_time, delta, max_delta, min_delta }`.
+    pub fn get_intrabar_delta(&self, from: u64, to: u64) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let deltas: Vec<serde_json::Value> = market.intrabar_deltas(from..=to).iter()
+                    .map(|(open_time, delta)| serde_json::json!({
+                        "open_time": open_time,
+                        "delta": delta.close,
+                        "max_delta": delta.max,
+                        "min_delta": delta.min,
+                    }))
+                    .collect();
+                serde_json::Value::Array(deltas).to_string()
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_intrabar_delta: {}", e));
+                "[]".to_string()
+            }
+        }
+    }
+    /// POC and value area of the kline opening at `open_time` as JSON
+    /// `{ open_time, poc, value_area_high, value_area_low, buy_volume, sell_volume }`, or `null`.
+    pub fn get_candle_profile(&self, open_time: u64) -> String {
+        match self.market.try_read() {
+            Ok(market) => {
+                let tick_size = market.tick_size();
+                match market.footprint_at(open_time, &self.footprint_config).and_then(|footprint| footprint.profile) {
+                    Some(profile) => serde_json::json!({
+                        "open_time": open_time,
+                        "poc": profile.poc.to_f64(tick_size),
+                        "value_area_high": profile.value_area_high.to_f64(tick_size),
+                        "value_area_low": profile.value_area_low.to_f64(tick_size),
+                        "buy_volume": profile.buy_volume,
+                        "sell_volume": profile.sell_volume,
+                    }).to_string(),
+                    None => "null".to_string(),
+                }
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during get_candle_profile: {}", e));
+                "null".to_string()
+            }
         }
     }
     pub fn get_kline_ohlcv_keys(&self) -> Vec<u64> {
-        match self.klines_ohlcv.try_read() {
-            Ok(klines_borrowed) => klines_borrowed.keys().cloned().collect(),
+        match self.market.try_read() {
+            Ok(market) => market.klines().keys().cloned().collect(),
             Err(e) => {
                 log(&format!("Failed to acquire lock on klines_ohlcv during get_kline_ohlcv_keys: {}", e));
                 Vec::new()
@@ -641,665 +975,298 @@ impl CanvasManager {
     }
 
     pub fn clear_datasets(&mut self) {
-        try_clear!(self.oi_datapoints, "oi_datapoints");
-        try_clear!(self.klines_ohlcv, "klines_ohlcv");
-        try_clear!(self.klines_trades, "klines_trades");
+        match self.market.try_write() {
+            Ok(mut market) => market.clear(),
+            Err(_) => log("Failed to acquire write lock on market state during clear_datasets"),
+        }
         self.canvas_bubble.borrow_mut().reset();
+        self.canvas_tape.borrow_mut().reset();
     }
-}
-pub struct CanvasOrderbook {
-    ctx: CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    dpi: f64,
-}
-impl CanvasOrderbook {
-    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, JsValue> {
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
 
-        match canvas.get_context("2d") {
-            Ok(Some(context)) => {
-                let ctx = context.dyn_into::<CanvasRenderingContext2d>()?;
-                let dpi = window().unwrap().device_pixel_ratio();
-                Ok(Self {
-                    ctx,
-                    width,
-                    height,
-                    dpi
-                })
-            },
-            Ok(None) => Err(JsValue::from_str("No 2D context available")),
-            Err(error) => Err(error),
+    /// Starts capturing stream messages and the REST data passed to `gather_depth`,
+    /// `gather_klines` and `gather_hist_trades`. Call it before fetching a symbol's data so
+    /// the recording can be replayed on its own.
+    pub fn start_recording(&mut self, symbol: &str) {
+        let recording = match self.market.read() {
+            Ok(market) => Recording::new(symbol, market.timeframe(), market.tick_size()),
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state: {}", e));
+                return;
+            }
+        };
+        *self.recorder.borrow_mut() = Some(recording);
+    }
+    /// Stops capturing and returns the recording in its file format, empty if none was running.
+    pub fn stop_recording(&mut self) -> String {
+        match self.recorder.borrow_mut().take() {
+            Some(recording) => recording.serialize(),
+            None => String::new(),
         }
     }
-    pub fn resize(&mut self, new_width: f64, new_height: f64) {
-        self.width = new_width;
-        self.height = new_height;
-        self.dpi = window().unwrap().device_pixel_ratio();
+    pub fn is_recording(&self) -> bool {
+        self.recorder.borrow().is_some()
     }
-    
-    pub fn render(&mut self, y_min: f64, y_max: f64, bids: Vec<Order>, asks: Vec<Order>, klines: &Vec<(&u64, &Kline)>, last_depth_update: &Rc<RefCell<u64>>, decimals: i32, num_possible_lines: f64) {
-        let context = &self.ctx;
-        self.ctx.clear_rect(0.0, 0.0, self.width, self.height);
-
-        let max_quantity = {
-            let max_bid_quantity = bids.iter().fold(0.0, |max, x| x.quantity.max(max));
-            let max_ask_quantity = asks.iter().fold(0.0, |max, x| x.quantity.max(max));
-            max_bid_quantity.max(max_ask_quantity)
-        }; 
-
-        let num_labels = 12; 
-        let step = (y_max - y_min) / num_labels as f64;
-
-        let font_size = (12.0 * self.dpi as f64).round();
-        context.set_font(&format!("{}px monospace", font_size));
-        context.set_fill_style(&"rgba(200, 200, 200, 0.8)".into());
-
-        let max_quantity_str = format!("{:.1}", max_quantity);
-        let text_metrics = context.measure_text(&max_quantity_str).unwrap();
-        context.fill_text(&max_quantity_str, self.width - text_metrics.width() - 6.0, 20.0).unwrap();
-        
-        for i in 0..=num_labels {
-            let y_value = y_min + step * i as f64;
-            let y = self.height - ((y_value - y_min) / (y_max - y_min)) * self.height as f64;
-
-            let y_value_str = format!("{:.*}", decimals as usize, y_value);
-
-            context.fill_text(&y_value_str, 2.0*self.dpi, y).unwrap();
-        }
-
-        let height_per_line = (self.height / num_possible_lines).round();
 
-        if let Some(_best_bid) = bids.first() {     
-            context.set_fill_style(&"rgba(81, 205, 160, 1)".into());
-            for (_i, bid) in bids.iter().enumerate() {
-                let x = (bid.quantity / (max_quantity + max_quantity/4.0)) * (self.width - (self.width/12.0)) as f64;
-                let y = ((bid.price - y_min) / (y_max - y_min)) * self.height as f64;
-                let y_top = self.height - y - height_per_line / 2.0;
-                context.fill_rect(self.dpi*60.0, y_top, x, height_per_line);
+    /// Closes the live stream, drops loaded data and plays back a recording from
+    /// `stop_recording` as `render_start` is called. Returns false if it couldn't be read.
+    pub fn load_replay(&mut self, data: &str) -> bool {
+        let recording = match Recording::parse(data) {
+            Ok(recording) => recording,
+            Err(e) => {
+                log(&e);
+                return false;
             }
+        };
+        self.stream_generation.set(self.stream_generation.get().wrapping_add(1));
+        let previous = self.websocket.borrow_mut().take();
+        if let Some(ws) = previous {
+            connection::close(&ws);
         }
-        if let Some(_best_ask) = asks.first() {
-            context.set_fill_style(&"rgba(192, 80, 77, 1)".into());
-            for (_i, ask) in asks.iter().enumerate() {
-                let x = (ask.quantity / (max_quantity + max_quantity/4.0)) * (self.width - (self.width/12.0)) as f64;
-                let y = ((ask.price - y_min) / (y_max - y_min)) * self.height as f64;
-                let y_top = self.height - y - height_per_line / 2.0;
-                context.fill_rect(self.dpi*60.0, y_top, x, height_per_line);
+        self.stream_session = None;
+        self.connection_state.set(ConnectionState::Disconnected);
+
+        self.bar_replay = None;
+        self.set_timeframe(recording.timeframe.as_str());
+        self.clear_datasets();
+        match self.market.write() {
+            Ok(mut market) => {
+                market.set_tick_size(recording.tick_size);
+            },
+            Err(e) => {
+                log(&format!("Failed to acquire write lock on market state: {}", e));
+                return false;
             }
-
         }
-        klines.last().map(|(_last_time, kline)| {
-            context.set_font(&format!("{}px monospace", font_size));
-            let y = ((kline.close - y_min) / (y_max - y_min)) * self.height as f64;
-            let y_value_str = format!("{:.*}", decimals as usize, kline.close);
-
-            if kline.open < kline.close {
-                context.set_fill_style(&"rgba(81, 205, 160, 1)".into());
-            } else {
-                context.set_fill_style(&"rgba(192, 80, 77, 1)".into());
-            }
-            let rect_y = self.height - y - 15.0*self.dpi;
-            context.fill_rect(1.5*self.dpi, rect_y, 55.0*self.dpi, 30.0*self.dpi); 
-
-            context.set_fill_style(&"black".into());
-            context.fill_text(&y_value_str, 3.0*self.dpi, self.height - y).unwrap();
-
-            let time_left = if kline.close_time < *last_depth_update.borrow() {
-                0
-            } else {
-                (kline.close_time - *last_depth_update.borrow()) / 1000
-            };
-            let time_left_str = format!("{:02}:{:02}", time_left / 60, time_left % 60);
-            context.set_font(&format!("{}px monospace", (font_size/1.4).round()));
-            context.fill_text(&time_left_str, 3.0*self.dpi, self.height - y + (12.0*self.dpi)).unwrap(); 
-        });
-    } 
-}
-pub struct CanvasMain {
-    ctx: CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    dpi: f64,
-    x_zoom: f64,
-}
-impl CanvasMain {
-    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, JsValue> {
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
-
-        match canvas.get_context("2d") {
-            Ok(Some(context)) => {
-                let ctx = context.dyn_into::<CanvasRenderingContext2d>()?;
-                let dpi = window().unwrap().device_pixel_ratio();
-                Ok(Self {
-                    ctx,
-                    width,
-                    height,
-                    dpi,
-                    x_zoom: 30.0,
-                })
+        self.data_source = DataSource::Replay;
+        log(&format!("Replaying {} messages of {} over {} ms", recording.messages.len(), recording.symbol, recording.duration()));
+        self.replay = Some(Replay::new(recording));
+        true
+    }
+    /// "1x", "5x" or "max".
+    pub fn set_replay_speed(&mut self, speed: &str) {
+        match speed.parse::<ReplaySpeed>() {
+            Ok(speed) => {
+                if let Some(replay) = self.replay.as_mut() {
+                    replay.set_speed(speed);
+                }
             },
-            Ok(None) => Err(JsValue::from_str("No 2D context available")),
-            Err(error) => Err(error),
+            Err(e) => log(&e),
         }
     }
-    pub fn resize(&mut self, new_width: f64, new_height: f64) {
-        self.width = new_width;
-        self.height = new_height;
-        self.dpi = window().unwrap().device_pixel_ratio();
+    pub fn pause_replay(&mut self) {
+        if let Some(replay) = self.replay.as_mut() {
+            replay.set_paused(true);
+        }
     }
-
-    pub fn render(&mut self, y_min: f64, y_max: f64, klines: &Vec<(&u64, &Kline)>, trades: Vec<(u64, GroupedTrades)>, multiplier: f64, num_possible_lines: f64) {
-        let context = &self.ctx;
-        context.clear_rect(0.0, 0.0, self.width, self.height);
-        
-        if let Some((last_kline_open, _)) = klines.iter().last() {
-            let zoom_scale = self.x_zoom * MINUTE_IN_MS as f64;
-            let time_difference: f64 = **last_kline_open as f64 + MINUTE_IN_MS as f64 - zoom_scale;
-            let rect_width: f64 = (self.width as f64 / &self.x_zoom)/2.0;
-
-            let max_quantity = trades.iter().flat_map(|(_, trade_groups)| {
-                trade_groups.buys.iter().chain(trade_groups.sells.iter()).map(|(_, quantity)| *quantity)
-            }).fold(0.0, f64::max);
-
-            let height_per_line = (self.height / num_possible_lines).round();
-            let font_size = (height_per_line/2.6).round();
-    
-            for (_i, (_, kline)) in klines.iter().enumerate() {
-                let x: f64 = ((kline.open_time as f64 - time_difference) as f64 / zoom_scale) * self.width;
-
-                let y_open = self.height as f64 * (kline.open - y_min) / (y_max - y_min);
-                let y_close = self.height as f64 * (kline.close - y_min) / (y_max - y_min);
-                let y_high = self.height as f64 * (kline.high - y_min) / (y_max - y_min);
-                let y_low = self.height as f64 * (kline.low - y_min) / (y_max - y_min);
-
-                context.set_font(&format!("{}px monospace", font_size));
-                if let Some((_, trade_groups)) = trades.iter().find(|&&(time, _)| time == kline.open_time) {
-                    context.set_fill_style(&"rgba(81, 205, 160, 1)".into());
-                    let mut texts = Vec::new(); 
-
-                    for (price_as_int, quantity) in &trade_groups.buys { 
-                        let price = *price_as_int as f64 / multiplier;
-                        let y_trade = self.height as f64 * (price - y_min) / (y_max - y_min);
-                        let scaled_quantity = rect_width as f64 * quantity / max_quantity;
-
-                        let y_top = self.height - y_trade - height_per_line / 2.0;
-
-                        context.fill_rect(x + rect_width + 4.0, y_top, scaled_quantity, height_per_line);
-                        
-                        if font_size > (6.0*self.dpi)&& rect_width > 60.0 {
-                            let quantity_str = format!("{:.3}", quantity);
-                            texts.push((quantity_str, x + rect_width + 6.0, (y_top + height_per_line / 2.0 + font_size / 3.0))); 
-                        }
-                    }
-                    context.set_fill_style(&"white".into());
-                    for (quantity_str, x, y) in texts.drain(..) {
-                        context.fill_text(&quantity_str, x, y).unwrap();
-                    }
-
-                    context.set_fill_style(&"rgba(192, 80, 77, 1)".into());
-                    for (price_as_int, quantity) in &trade_groups.sells {
-                        let price = *price_as_int as f64 / multiplier;
-                        let y_trade = self.height as f64 * (price - y_min) / (y_max - y_min);
-                        let scaled_quantity = rect_width as f64 * quantity / max_quantity;
-                    
-                        let y_top = self.height - y_trade - height_per_line / 2.0;
-
-                        context.fill_rect(x + rect_width - 4.0, y_top, -scaled_quantity, height_per_line);
-                        
-                        if font_size > (6.0*self.dpi)&& rect_width > 60.0 {
-                            let quantity_str = format!("{:.3}", quantity);
-                            let text_metrics = context.measure_text(&quantity_str).unwrap();
-                            texts.push((quantity_str, x + rect_width - 6.0 - text_metrics.width(), (y_top + height_per_line / 2.0 + font_size / 3.0))); 
-                        }
-                    }
-                    context.set_fill_style(&"white".into());
-                    for (quantity_str, x, y) in texts {
-                        context.fill_text(&quantity_str, x, y).unwrap();
-                    }
-                } else {
-                    context.set_stroke_style(&"rgba(200, 200, 200, 0.5)".into());
-                    context.begin_path();
-                    context.move_to(x, self.height - y_high);
-                    context.line_to(x + (rect_width*2.0), self.height - y_high);
-
-                    context.move_to(x, self.height - y_low);
-                    context.line_to(x + (rect_width*2.0), self.height - y_low);
-                    context.stroke();
-                }
-                context.set_stroke_style(&(if kline.open < kline.close { "rgba(50, 200, 50, 1)" } else { "rgba(200, 50, 50, 1)" }).into());
-                context.set_line_width(rect_width/44.0);
-                context.begin_path();
-                context.move_to(x + rect_width, self.height - y_open);
-                context.line_to(x + rect_width, self.height - y_close);
-                context.stroke();    
-
-                // time labels from kline.open_time
-                let text_height = 20.0 + 1.0 * 1.0; // font size + padding + margin
-                if kline.open_time % (MINUTE_IN_MS as u64) == 0 {
-                    context.set_font(&format!("{}px monospace", 12.0*self.dpi));
-                    context.set_fill_style(&"rgba(200, 200, 200, 0.8)".into());
-                    let hour = (kline.open_time / 3600000) % 24;
-                    let minute = (kline.open_time / 60000) % 60;
-                    let text_metrics = context.measure_text(&format!("{:02}:{:02}", hour, minute)).unwrap();
-                    context.fill_text(&format!("{:02}:{:02}", hour, minute), x + rect_width - text_metrics.width() / 2.0, self.height - ((text_height/2.0)*self.dpi)).unwrap();              
-                }        
-            }
+    pub fn resume_replay(&mut self) {
+        if let Some(replay) = self.replay.as_mut() {
+            replay.set_paused(false);
         }
     }
-}
-pub struct CanvasIndicatorVolume {
-    ctx: CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    dpi: f64,
-    x_zoom: f64,
-}
-impl CanvasIndicatorVolume {
-    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, JsValue> {
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
-
-        match canvas.get_context("2d") {
-            Ok(Some(context)) => {
-                let ctx = context.dyn_into::<CanvasRenderingContext2d>()?;
-                let dpi = window().unwrap().device_pixel_ratio();
-                Ok(Self {
-                    ctx,
-                    width,
-                    height,
-                    dpi,
-                    x_zoom: 30.0,
-                })
-            },
-            Ok(None) => Err(JsValue::from_str("No 2D context available")),
-            Err(error) => Err(error),
+    /// Jumps to `offset_ms` from the start of the recording. Seeking back replays it from
+    /// the start up to that point.
+    pub fn seek_replay(&mut self, offset_ms: f64) {
+        let mut replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => return,
+        };
+        let (rewind, messages) = replay.seek(offset_ms.max(0.0) as u64);
+        if rewind {
+            self.clear_datasets();
         }
+        apply_recorded(&self.market, &self.canvas_bubble, &self.canvas_tape, messages);
+        self.replay = Some(replay);
     }
-    pub fn resize(&mut self, new_width: f64, new_height: f64) {
-        self.width = new_width;
-        self.height = new_height;
-        self.dpi = window().unwrap().device_pixel_ratio();
+    /// Position of the replay in ms from the start of the recording, -1 if none is loaded.
+    pub fn get_replay_position(&self) -> f64 {
+        self.replay.as_ref().map_or(-1.0, |replay| replay.position() as f64)
     }
-
-    pub fn render(&mut self, klines: &Vec<(&u64, &Kline)>) {
-        let context = &self.ctx;
-        context.clear_rect(0.0, 0.0, self.width, self.height);
-        
-        let zoom_scale = self.x_zoom * MINUTE_IN_MS as f64;
-        let rect_width: f64 = (self.width as f64 / &self.x_zoom)/2.0;
-
-        match klines.iter().last() {
-            Some((last_kline_open, _)) => {
-                let max_volume = klines.iter().map(|(_, kline)| f64::max(kline.buy_volume, kline.sell_volume)).fold(0.0, f64::max);
-                let time_difference = **last_kline_open as f64 + MINUTE_IN_MS as f64 - zoom_scale;
-
-                let font_size = (12.0 * self.dpi as f64).round();
-                context.set_font(&format!("{}px monospace", font_size));
-                for (_i, (_, kline)) in klines.iter().enumerate() {
-                    let x = ((kline.open_time as f64 - time_difference) as f64 / zoom_scale) * self.width;
-                
-                    let buy_height = self.height as f64 * (kline.buy_volume / max_volume);
-                    let sell_height = self.height as f64 * (kline.sell_volume / max_volume);
-                
-                    context.set_fill_style(&"rgba(81, 205, 160, 1)".into());
-                    context.fill_rect(x + rect_width, self.height as f64 - buy_height, rect_width - 10.0, buy_height);
-                
-                    context.set_fill_style(&"rgba(192, 80, 77, 1)".into());
-                    context.fill_rect(x + 10.0, self.height as f64 - sell_height, rect_width - 10.0, sell_height);
-
-                    if self.x_zoom < 18.0 && rect_width > 60.0{
-                        let text_height = font_size + 2.0 * 2.0; // font size + padding + margin            
-                        context.set_fill_style(&"black".into());
-                
-                        if buy_height > text_height {
-                            context.fill_text(&format!("{:.2}", kline.buy_volume), x + rect_width + 6.0, self.height as f64 - buy_height + text_height).unwrap();
-                        }
-                        if sell_height > text_height {
-                            context.fill_text(&format!("{:.2}", kline.sell_volume), x + 14.0, self.height as f64 - sell_height + text_height).unwrap();
-                        }
-                    }
-                }
-            },
-            None => {
-                log(&format!("No klines"));
-            }
-        }
+    pub fn get_replay_duration(&self) -> f64 {
+        self.replay.as_ref().map_or(0.0, |replay| replay.recording.duration() as f64)
+    }
+    /// Leaves replay mode, keeping what was played so far on screen. Restart the stream with
+    /// `initialize_ws` to go back to live data.
+    pub fn stop_replay(&mut self) {
+        self.replay = None;
     }
-}
-pub struct CanvasIndiCVD {
-    ctx: CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    dpi: f64,
-    x_zoom: f64,
-}
-impl CanvasIndiCVD {
-    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, JsValue> {
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
 
-        match canvas.get_context("2d") {
-            Ok(Some(context)) => {
-                let ctx = context.dyn_into::<CanvasRenderingContext2d>()?;
-                let dpi = window().unwrap().device_pixel_ratio();
-                Ok(Self {
-                    ctx,
-                    width,
-                    height,
-                    dpi,
-                    x_zoom: 30.0,
-                })
-            },
-            Ok(None) => Err(JsValue::from_str("No 2D context available")),
-            Err(error) => Err(error),
+    /// Closes the live stream and only shows data up to `time`, which can then be stepped
+    /// forward with `step_bar_replay`. History from `gather_klines`/`gather_hist_trades` has
+    /// to be loaded first; the book panel shows the last heatmap snapshot before the cursor.
+    pub fn start_bar_replay(&mut self, time: f64) {
+        self.stream_generation.set(self.stream_generation.get().wrapping_add(1));
+        let previous = self.websocket.borrow_mut().take();
+        if let Some(ws) = previous {
+            connection::close(&ws);
         }
+        self.stream_session = None;
+        self.connection_state.set(ConnectionState::Disconnected);
+        self.replay = None;
+        // trades missed while the stream is closed leave a gap
+        self.data_source = DataSource::Replay;
+
+        match self.market.read() {
+            Ok(market) => self.bar_replay = Some(BarReplay::new(&market, time.max(0.0) as u64)),
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state: {}", e));
+                return;
+            }
+        }
+        self.canvas_bubble.borrow_mut().reset();
+        self.fill_bar_replay_tape();
     }
-    pub fn resize(&mut self, new_width: f64, new_height: f64) {
-        self.width = new_width;
-        self.height = new_height;
-        self.dpi = window().unwrap().device_pixel_ratio();
-    }
-
-    pub fn render(&mut self, klines: &Vec<(&u64, &Kline)>, oi_obj: &Vec<&(u64, f64)>) {
-        let context = &self.ctx;
-        context.clear_rect(0.0, 0.0, self.width, self.height);
-    
-        let zoom_scale = self.x_zoom * MINUTE_IN_MS as f64;
-        
-        match klines.iter().last() {
-            Some((last_kline_open, _)) => {
-                let max_cvd = klines.iter().map(|(_, kline)| kline.cum_volume_delta).fold(0.0, f64::max);
-                let min_cvd = klines.iter().map(|(_, kline)| kline.cum_volume_delta).fold(f64::MAX, f64::min);
-
-                let time_difference = *last_kline_open + MINUTE_IN_MS - zoom_scale as u64;
-                let rect_width: f64 = (self.width as f64 / &self.x_zoom)/2.0;
-
-                let padding_ratio = 0.1; 
-                let padded_height = self.height as f64 * (1.0 - padding_ratio);
-                let padding = self.height as f64 * padding_ratio / 2.0;
-
-                let mut previous_point: Option<(f64, f64)> = None;
-                context.set_stroke_style(&"rgba(238, 216, 139, 0.4)".into());
-                for (_i, (_, kline)) in klines.iter().enumerate() {
-                    let x = ((kline.open_time - time_difference) as f64 / zoom_scale) * self.width;
-                    let y = if max_cvd == min_cvd {
-                        padded_height / 2.0 + padding
-                    } else {
-                        padded_height * (kline.cum_volume_delta - min_cvd) / (max_cvd - min_cvd) + padding
-                    };
-            
-                    match previous_point {
-                        Some((prev_x, prev_y)) => {
-                            context.begin_path();
-                            context.move_to(prev_x + (rect_width*2.0), self.height - prev_y);
-                            context.line_to(x + (rect_width*2.0), self.height - y);
-                            context.stroke();
-                        },
-                        None => (),
-                    }
-                    previous_point = Some((x, y));
-                }
-
-                match oi_obj.iter().last() {
-                    Some((_oi_last_time, _)) => {
-                        let max_oi = oi_obj.iter().map(|(_, oi)| *oi).fold(0.0, f64::max);
-                        let min_oi = oi_obj.iter().map(|(_, oi)| *oi).fold(f64::MAX, f64::min);
-
-                        let time_difference = *last_kline_open + 60000 - zoom_scale as u64;
-                        let padding_ratio = 0.1; 
-                        let padded_height = self.height as f64 * (1.0 - padding_ratio);
-                        let padding = self.height as f64 * padding_ratio / 2.0;
-
-                        context.set_fill_style(&"white".into());
-                        let font_size = (12.0 * self.dpi as f64).round();
-                        context.set_font(&format!("{}px monospace", font_size));
-
-                        let mut previous_oi = None;
-
-                        for (_i, (time, oi)) in oi_obj.iter().enumerate() {
-                            let x = ((time - time_difference) as f64 / zoom_scale) * self.width;
-                            let y = if max_oi == min_oi {
-                                padded_height / 2.0 + padding
-                            } else {
-                                padded_height * (*oi - min_oi) / (max_oi - min_oi) + padding
-                            };
-                            context.begin_path();
-                            context.arc(x, self.height - y, 2.0*self.dpi, 0.0, 2.0 * std::f64::consts::PI).unwrap();
-                            context.fill();
-
-                            if let Some(prev_oi) = previous_oi {
-                                if self.x_zoom < 18.0 {
-                                    let diff = (*oi as f64 - prev_oi as f64).round();
-                                    context.set_fill_style(&"rgba(200, 200, 200, 0.8".into());
-                                    let measured_text = context.measure_text(&format!("{:+}", diff)).unwrap();
-                                    context.fill_text(&format!("{:+}", diff), x - measured_text.width() - 12.0, self.height - y + (3.0*self.dpi)).unwrap();
-                                    context.set_fill_style(&"white".into());
-                                }
-                            }
-                            previous_oi = Some(*oi);
-                        }
-                    },
-                    None => {
-                    }
-                }
-            },
-            None => {
-                log(&format!("No klines"));
+    pub fn set_bar_replay_cursor(&mut self, time: f64) {
+        if let Some(bar_replay) = self.bar_replay.as_mut() {
+            match self.market.read() {
+                Ok(market) => bar_replay.seek(&market, time.max(0.0) as u64),
+                Err(e) => log(&format!("Failed to acquire lock on market state: {}", e)),
             }
+            self.fill_bar_replay_tape();
         }
     }
-}
-pub struct CanvasBubbleTrades {
-    ctx: CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    dpi: f64,
-    trades: Vec<Trade>,
-    sell_trade_counts: BTreeMap<u64, usize>,
-    buy_trade_counts: BTreeMap<u64, usize>,
-}
-impl CanvasBubbleTrades {
-    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, JsValue> {
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
-
-        match canvas.get_context("2d") {
-            Ok(Some(context)) => {
-                let ctx = context.dyn_into::<CanvasRenderingContext2d>()?;
-                let dpi = window().unwrap().device_pixel_ratio();
-                Ok(Self {
-                    ctx,
-                    width,
-                    height,
-                    dpi,
-                    trades: Vec::new(),
-                    sell_trade_counts: BTreeMap::new(),
-                    buy_trade_counts: BTreeMap::new(),
-                })
-            },
-            Ok(None) => Err(JsValue::from_str("No 2D context available")),
-            Err(error) => Err(error),
+    /// "kline" or "trade". Returns false at the end of the loaded data.
+    pub fn step_bar_replay(&mut self, step: &str) -> bool {
+        let step = match step.parse::<BarStep>() {
+            Ok(step) => step,
+            Err(e) => {
+                log(&e);
+                return false;
+            }
+        };
+        let stepped = match (self.bar_replay.as_mut(), self.market.read()) {
+            (Some(bar_replay), Ok(market)) => bar_replay.step(&market, step),
+            (None, _) => false,
+            (_, Err(e)) => {
+                log(&format!("Failed to acquire lock on market state: {}", e));
+                false
+            }
+        };
+        if stepped {
+            self.fill_bar_replay_tape();
         }
+        stepped
     }
-    pub fn resize(&mut self, new_width: f64, new_height: f64) {
-        self.width = new_width;
-        self.height = new_height;
-        self.dpi = window().unwrap().device_pixel_ratio();
+    /// Cursor time in ms, -1 outside of bar replay.
+    pub fn get_bar_replay_cursor(&self) -> f64 {
+        self.bar_replay.as_ref().map_or(-1.0, |bar_replay| bar_replay.cursor() as f64)
     }
-    pub fn reset(&mut self) {
-        self.trades.clear();
-        self.sell_trade_counts.clear();
-        self.buy_trade_counts.clear();
+    /// Shows all loaded data again. Restarting the stream with `initialize_ws` to go back to live
+    /// data drops it, so the REST history fetched afterwards isn't merged with the gap.
+    pub fn stop_bar_replay(&mut self) {
+        self.bar_replay = None;
+        self.canvas_tape.borrow_mut().reset();
     }
 
-    pub fn render(&mut self, trades_buffer: &Vec<Trade>, last_update: u64) {
-        let context = &self.ctx;
-        context.clear_rect(0.0, 0.0, self.width, self.height);
-
-        let padding_percentage = 0.25; 
-        let padded_height = self.height * (1.0 - padding_percentage);
-        let thirty_seconds_ago = last_update - 30 * 1000;
-
-        // Trade counts
-        self.buy_trade_counts.retain(|&time, _| time >= thirty_seconds_ago);
-        self.sell_trade_counts.retain(|&time, _| time >= thirty_seconds_ago);
-
-        let sell_count = trades_buffer.iter()
-            .filter(|trade| trade.is_buyer_maker)
-            .count();
-        self.sell_trade_counts.insert(last_update, sell_count);
-        let buy_count = trades_buffer.iter()
-            .filter(|trade| !trade.is_buyer_maker)
-            .count();
-        self.buy_trade_counts.insert(last_update, buy_count);
-
-        let max_trade_count = self.sell_trade_counts.iter().chain(self.buy_trade_counts.iter())
-            .map(|(_, &count)| count)
-            .fold(0, usize::max);
-        let y_scale = padded_height / (2 * max_trade_count) as f64;
+    fn fill_bar_replay_tape(&self) {
+        let bar_replay = match &self.bar_replay {
+            Some(bar_replay) => bar_replay,
+            None => return,
+        };
+        let mut trades: Vec<Trade> = bar_replay.view().footprint().values().rev()
+            .flat_map(|trade_groups| trade_groups.trades.iter().rev())
+            .take(MAX_TAPE_TRADES)
+            .copied()
+            .collect();
+        trades.reverse();
+        let mut canvas_tape = self.canvas_tape.borrow_mut();
+        canvas_tape.reset();
+        canvas_tape.push(&trades);
+    }
 
-        context.set_stroke_style(&"rgba(200, 50, 50, 0.4)".into());
-        let mut previous_point: Option<(f64, f64)> = None;
-        for (&time, &count) in self.sell_trade_counts.iter() {
-            let x = ((time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
-            let y = self.height * padding_percentage / 2.0 + padded_height / 2.0 + count as f64 * y_scale;
-            match previous_point {
-                Some((prev_x, prev_y)) => {
-                    context.begin_path();
-                    context.move_to(prev_x, prev_y);
-                    context.line_to(x, y);
-                    context.stroke();
-                },
-                None => (),
+    fn export(&self, open_times: RangeInclusive<u64>, format: &str, export: fn(&MarketState, RangeInclusive<u64>, ExportFormat) -> String) -> String {
+        let format = match format.parse::<ExportFormat>() {
+            Ok(format) => format,
+            Err(e) => {
+                log(&e);
+                return String::new();
             }
-            previous_point = Some((x, y));
-        }
-        context.set_stroke_style(&"rgba(50, 200, 50, 0.4)".into());
-        previous_point = None;
-        for (&time, &count) in self.buy_trade_counts.iter() {
-            let x = ((time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
-            let y = self.height * padding_percentage / 2.0 + padded_height / 2.0 - count as f64 * y_scale;
-            match previous_point {
-                Some((prev_x, prev_y)) => {
-                    context.begin_path();
-                    context.move_to(prev_x, prev_y);
-                    context.line_to(x, y);
-                    context.stroke();
-                },
-                None => (),
+        };
+        match self.market.try_read() {
+            Ok(market) => export(&market, open_times, format),
+            Err(e) => {
+                log(&format!("Failed to acquire lock on market state during export: {}", e));
+                String::new()
             }
-            previous_point = Some((x, y));
         }
+    }
 
-        // Bubble trades
-        self.trades.retain(|trade| trade.time >= thirty_seconds_ago);
-
-        for trade in trades_buffer {
-            self.trades.push(*trade);
+    fn record(&self, kind: RecordKind, data: &str) {
+        if let Some(recording) = self.recorder.borrow_mut().as_mut() {
+            recording.push(now_ms(), kind, data);
         }
-        
-        let max_quantity = self.trades.iter().map(|trade| trade.quantity).fold(0.0, f64::max);
-        let max_radius = 18.0*self.dpi;
-        let y_min = self.trades.iter().map(|trade| trade.price).fold(f64::MAX, f64::min);
-        let y_max = self.trades.iter().map(|trade| trade.price).fold(0.0, f64::max);
-
-        let sell_trades: Vec<_> = self.trades.iter().filter(|trade| trade.is_buyer_maker).collect();
-        let buy_trades: Vec<_> = self.trades.iter().filter(|trade| !trade.is_buyer_maker).collect();
-
-        context.set_fill_style(&"rgba(192, 80, 77, 1)".into());
-        for trade in &sell_trades {
-            let radius = ((trade.quantity / max_quantity) * 20.0 * self.dpi).min(max_radius);    
-            if radius > 1.0 {
-                let x = ((trade.time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
-                let y = ((trade.price - y_min) / (y_max - y_min)) * padded_height + self.height * padding_percentage / 2.0;
+    }
+}
 
-                context.begin_path();
-                context.arc(x, self.height - y, radius, 0.0, 2.0 * std::f64::consts::PI).unwrap();
-                context.fill();
-            }
+/// Feeds recorded messages through the same paths as live data. Resync requests are left
+/// unanswered, the recorded depth snapshots follow on their own.
+fn apply_recorded(market: &Arc<RwLock<MarketState>>, canvas_bubble: &Rc<RefCell<CanvasBubbleTrades>>, canvas_tape: &Rc<RefCell<CanvasTape>>, messages: &[RecordedMessage]) {
+    if messages.is_empty() {
+        return;
+    }
+    let mut market = match market.write() {
+        Ok(market) => market,
+        Err(e) => {
+            log(&format!("Failed to acquire write lock on market state: {}", e));
+            return;
         }
-        context.set_fill_style(&"rgba(81, 205, 160, 1)".into());
-        for trade in &buy_trades {
-            let radius = ((trade.quantity / max_quantity) * 20.0 * self.dpi).min(max_radius);
-            if radius > 1.0 {
-                let x = ((trade.time - thirty_seconds_ago) as f64 / 30000.0) * self.width;
-                let y = ((trade.price - y_min) / (y_max - y_min)) * padded_height + self.height * padding_percentage / 2.0;
-
-                context.begin_path();
-                context.arc(x, self.height - y, radius, 0.0, 2.0 * std::f64::consts::PI).unwrap();
-                context.fill();
-            }
+    };
+    for message in messages {
+        match message.kind {
+            RecordKind::Stream => {
+                let effects = market.apply_message(&message.data);
+                for (update_time, trades) in &effects.flushed_trades {
+                    canvas_bubble.borrow_mut().render(trades, *update_time);
+                    canvas_tape.borrow_mut().push(trades);
+                }
+            },
+            RecordKind::Depth => {
+                market.apply_depth_snapshot(&message.data);
+            },
+            RecordKind::Klines => market.apply_klines(&message.data),
+            RecordKind::HistTrades(kline_open) => market.apply_hist_trades(&message.data, kline_open),
         }
     }
 }
 
-pub fn group_orders(bucket_size: f64, orders: Vec<&Order>, multiplier: f64) -> Vec<Order> {
-    let mut grouped_orders = HashMap::new();
-    for order in orders {
-        let price = ((order.price / bucket_size).round() * bucket_size * multiplier) as i64;
-        let quantity = grouped_orders.entry(price).or_insert(0.0);
-        *quantity += order.quantity;
-    }
-    let mut orders = Vec::new();
-    for (price, quantity) in grouped_orders {
-        let price = price as f64 / multiplier; 
-        orders.push(Order { price, quantity });
-    }
-    orders
+pub(crate) fn call_js_callback(callback: &Rc<RefCell<Option<js_sys::Function>>>) {
+    if let Some(callback) = callback.borrow().as_ref() {
+        if let Err(e) = callback.call0(&JsValue::NULL) {
+            log(&format!("JS callback failed: {:?}", e));
+        }
+    }
 }
-pub struct OrderbookManager {
-    bids: Arc<RwLock<Vec<Order>>>,
-    asks: Arc<RwLock<Vec<Order>>>,
-    last_update_id: Arc<RwLock<u64>>,
+
+fn web_surface(canvas: &HtmlCanvasElement, name: &str) -> WebSurface {
+    WebSurface::new(canvas).unwrap_or_else(|e| panic!("Failed to create {}: {:?}", name, e))
 }
-impl OrderbookManager {
-    pub fn new() -> Self {
-        Self {
-            bids: Arc::new(RwLock::new(Vec::new())),
-            asks: Arc::new(RwLock::new(Vec::new())),
-            last_update_id: Arc::new(RwLock::new(0)),
-        }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(quantity: f64, time: u64, is_buyer_maker: bool) -> Trade {
+        Trade { price: Price::from_ticks(1000), quantity, time, is_buyer_maker }
+    }
+    fn times(trade_groups: &TradeGroups) -> Vec<u64> {
+        trade_groups.trades.iter().map(|trade| trade.time).collect()
     }
-    pub fn fetch_depth(&mut self, depth: JsValue) {
-        if let Some(depth_str) = depth.as_string() {
-            match serde_json::from_str::<serde_json::Value>(&depth_str) {
-                Ok(depth) => {
-                    if let Some(bids_array) = depth["bids"].as_array() {
-                        if let Ok(mut bids_borrowed) = self.bids.try_write() {
-                            *bids_borrowed = bids_array.iter().filter_map(|x| {
-                                x[0].as_str().and_then(|price_str| price_str.parse::<f64>().ok())
-                                    .and_then(|price| x[1].as_str().and_then(|quantity_str| quantity_str.parse::<f64>().ok())
-                                    .map(|quantity| Some(Order { price, quantity })).flatten())
-                            }).collect();
-                        } else {
-                            log(&format!("bids locked on render"));
-                        }
-                    }     
-                    if let Some(asks_array) = depth["asks"].as_array() {
-                        if let Ok(mut asks_borrowed) = self.asks.try_write() {
-                            *asks_borrowed = asks_array.iter().filter_map(|x| {
-                                x[0].as_str().and_then(|price_str| price_str.parse::<f64>().ok())
-                                    .and_then(|price| x[1].as_str().and_then(|quantity_str| quantity_str.parse::<f64>().ok())
-                                    .map(|quantity| Some(Order { price, quantity })).flatten())
-                            }).collect();
-                        } else {
-                            log(&format!("asks locked on render"));
-                        }
-                    }
 
-                    if let Ok(mut last_update_id) = self.last_update_id.try_write() {
-                        if let Some(last_update_id_val) = depth["lastUpdateId"].as_u64() {
-                            *last_update_id = last_update_id_val;
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Failed to parse depth: {}", e);
-                }
-            }
-        }
+    #[test]
+    fn late_trades_are_put_in_place() {
+        let mut trade_groups = TradeGroups::default();
+        trade_groups.push(&trade(1.0, 1, false));
+        trade_groups.push(&trade(3.0, 3, true));
+        assert_eq!(trade_groups.delta, RunningDelta { close: -2.0, max: 1.0, min: -2.0 });
+
+        trade_groups.push(&trade(2.0, 2, false));
+        assert_eq!(times(&trade_groups), vec![1, 2, 3]);
+        // replayed in time order, the sell comes after both buys
+        assert_eq!(trade_groups.delta, RunningDelta { close: 0.0, max: 3.0, min: 0.0 });
+
+        // after the trades of the same time already there
+        trade_groups.push(&trade(5.0, 1, true));
+        assert_eq!(times(&trade_groups), vec![1, 1, 2, 3]);
+        assert_eq!(trade_groups.trades[1].quantity, 5.0);
+        assert_eq!(trade_groups.delta, TradeGroups::from_trades(trade_groups.trades.clone()).delta);
     }
-}
\ No newline at end of file
+}
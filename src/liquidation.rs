@@ -0,0 +1,47 @@
+use crate::Price;
+
+/// Which side of the market was force closed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LiquidationSide {
+    /// A long position, closed with a sell order.
+    Long,
+    /// A short position, closed with a buy order.
+    Short,
+}
+impl LiquidationSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LiquidationSide::Long => "long",
+            LiquidationSide::Short => "short",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Liquidation {
+    /// Average fill price of the liquidation order.
+    pub price: Price,
+    pub quantity: f64,
+    pub time: u64,
+    pub side: LiquidationSide,
+}
+
+/// Liquidated quantity of one kline, per side.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LiquidationTotals {
+    pub long: f64,
+    pub short: f64,
+}
+impl LiquidationTotals {
+    /// Totals of the liquidations at least `min_quantity` in size.
+    pub fn new(liquidations: &[Liquidation], min_quantity: f64) -> Self {
+        let mut totals = Self::default();
+        for liquidation in liquidations.iter().filter(|liquidation| liquidation.quantity >= min_quantity) {
+            match liquidation.side {
+                LiquidationSide::Long => totals.long += liquidation.quantity,
+                LiquidationSide::Short => totals.short += liquidation.quantity,
+            }
+        }
+        totals
+    }
+}
@@ -0,0 +1,870 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::connector::{BinanceFutures, ExchangeConnector, MarketEvent};
+use crate::cvd::{cvd_candles, CvdAnchor, CvdCandle};
+use crate::footprint::{Footprint, FootprintConfig, VolumeProfile};
+use crate::funding::FundingState;
+use crate::heatmap::{BookSnapshot, DepthHistory, SNAPSHOTS_PER_KLINE};
+use crate::large_trades::{LargeTradeThreshold, RECENT_TRADES};
+use crate::liquidation::{Liquidation, LiquidationTotals};
+use crate::open_interest::{OiPoint, OpenInterest};
+use crate::orderbook::{BookSyncState, OrderbookManager, TopOfBook};
+use crate::{log, Kline, Price, RunningDelta, TickSize, Timeframe, Trade, TradeGroups};
+
+/// Trade as sent by the frontend for historical fetches, before prices are converted to ticks.
+#[derive(Deserialize)]
+struct HistTrade {
+    price: f64,
+    quantity: f64,
+    time: u64,
+    is_buyer_maker: bool,
+}
+
+/// What applying a stream message changed, for the side effects the caller owns.
+#[derive(Debug, Default)]
+pub struct MessageEffects {
+    /// The book lost sync and a new depth snapshot has to be fetched.
+    pub resync_needed: bool,
+    /// Trades moved into the footprint, with the time of the depth update that flushed them.
+    pub flushed_trades: Vec<(u64, Vec<Trade>)>,
+}
+
+/// Market data for one symbol and timeframe, independent of any canvas or browser API.
+///
+/// Every input is a raw payload string, so the same aggregation runs in the browser
+/// and natively.
+pub struct MarketState {
+    pub(crate) klines_ohlcv: BTreeMap<u64, Kline>,
+    pub(crate) klines_trades: BTreeMap<u64, TradeGroups>,
+    pub(crate) orderbook_manager: OrderbookManager,
+    pub(crate) oi_datapoints: OpenInterest,
+    pub(crate) funding: FundingState,
+    pub(crate) liquidations: BTreeMap<u64, Vec<Liquidation>>,
+    pub(crate) depth_history: DepthHistory,
+    connector: Arc<dyn ExchangeConnector>,
+    timeframe: Timeframe,
+    tick_size: TickSize,
+    /// Price grouping for the footprint and the book, in ticks.
+    bucket_size: i64,
+    trades_buffer: Vec<Trade>,
+    current_kline_open: u64,
+    last_depth_update: u64,
+    top_of_book: Option<TopOfBook>,
+}
+impl Default for MarketState {
+    fn default() -> Self {
+        Self::new(Box::new(BinanceFutures))
+    }
+}
+impl MarketState {
+    pub fn new(connector: Box<dyn ExchangeConnector>) -> Self {
+        Self {
+            klines_ohlcv: BTreeMap::new(),
+            klines_trades: BTreeMap::new(),
+            orderbook_manager: OrderbookManager::new(),
+            oi_datapoints: OpenInterest::default(),
+            funding: FundingState::default(),
+            liquidations: BTreeMap::new(),
+            depth_history: DepthHistory::default(),
+            connector: Arc::from(connector),
+            timeframe: Timeframe::M1,
+            tick_size: TickSize::default(),
+            bucket_size: 5,
+            trades_buffer: Vec::new(),
+            current_kline_open: 0,
+            last_depth_update: 0,
+            top_of_book: None,
+        }
+    }
+
+    pub fn connector(&self) -> &dyn ExchangeConnector {
+        self.connector.as_ref()
+    }
+    pub fn timeframe(&self) -> Timeframe {
+        self.timeframe
+    }
+    pub fn tick_size(&self) -> TickSize {
+        self.tick_size
+    }
+    pub fn bucket_size(&self) -> i64 {
+        self.bucket_size
+    }
+    pub fn last_depth_update(&self) -> u64 {
+        self.last_depth_update
+    }
+    /// Best bid and ask as of the last depth update, `None` while the book isn't synced.
+    pub fn top_of_book(&self) -> Option<TopOfBook> {
+        self.top_of_book
+    }
+    pub fn klines(&self) -> &BTreeMap<u64, Kline> {
+        &self.klines_ohlcv
+    }
+    pub fn footprint(&self) -> &BTreeMap<u64, TradeGroups> {
+        &self.klines_trades
+    }
+    /// Footprints of klines opening within `open_times`, grouped at the current bucket size.
+    pub fn footprints(&self, open_times: RangeInclusive<u64>, config: &FootprintConfig) -> Vec<(u64, Footprint)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        self.klines_trades.range(open_times)
+            .map(|(&open_time, trade_groups)| (open_time, Footprint::new(trade_groups, self.bucket_size, config)))
+            .collect()
+    }
+    /// Volume per price bucket summed over the footprints of klines opening within `open_times`.
+    pub fn volume_profile(&self, open_times: RangeInclusive<u64>) -> VolumeProfile {
+        let mut profile = VolumeProfile::default();
+        if open_times.start() <= open_times.end() {
+            for trade_groups in self.klines_trades.range(open_times).map(|(_, trade_groups)| trade_groups) {
+                profile.add(&trade_groups.grouped(self.bucket_size));
+            }
+        }
+        profile
+    }
+    /// Trades of klines opening within `open_times` that meet `threshold`, with their kline's open time.
+    /// Percentiles are taken over the latest trades, so the same trades stay marked while panning.
+    pub fn large_trades(&self, open_times: RangeInclusive<u64>, threshold: LargeTradeThreshold) -> Vec<(u64, Trade)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        let recent_quantities = self.klines_trades.values().rev()
+            .flat_map(|trade_groups| trade_groups.trades.iter().rev())
+            .take(RECENT_TRADES)
+            .map(|trade| trade.quantity)
+            .collect();
+        let min_quantity = match threshold.min_quantity(recent_quantities) {
+            Some(min_quantity) => min_quantity,
+            None => return Vec::new(),
+        };
+        self.klines_trades.range(open_times)
+            .flat_map(|(&open_time, trade_groups)| trade_groups.trades.iter().map(move |trade| (open_time, *trade)))
+            .filter(|(_, trade)| trade.quantity >= min_quantity)
+            .collect()
+    }
+    pub fn footprint_at(&self, open_time: u64, config: &FootprintConfig) -> Option<Footprint> {
+        self.klines_trades.get(&open_time)
+            .map(|trade_groups| Footprint::new(trade_groups, self.bucket_size, config))
+    }
+    pub fn open_interest(&self) -> &OpenInterest {
+        &self.oi_datapoints
+    }
+    /// Open interest of klines opening within `open_times`.
+    pub fn open_interest_points(&self, open_times: RangeInclusive<u64>) -> Vec<(u64, OiPoint)> {
+        self.oi_datapoints.points(&self.klines_ohlcv, self.tick_size, open_times)
+    }
+    pub fn funding(&self) -> &FundingState {
+        &self.funding
+    }
+    /// Liquidations of at least `min_quantity` in klines opening within `open_times`.
+    pub fn liquidations(&self, open_times: RangeInclusive<u64>, min_quantity: f64) -> Vec<(u64, Liquidation)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        self.liquidations.range(open_times)
+            .flat_map(|(&open_time, liquidations)| liquidations.iter().map(move |liquidation| (open_time, *liquidation)))
+            .filter(|(_, liquidation)| liquidation.quantity >= min_quantity)
+            .collect()
+    }
+    pub fn liquidation_totals(&self, open_times: RangeInclusive<u64>, min_quantity: f64) -> Vec<(u64, LiquidationTotals)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        self.liquidations.range(open_times)
+            .map(|(&open_time, liquidations)| (open_time, LiquidationTotals::new(liquidations, min_quantity)))
+            .collect()
+    }
+    pub fn depth_history(&self) -> &DepthHistory {
+        &self.depth_history
+    }
+    pub fn orderbook(&self) -> &OrderbookManager {
+        &self.orderbook_manager
+    }
+    pub fn book_sync_state(&self) -> BookSyncState {
+        self.orderbook_manager.sync_state()
+    }
+
+    /// Returns `true` if the timeframe changed, in which case all loaded data was dropped.
+    pub fn set_timeframe(&mut self, timeframe: Timeframe) -> bool {
+        if timeframe == self.timeframe {
+            return false;
+        }
+        self.timeframe = timeframe;
+        self.depth_history.set_interval(timeframe.as_ms() / SNAPSHOTS_PER_KLINE);
+        self.clear();
+        true
+    }
+
+    /// Prices are stored in ticks, so a different tick size drops everything loaded, which
+    /// can't be carried over. Returns `true` if an existing book had to be dropped and a new
+    /// snapshot is needed.
+    pub fn set_tick_size(&mut self, tick_size: TickSize) -> bool {
+        if tick_size == self.tick_size {
+            return false;
+        }
+        self.tick_size = tick_size;
+        let had_book = self.orderbook_manager.has_book();
+        self.clear();
+        had_book
+    }
+
+    /// Heatmap snapshots grouped at the previous size are dropped.
+    pub fn set_bucket_size(&mut self, bucket_ticks: i64) {
+        let bucket_ticks = bucket_ticks.max(1);
+        if bucket_ticks != self.bucket_size {
+            self.depth_history.clear();
+        }
+        self.bucket_size = bucket_ticks;
+    }
+    /// How long book snapshots are kept for the heatmap, zero to stop recording.
+    pub fn set_heatmap_retention(&mut self, retention_ms: u64) {
+        self.depth_history.set_retention(retention_ms);
+    }
+
+    /// Applies one raw stream message.
+    pub fn apply_message(&mut self, data: &str) -> MessageEffects {
+        let mut effects = MessageEffects::default();
+
+        for market_event in self.connector.parse_message(data, self.tick_size) {
+            match market_event {
+                MarketEvent::Trade(trade) => {
+                    self.trades_buffer.push(trade);
+                },
+                MarketEvent::Depth(depth_update) => {
+                    let update_time = depth_update.time;
+                    if self.orderbook_manager.apply_depth_update(depth_update) {
+                        effects.resync_needed = true;
+                    }
+                    self.update_top_of_book(update_time);
+                    self.record_depth(update_time);
+                    if self.current_kline_open != 0 {
+                        let trades: Vec<Trade> = self.trades_buffer.drain(..).collect();
+                        for trade in &trades {
+                            self.push_trade(trade);
+                        }
+                        effects.flushed_trades.push((update_time, trades));
+                        self.last_depth_update = update_time;
+                    }
+                },
+                MarketEvent::Kline(kline) => {
+                    self.current_kline_open = kline.open_time;
+                    self.insert_kline(kline);
+                },
+                MarketEvent::Liquidation(liquidation) => {
+                    self.liquidations.entry(self.timeframe.kline_open(liquidation.time))
+                        .or_default()
+                        .push(liquidation);
+                },
+                MarketEvent::MarkPrice(update) => {
+                    self.funding.apply_mark_price(update, self.timeframe.kline_open(update.time));
+                },
+            }
+        }
+        effects
+    }
+
+    /// Replaces the book with a REST depth snapshot. Returns `true` if another snapshot is needed.
+    pub fn apply_depth_snapshot(&mut self, data: &str) -> bool {
+        let resync_needed = match self.connector.parse_depth_snapshot(data, self.tick_size) {
+            Some(snapshot) => self.orderbook_manager.fetch_depth(snapshot),
+            None => false,
+        };
+        self.update_top_of_book(self.last_depth_update);
+        resync_needed
+    }
+
+    /// Inserts historical klines, replacing any with the same open time, e.g. when filling
+    /// a gap after a reconnect. CVD is re-accumulated over all loaded klines.
+    pub fn apply_klines(&mut self, data: &str) {
+        for kline in self.connector.parse_klines(data, self.tick_size) {
+            self.klines_ohlcv.insert(kline.open_time, kline);
+        }
+        let mut cum_volume_delta = 0.0;
+        for kline in self.klines_ohlcv.values_mut() {
+            cum_volume_delta += kline.buy_volume - kline.sell_volume;
+            kline.cum_volume_delta = cum_volume_delta;
+        }
+    }
+
+    /// Replaces the footprint of the kline opening at `kline_open` with historical trades.
+    /// Streamed trades newer than the last historical one are kept, so fetching the current
+    /// kline doesn't drop what came in while the request was in flight.
+    pub fn apply_hist_trades(&mut self, data: &str, kline_open: u64) {
+        match serde_json::from_str::<Vec<HistTrade>>(data) {
+            Ok(hist_trades) => {
+                let mut trades: Vec<Trade> = hist_trades.into_iter().filter_map(|trade| {
+                    let price = Price::from_f64(trade.price, self.tick_size)?;
+                    Some(Trade { price, quantity: trade.quantity, time: trade.time, is_buyer_maker: trade.is_buyer_maker })
+                }).collect();
+                let last_hist_time = trades.iter().map(|trade| trade.time).max();
+
+                if let Some(streamed) = self.klines_trades.get(&kline_open) {
+                    trades.extend(streamed.trades.iter().filter(|trade| last_hist_time.is_none_or(|last| trade.time > last)));
+                }
+                self.klines_trades.insert(kline_open, TradeGroups::from_trades(trades));
+            },
+            Err(e) => {
+                log(&format!("Failed to parse hist_trades: {}", e));
+            }
+        }
+    }
+
+    pub fn cvd_candles(&self, open_times: RangeInclusive<u64>, anchor: CvdAnchor) -> Vec<(u64, CvdCandle)> {
+        cvd_candles(&self.klines_ohlcv, &self.klines_trades, open_times, anchor)
+    }
+
+    /// Running delta of klines opening within `open_times`.
+    pub fn intrabar_deltas(&self, open_times: RangeInclusive<u64>) -> Vec<(u64, RunningDelta)> {
+        if open_times.start() > open_times.end() {
+            return Vec::new();
+        }
+        self.klines_trades.range(open_times).map(|(&open_time, trade_groups)| (open_time, trade_groups.delta)).collect()
+    }
+
+    /// Settled funding rates from the REST history.
+    pub fn apply_funding(&mut self, data: &str) {
+        for (funding_time, rate) in self.connector.parse_funding_history(data) {
+            self.funding.insert_history(funding_time, rate);
+        }
+    }
+
+    /// Mark price and funding from REST, until the stream takes over.
+    pub fn apply_mark_price(&mut self, data: &str) {
+        if let Some(update) = self.connector.parse_mark_price(data) {
+            self.funding.apply_mark_price(update, self.timeframe.kline_open(update.time));
+        }
+    }
+
+    /// Polled open interest, stored under the kline it falls into.
+    pub fn apply_oi(&mut self, data: &str) {
+        match serde_json::from_str::<serde_json::Value>(data) {
+            Ok(oi_obj) => {
+                if let (Some(time), Some(open_interest_str)) = (oi_obj["time"].as_u64(), oi_obj["openInterest"].as_str()) {
+                    if let Ok(open_interest) = open_interest_str.parse::<f64>() {
+                        self.oi_datapoints.insert(self.timeframe.kline_open(time), time, open_interest);
+                    }
+                }
+            },
+            Err(e) => {
+                log(&format!("Failed to parse oi: {}", e));
+            }
+        }
+    }
+
+    /// Historical open interest, e.g. from a period matching the timeframe. Points replace any
+    /// already loaded for the same kline.
+    pub fn apply_hist_oi(&mut self, data: &str) {
+        match serde_json::from_str::<Vec<serde_json::Value>>(data) {
+            Ok(hist_ois) => {
+                for hist_oi in hist_ois {
+                    if let (Some(time), Some(open_interest_str)) = (hist_oi["timestamp"].as_u64(), hist_oi["sumOpenInterest"].as_str()) {
+                        if let Ok(open_interest) = open_interest_str.parse::<f64>() {
+                            self.oi_datapoints.insert(self.timeframe.kline_open(time), time, open_interest);
+                        }
+                    }
+                }
+            },
+            Err(e) => {
+                log(&format!("Failed to parse hist_ois: {}", e));
+            }
+        }
+    }
+
+    /// Drops state that can't be trusted across a dropped stream: the book, which needs a
+    /// new snapshot, and unflushed trades. Returns the open time of the last kline seen before
+    /// the drop, from which klines and footprint trades have to be refetched.
+    pub fn reset_for_reconnect(&mut self) -> u64 {
+        self.orderbook_manager.reset();
+        self.top_of_book = None;
+        self.trades_buffer.clear();
+        self.klines_ohlcv.keys().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.oi_datapoints.clear();
+        self.funding.clear();
+        self.liquidations.clear();
+        self.depth_history.clear();
+        self.klines_ohlcv.clear();
+        self.klines_trades.clear();
+        self.trades_buffer.clear();
+        self.current_kline_open = 0;
+        self.orderbook_manager.reset();
+        self.top_of_book = None;
+    }
+
+    /// Copy of the data as it stood at `time`, for stepping through history. Later klines are
+    /// dropped and the one containing `time` is rebuilt from its trades up to then. The book
+    /// is the last heatmap snapshot taken by then, empty if there is none.
+    pub fn until(&self, time: u64) -> MarketState {
+        let current_kline_open = self.timeframe.kline_open(time);
+
+        let mut klines_trades: BTreeMap<u64, TradeGroups> = self.klines_trades.range(..current_kline_open)
+            .map(|(&open_time, trade_groups)| (open_time, trade_groups.clone()))
+            .collect();
+        if let Some(trade_groups) = self.klines_trades.get(&current_kline_open) {
+            let trades = trade_groups.trades.iter().filter(|trade| trade.time <= time).copied().collect();
+            klines_trades.insert(current_kline_open, TradeGroups::from_trades(trades));
+        }
+
+        let mut klines_ohlcv: BTreeMap<u64, Kline> = self.klines_ohlcv.range(..current_kline_open)
+            .map(|(&open_time, kline)| (open_time, kline.clone()))
+            .collect();
+        match self.klines_ohlcv.get(&current_kline_open) {
+            Some(kline) if kline.close_time <= time => {
+                klines_ohlcv.insert(current_kline_open, kline.clone());
+            },
+            Some(kline) => {
+                let cum_volume_delta = klines_ohlcv.values().next_back().map_or(0.0, |kline| kline.cum_volume_delta);
+                klines_ohlcv.insert(current_kline_open, partial_kline(kline, klines_trades.get(&current_kline_open), cum_volume_delta));
+            },
+            None => {},
+        }
+
+        let liquidations = self.liquidations.range(..=current_kline_open)
+            .map(|(&open_time, liquidations)| {
+                (open_time, liquidations.iter().filter(|liquidation| liquidation.time <= time).copied().collect::<Vec<_>>())
+            })
+            .filter(|(_, liquidations)| !liquidations.is_empty())
+            .collect();
+
+        let depth_history = self.depth_history.until(time);
+        let (orderbook_manager, last_depth_update, top_of_book) = replayed_book(&depth_history);
+
+        MarketState {
+            klines_ohlcv,
+            klines_trades,
+            orderbook_manager,
+            oi_datapoints: self.oi_datapoints.until(time),
+            funding: self.funding.until(time),
+            liquidations,
+            depth_history,
+            connector: Arc::clone(&self.connector),
+            timeframe: self.timeframe,
+            tick_size: self.tick_size,
+            bucket_size: self.bucket_size,
+            trades_buffer: Vec::new(),
+            current_kline_open,
+            last_depth_update,
+            top_of_book,
+        }
+    }
+
+    /// Brings `view`, made by `until(from)`, forward to `to` by copying only what happened in
+    /// between, rather than everything before `to`.
+    pub fn extend_until(&self, view: &mut MarketState, from: u64, to: u64) {
+        let (from_kline_open, to_kline_open) = (self.timeframe.kline_open(from), self.timeframe.kline_open(to));
+
+        for (&open_time, trade_groups) in self.klines_trades.range(from_kline_open..=to_kline_open) {
+            let view_trades = view.klines_trades.entry(open_time).or_default();
+            for trade in trade_groups.trades.iter().filter(|trade| trade.time > from && trade.time <= to) {
+                view_trades.push(trade);
+            }
+        }
+        for (&open_time, kline) in self.klines_ohlcv.range(from_kline_open..=to_kline_open) {
+            let kline = if kline.close_time <= to {
+                kline.clone()
+            } else {
+                let cum_volume_delta = view.klines_ohlcv.range(..open_time).next_back().map_or(0.0, |(_, kline)| kline.cum_volume_delta);
+                partial_kline(kline, view.klines_trades.get(&open_time), cum_volume_delta)
+            };
+            view.klines_ohlcv.insert(open_time, kline);
+        }
+        for (&open_time, liquidations) in self.liquidations.range(from_kline_open..=to_kline_open) {
+            let liquidations: Vec<Liquidation> = liquidations.iter().filter(|liquidation| liquidation.time <= to).copied().collect();
+            if !liquidations.is_empty() {
+                view.liquidations.insert(open_time, liquidations);
+            }
+        }
+
+        let mut book_changed = false;
+        for snapshot in self.depth_history.range(from.saturating_add(1)..=to) {
+            view.depth_history.push(snapshot.clone());
+            book_changed = true;
+        }
+        if book_changed {
+            (view.orderbook_manager, view.last_depth_update, view.top_of_book) = replayed_book(&view.depth_history);
+        }
+
+        view.oi_datapoints = self.oi_datapoints.until(to);
+        view.funding = self.funding.until(to);
+        view.current_kline_open = to_kline_open;
+    }
+
+    fn update_top_of_book(&mut self, time: u64) {
+        if self.orderbook_manager.sync_state() != BookSyncState::Synced {
+            self.top_of_book = None;
+            return;
+        }
+        self.top_of_book = match (self.orderbook_manager.bids.read(), self.orderbook_manager.asks.read()) {
+            (Ok(bids), Ok(asks)) => TopOfBook::new(time, &bids, &asks),
+            _ => None,
+        };
+    }
+
+    fn record_depth(&mut self, time: u64) {
+        if !self.depth_history.is_due(time) || self.orderbook_manager.sync_state() != BookSyncState::Synced {
+            return;
+        }
+        match (self.orderbook_manager.bids.read(), self.orderbook_manager.asks.read()) {
+            (Ok(bids), Ok(asks)) => {
+                let snapshot = BookSnapshot::new(time, &bids, &asks, self.bucket_size);
+                self.depth_history.push(snapshot);
+            },
+            _ => log("Failed to acquire lock on the book while recording depth"),
+        }
+    }
+
+    fn push_trade(&mut self, trade: &Trade) {
+        self.klines_trades.entry(self.timeframe.kline_open(trade.time))
+            .or_default()
+            .push(trade);
+    }
+
+    /// Stream klines repeat while open, so the CVD of an update to the last kline
+    /// swaps out that kline's previous delta instead of adding to it.
+    fn insert_kline(&mut self, mut kline: Kline) {
+        kline.cum_volume_delta = match self.klines_ohlcv.iter().next_back() {
+            Some((&last_open_time, last_kline)) if last_open_time == kline.open_time => {
+                last_kline.cum_volume_delta - (last_kline.buy_volume - last_kline.sell_volume) + (kline.buy_volume - kline.sell_volume)
+            },
+            Some((_, last_kline)) => last_kline.cum_volume_delta + kline.buy_volume - kline.sell_volume,
+            None => kline.buy_volume - kline.sell_volume,
+        };
+        self.klines_ohlcv.insert(kline.open_time, kline);
+    }
+}
+
+/// Book as of the last snapshot in `depth_history`, as shown while replaying.
+fn replayed_book(depth_history: &DepthHistory) -> (OrderbookManager, u64, Option<TopOfBook>) {
+    let (orderbook_manager, last_depth_update) = match depth_history.latest() {
+        Some(snapshot) => (OrderbookManager::with_levels(&snapshot.bids, &snapshot.asks), snapshot.time),
+        None => (OrderbookManager::new(), 0),
+    };
+    let top_of_book = match (orderbook_manager.bids.read(), orderbook_manager.asks.read()) {
+        (Ok(bids), Ok(asks)) => TopOfBook::new(last_depth_update, &bids, &asks),
+        _ => None,
+    };
+    (orderbook_manager, last_depth_update, top_of_book)
+}
+
+/// `kline` as far as `trades` got, carrying on from the CVD of the kline before it.
+/// Without trades only the open is known.
+fn partial_kline(kline: &Kline, trades: Option<&TradeGroups>, cum_volume_delta: f64) -> Kline {
+    let mut partial = Kline {
+        open_time: kline.open_time,
+        open: kline.open,
+        high: kline.open,
+        low: kline.open,
+        close: kline.open,
+        buy_volume: 0.0,
+        sell_volume: 0.0,
+        cum_volume_delta,
+        close_time: kline.close_time,
+    };
+    for trade in trades.map_or(&[][..], |trade_groups| &trade_groups.trades) {
+        partial.high = partial.high.max(trade.price);
+        partial.low = partial.low.min(trade.price);
+        partial.close = trade.price;
+        if trade.is_buyer_maker {
+            partial.sell_volume += trade.quantity;
+        } else {
+            partial.buy_volume += trade.quantity;
+        }
+    }
+    partial.cum_volume_delta += partial.buy_volume - partial.sell_volume;
+    partial
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+    use crate::orderbook::PriceLevels;
+
+    fn market() -> MarketState {
+        let mut market = MarketState::default();
+        market.set_tick_size(TickSize::parse("0.1").unwrap());
+        market.set_bucket_size(5);
+        market
+    }
+    fn price(s: &str) -> Price {
+        Price::parse(s, TickSize::parse("0.1").unwrap()).unwrap()
+    }
+    fn trade(price: &str, quantity: &str, time: u64, is_buyer_maker: bool) -> String {
+        format!(r#"{{"stream":"btcusdt@aggTrade","data":{{"p":"{}","q":"{}","T":{},"m":{}}}}}"#, price, quantity, time, is_buyer_maker)
+    }
+    fn kline(open_time: u64, close: &str, volume: &str, buy_volume: &str) -> String {
+        format!(
+            r#"{{"stream":"btcusdt@kline_1m","data":{{"k":{{"t":{},"T":{},"o":"100.0","h":"101.0","l":"99.0","c":"{}","v":"{}","V":"{}"}}}}}}"#,
+            open_time, open_time + 59_999, close, volume, buy_volume,
+        )
+    }
+    fn depth(time: u64, first: u64, last: u64, previous: u64, bids: &str, asks: &str) -> String {
+        format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"T":{},"U":{},"u":{},"pu":{},"b":{},"a":{}}}}}"#,
+            time, first, last, previous, bids, asks,
+        )
+    }
+    fn snapshot(last_update_id: u64) -> String {
+        format!(r#"{{"lastUpdateId":{},"bids":[["99.9","1.0"],["99.8","2.0"]],"asks":[["100.0","1.5"],["100.1","3.0"]]}}"#, last_update_id)
+    }
+    fn level(levels: &RwLock<PriceLevels>, price: Price) -> Option<f64> {
+        levels.read().unwrap().range(price..=price).next().map(|(_, quantity)| quantity)
+    }
+    fn hist_trades(trades: &[(f64, f64, u64, bool)]) -> String {
+        serde_json::json!(trades.iter().map(|&(price, quantity, time, is_buyer_maker)| serde_json::json!({
+            "price": price, "quantity": quantity, "time": time, "is_buyer_maker": is_buyer_maker,
+        })).collect::<Vec<_>>()).to_string()
+    }
+
+    #[test]
+    fn trades_are_bucketed_into_the_footprint_on_depth_updates() {
+        let mut market = market();
+        market.apply_message(&kline(60_000, "100.0", "0", "0"));
+        market.apply_message(&trade("100.0", "1", 60_001, false));
+        market.apply_message(&trade("100.2", "2", 60_002, true));
+        market.apply_message(&trade("100.3", "0.5", 60_003, false));
+        assert!(market.footprint().is_empty(), "trades wait for a depth update");
+
+        let effects = market.apply_message(&depth(60_004, 1, 2, 0, "[]", "[]"));
+        assert_eq!(effects.flushed_trades.len(), 1);
+        assert_eq!(effects.flushed_trades[0].0, 60_004);
+        assert_eq!(effects.flushed_trades[0].1.len(), 3);
+        assert_eq!(market.last_depth_update(), 60_004);
+
+        let trade_groups = &market.footprint()[&60_000];
+        let grouped = trade_groups.grouped(market.bucket_size());
+        assert_eq!(grouped.buys.len(), 2);
+        assert_eq!(grouped.buys[&price("100.0")], 1.0);
+        assert_eq!(grouped.buys[&price("100.5")], 0.5);
+        assert_eq!(grouped.sells.len(), 1);
+        assert_eq!(grouped.sells[&price("100.0")], 2.0);
+        assert_eq!(trade_groups.delta, RunningDelta { close: -0.5, max: 1.0, min: -1.0 });
+    }
+
+    #[test]
+    fn trades_before_the_first_kline_stay_buffered() {
+        let mut market = market();
+        market.apply_message(&trade("100.0", "1", 60_001, false));
+        let effects = market.apply_message(&depth(60_002, 1, 2, 0, "[]", "[]"));
+        assert!(effects.flushed_trades.is_empty());
+        assert!(market.footprint().is_empty());
+    }
+
+    #[test]
+    fn trades_land_in_the_kline_they_happened_in() {
+        let mut market = market();
+        market.apply_message(&kline(60_000, "100.0", "0", "0"));
+        market.apply_message(&trade("100.0", "1", 119_999, false));
+        market.apply_message(&trade("100.0", "2", 120_000, true));
+        market.apply_message(&depth(120_001, 1, 2, 0, "[]", "[]"));
+        assert_eq!(market.footprint()[&60_000].trade_count(), 1);
+        assert_eq!(market.footprint()[&120_000].trade_count(), 1);
+    }
+
+    #[test]
+    fn historical_trades_keep_newer_streamed_ones() {
+        let mut market = market();
+        market.apply_message(&kline(60_000, "100.0", "0", "0"));
+        market.apply_message(&trade("100.0", "1", 60_001, false));
+        market.apply_message(&trade("100.0", "1", 60_004, true));
+        market.apply_message(&trade("100.0", "4", 60_010, false));
+        market.apply_message(&depth(60_011, 1, 2, 0, "[]", "[]"));
+
+        // the request covered the first two streamed trades, but not the last
+        market.apply_hist_trades(&hist_trades(&[(100.0, 1.0, 60_001, false), (100.0, 2.0, 60_003, true), (100.0, 1.0, 60_005, true)]), 60_000);
+        assert_eq!(trade_times(&market), vec![(60_000, vec![60_001, 60_003, 60_005, 60_010])]);
+        assert_eq!(market.footprint()[&60_000].delta, RunningDelta { close: 2.0, max: 2.0, min: -2.0 });
+
+        // no historical trades leaves the streamed ones
+        market.apply_hist_trades("[]", 60_000);
+        assert_eq!(trade_times(&market), vec![(60_000, vec![60_001, 60_003, 60_005, 60_010])]);
+    }
+
+    #[test]
+    fn volume_profile_sums_the_klines_in_range() {
+        let mut market = market();
+        market.apply_hist_trades(&hist_trades(&[(100.0, 1.0, 60_001, false), (100.2, 2.0, 60_002, true)]), 60_000);
+        market.apply_hist_trades(&hist_trades(&[(100.1, 3.0, 120_001, false), (100.5, 1.0, 120_002, false)]), 120_000);
+        market.apply_hist_trades(&hist_trades(&[(100.0, 5.0, 180_001, false)]), 180_000);
+
+        // buys and sells per bucket of 5 ticks
+        let profile = market.volume_profile(60_000..=120_000);
+        assert_eq!(profile.levels, BTreeMap::from([(price("100.0"), (4.0, 2.0)), (price("100.5"), (1.0, 0.0))]));
+        assert_eq!(profile.max_volume(), 6.0);
+        assert!(market.volume_profile(240_000..=300_000).levels.is_empty());
+    }
+
+    #[test]
+    fn klines_accumulate_cvd() {
+        let mut market = market();
+        market.apply_message(&kline(0, "100.5", "5", "3"));
+        assert_eq!(market.klines()[&0].cum_volume_delta, 1.0);
+        // a repeated update of the open kline replaces its delta
+        market.apply_message(&kline(0, "100.7", "6", "4"));
+        assert_eq!(market.klines()[&0].cum_volume_delta, 2.0);
+        assert_eq!(market.klines()[&0].close, price("100.7"));
+        market.apply_message(&kline(60_000, "100.2", "4", "1"));
+        assert_eq!(market.klines()[&60_000].cum_volume_delta, 0.0);
+        assert_eq!(market.klines().len(), 2);
+
+        // REST history replacing a kline re-accumulates everything after it
+        market.apply_klines(r#"[[0,"100.0","101.0","99.0","100.0","10",59999,"0",1,"10","0","0"]]"#);
+        let cvd: Vec<f64> = market.klines().values().map(|kline| kline.cum_volume_delta).collect();
+        assert_eq!(cvd, vec![10.0, 8.0]);
+    }
+
+    #[test]
+    fn snapshot_bridges_buffered_diffs() {
+        let mut market = market();
+        // before the snapshot, diffs are only buffered
+        assert!(!market.apply_message(&depth(1, 90, 95, 89, r#"[["99.9","9.0"]]"#, "[]")).resync_needed);
+        assert!(!market.apply_message(&depth(2, 96, 105, 95, r#"[["99.8","4.0"]]"#, r#"[["100.0","0"]]"#)).resync_needed);
+        assert_eq!(market.book_sync_state(), BookSyncState::AwaitingSnapshot);
+
+        assert!(!market.apply_depth_snapshot(&snapshot(100)));
+        assert_eq!(market.book_sync_state(), BookSyncState::Synced);
+        let book = market.orderbook();
+        // the diff ending at 95 predates the snapshot and is skipped
+        assert_eq!(level(&book.bids, price("99.9")), Some(1.0));
+        // the one spanning 100 is applied
+        assert_eq!(level(&book.bids, price("99.8")), Some(4.0));
+        assert_eq!(level(&book.asks, price("100.0")), None);
+        let top_of_book = market.top_of_book().unwrap();
+        assert_eq!((top_of_book.best_bid, top_of_book.best_ask), (price("99.9"), price("100.1")));
+
+        // continuing the `pu` chain
+        assert!(!market.apply_message(&depth(3, 106, 110, 105, r#"[["99.9","2.5"]]"#, "[]")).resync_needed);
+        assert_eq!(level(&market.orderbook().bids, price("99.9")), Some(2.5));
+    }
+
+    #[test]
+    fn snapshot_older_than_buffered_diffs_needs_another() {
+        let mut market = market();
+        market.apply_message(&depth(1, 120, 130, 119, "[]", "[]"));
+        assert!(market.apply_depth_snapshot(&snapshot(100)));
+        assert_eq!(market.book_sync_state(), BookSyncState::OutOfSync);
+    }
+
+    #[test]
+    fn broken_pu_chain_requests_a_resync() {
+        let mut market = market();
+        market.apply_depth_snapshot(&snapshot(100));
+        assert!(!market.apply_message(&depth(1, 95, 105, 94, "[]", "[]")).resync_needed);
+        // contiguous ids, but `pu` doesn't point at the last applied diff
+        assert!(market.apply_message(&depth(2, 106, 110, 104, r#"[["99.9","7.0"]]"#, "[]")).resync_needed);
+        assert_eq!(market.book_sync_state(), BookSyncState::OutOfSync);
+        assert_eq!(level(&market.orderbook().bids, price("99.9")), Some(1.0));
+        assert!(market.top_of_book().is_none());
+
+        // diffs are ignored until a new snapshot lines up
+        assert!(!market.apply_message(&depth(3, 111, 115, 110, r#"[["99.9","8.0"]]"#, "[]")).resync_needed);
+        assert!(!market.apply_depth_snapshot(&snapshot(112)));
+        assert_eq!(market.book_sync_state(), BookSyncState::Synced);
+        assert_eq!(level(&market.orderbook().bids, price("99.9")), Some(8.0));
+    }
+
+    #[test]
+    fn gap_after_the_snapshot_requests_a_resync() {
+        let mut market = market();
+        market.apply_depth_snapshot(&snapshot(100));
+        assert!(market.apply_message(&depth(1, 110, 115, 109, "[]", "[]")).resync_needed);
+        assert_eq!(market.book_sync_state(), BookSyncState::OutOfSync);
+    }
+
+    #[test]
+    fn first_diff_has_to_span_the_snapshot() {
+        // spot would accept `U == lastUpdateId + 1`, futures need `U <= lastUpdateId <= u`
+        let mut market = market();
+        market.apply_depth_snapshot(&snapshot(100));
+        assert!(market.apply_message(&depth(1, 101, 105, 100, r#"[["99.9","7.0"]]"#, "[]")).resync_needed);
+        assert_eq!(market.book_sync_state(), BookSyncState::OutOfSync);
+        assert_eq!(level(&market.orderbook().bids, price("99.9")), Some(1.0));
+
+        // the same diff buffered ahead of the snapshot doesn't bridge it either
+        let mut buffered = self::market();
+        buffered.apply_message(&depth(1, 101, 105, 100, "[]", "[]"));
+        assert!(buffered.apply_depth_snapshot(&snapshot(100)));
+        assert_eq!(buffered.book_sync_state(), BookSyncState::OutOfSync);
+
+        // ending exactly on it does
+        let mut bridged = self::market();
+        bridged.apply_depth_snapshot(&snapshot(100));
+        assert!(!bridged.apply_message(&depth(1, 90, 100, 89, r#"[["99.9","7.0"]]"#, "[]")).resync_needed);
+        assert_eq!(bridged.book_sync_state(), BookSyncState::Synced);
+        assert_eq!(level(&bridged.orderbook().bids, price("99.9")), Some(7.0));
+    }
+
+    fn kline_values(market: &MarketState) -> Vec<(u64, Price, f64, f64, f64)> {
+        market.klines().iter()
+            .map(|(&open_time, kline)| (open_time, kline.close, kline.buy_volume, kline.sell_volume, kline.cum_volume_delta))
+            .collect()
+    }
+    fn trade_times(market: &MarketState) -> Vec<(u64, Vec<u64>)> {
+        market.footprint().iter()
+            .map(|(&open_time, trade_groups)| (open_time, trade_groups.trades.iter().map(|trade| trade.time).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn extending_a_view_matches_rebuilding_it() {
+        let mut market = market();
+        for (open_time, close) in [(0, "100.5"), (60_000, "100.2"), (120_000, "99.8")] {
+            market.apply_message(&kline(open_time, close, "4", "1"));
+        }
+        for (price, time, is_buyer_maker) in [("100.0", 10_000, false), ("100.5", 50_000, true), ("100.2", 70_000, false), ("99.8", 130_000, true)] {
+            market.apply_message(&trade(price, "1", time, is_buyer_maker));
+        }
+        market.apply_message(&depth(130_001, 1, 2, 0, "[]", "[]"));
+
+        let mut view = market.until(30_000);
+        for (from, to) in [(30_000, 90_000), (90_000, 90_000), (90_000, 179_999)] {
+            market.extend_until(&mut view, from, to);
+            let rebuilt = market.until(to);
+            assert_eq!(kline_values(&view), kline_values(&rebuilt), "extended from {} to {}", from, to);
+            assert_eq!(trade_times(&view), trade_times(&rebuilt), "extended from {} to {}", from, to);
+        }
+        assert_eq!(view.klines()[&60_000].cum_volume_delta, -4.0);
+    }
+
+    #[test]
+    fn open_interest_polled_after_the_cursor_is_hidden() {
+        let mut market = market();
+        market.apply_message(&kline(60_000, "100.0", "0", "0"));
+        market.apply_oi(r#"{"openInterest":"1000.0","symbol":"BTCUSDT","time":90000}"#);
+        assert!(market.until(70_000).open_interest().is_empty());
+        assert_eq!(market.until(90_000).open_interest().len(), 1);
+    }
+
+    #[test]
+    fn a_new_tick_size_drops_data_keyed_in_the_old_one() {
+        let mut market = market();
+        market.apply_message(&kline(60_000, "100.0", "1", "1"));
+        market.apply_message(&trade("100.0", "1", 60_001, false));
+        market.apply_message(&depth(60_002, 1, 2, 0, "[]", "[]"));
+        market.apply_message(r#"{"stream":"btcusdt@forceOrder","data":{"o":{"S":"SELL","q":"1","p":"99.0","ap":"99.0","z":"1","T":60003}}}"#);
+        market.apply_message(&trade("100.1", "1", 60_004, true));
+        assert!(!market.apply_depth_snapshot(&snapshot(100)));
+
+        // the same tick size keeps everything
+        assert!(!market.set_tick_size(TickSize::parse("0.1").unwrap()));
+        assert_eq!(market.klines().len(), 1);
+        assert_eq!(market.footprint().len(), 1);
+        assert_eq!(market.liquidations(0..=60_000, 0.0).len(), 1);
+
+        assert!(market.set_tick_size(TickSize::parse("0.01").unwrap()), "the book needs a new snapshot");
+        assert!(market.klines().is_empty());
+        assert!(market.footprint().is_empty());
+        assert!(market.liquidations(0..=60_000, 0.0).is_empty());
+        assert!(market.top_of_book().is_none());
+        // the buffered trade isn't flushed into the footprint in the new ticks
+        market.apply_message(&kline(60_000, "100.0", "1", "1"));
+        market.apply_message(&depth(60_005, 3, 4, 2, "[]", "[]"));
+        assert!(market.footprint().is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::{Kline, TickSize};
+
+/// How open interest is drawn on the CVD indicator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OiMode {
+    Off,
+    /// OI per kline as dots.
+    Points,
+    /// Change from the previous point, as bars around zero.
+    Histogram,
+}
+impl std::str::FromStr for OiMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(OiMode::Off),
+            "points" => Ok(OiMode::Points),
+            "histogram" => Ok(OiMode::Histogram),
+            _ => Err(format!("Unsupported open interest mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct OiPoint {
+    pub open_interest: f64,
+    /// Change from the previous point, zero for the first one loaded.
+    pub change: f64,
+    /// Average price positions were opened at, see `OpenInterest::points`.
+    pub weighted_price: Option<f64>,
+}
+
+/// Open interest keyed by kline open time, with the time it was taken. A later value for the
+/// same kline replaces the earlier one, so overlapping fetches and polls don't produce duplicates.
+#[derive(Clone, Debug, Default)]
+pub struct OpenInterest {
+    points: BTreeMap<u64, (u64, f64)>,
+}
+impl OpenInterest {
+    pub fn insert(&mut self, kline_open: u64, time: u64, open_interest: f64) {
+        self.points.insert(kline_open, (time, open_interest));
+    }
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+    /// Copy holding the points taken up to `time`. A kline whose only value was polled later
+    /// has no point, as an earlier poll it replaced isn't kept.
+    pub fn until(&self, time: u64) -> Self {
+        Self { points: self.points.range(..=time).filter(|(_, &(taken, _))| taken <= time).map(|(&open_time, &point)| (open_time, point)).collect() }
+    }
+
+    /// Points of klines opening within `open_times`. Changes and weighted prices are carried
+    /// from everything loaded before the range, so they don't depend on what's in view.
+    pub fn points(&self, klines: &BTreeMap<u64, Kline>, tick_size: TickSize, open_times: RangeInclusive<u64>) -> Vec<(u64, OiPoint)> {
+        let (first, last) = (*open_times.start(), *open_times.end());
+        if first > last {
+            return Vec::new();
+        }
+        let mut points = Vec::new();
+        let mut previous: Option<f64> = None;
+        let mut weighted_price: Option<f64> = None;
+
+        for (&open_time, &(_, open_interest)) in self.points.range(..=last) {
+            let change = previous.map_or(0.0, |previous| open_interest - previous);
+            if let Some(close) = klines.get(&open_time).map(|kline| kline.close.to_f64(tick_size)) {
+                weighted_price = Some(match weighted_price {
+                    Some(average) if change > 0.0 && open_interest > 0.0 => {
+                        (average * (open_interest - change) + close * change) / open_interest
+                    },
+                    Some(average) => average,
+                    None => close,
+                });
+            }
+            previous = Some(open_interest);
+
+            if open_time >= first {
+                points.push((open_time, OiPoint { open_interest, change, weighted_price }));
+            }
+        }
+        points
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
+
+use wasm_bindgen::prelude::*;
+
+use crate::connector::{DepthSnapshot, DepthUpdate};
+use crate::{log, Price, TickSize};
+
+/// Upper bound on stream diffs kept for replaying on top of a new snapshot.
+const MAX_BUFFERED_UPDATES: usize = 1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// One side of the book, quantities keyed by price.
+/// Updates are O(log n), the best level is cached so reading it is O(1).
+#[derive(Clone, Debug)]
+pub struct PriceLevels {
+    side: Side,
+    levels: BTreeMap<Price, f64>,
+    best: Option<(Price, f64)>,
+}
+impl PriceLevels {
+    pub fn new(side: Side) -> Self {
+        Self {
+            side,
+            levels: BTreeMap::new(),
+            best: None,
+        }
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.levels.clear();
+        self.best = None;
+    }
+
+    /// Highest bid or lowest ask.
+    pub fn best(&self) -> Option<(Price, f64)> {
+        self.best
+    }
+    /// Level furthest away from the top of the book.
+    pub fn worst(&self) -> Option<(Price, f64)> {
+        let level = match self.side {
+            Side::Bid => self.levels.first_key_value(),
+            Side::Ask => self.levels.last_key_value(),
+        };
+        level.map(|(&price, &quantity)| (price, quantity))
+    }
+
+    /// Sets the quantity resting at `price`, a zero quantity removes the level.
+    pub fn update(&mut self, price: Price, quantity: f64) {
+        if quantity == 0.0 {
+            if self.levels.remove(&price).is_some() && self.best.is_some_and(|(best, _)| best == price) {
+                self.best = self.find_best();
+            }
+            return;
+        }
+        self.levels.insert(price, quantity);
+        let is_better = match (self.best, self.side) {
+            (None, _) => true,
+            (Some((best, _)), Side::Bid) => price >= best,
+            (Some((best, _)), Side::Ask) => price <= best,
+        };
+        if is_better {
+            self.best = Some((price, quantity));
+        }
+    }
+
+    /// Levels within `range` in ascending price order.
+    pub fn range(&self, range: RangeInclusive<Price>) -> impl DoubleEndedIterator<Item = (Price, f64)> + '_ {
+        self.levels.range(range).map(|(&price, &quantity)| (price, quantity))
+    }
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (Price, f64)> + '_ {
+        self.levels.iter().map(|(&price, &quantity)| (price, quantity))
+    }
+    pub fn max_quantity(&self) -> f64 {
+        self.levels.values().fold(0.0, |max, &quantity| quantity.max(max))
+    }
+    /// Levels from the best price outward, each with the quantity resting up to and including it.
+    pub fn cumulative(&self) -> Vec<(Price, f64)> {
+        let levels: Box<dyn Iterator<Item = (Price, f64)>> = match self.side {
+            Side::Bid => Box::new(self.iter().rev()),
+            Side::Ask => Box::new(self.iter()),
+        };
+        levels.scan(0.0, |total, (price, quantity)| {
+            *total += quantity;
+            Some((price, *total))
+        }).collect()
+    }
+
+    fn find_best(&self) -> Option<(Price, f64)> {
+        let level = match self.side {
+            Side::Bid => self.levels.last_key_value(),
+            Side::Ask => self.levels.first_key_value(),
+        };
+        level.map(|(&price, &quantity)| (price, quantity))
+    }
+}
+
+/// Best bid and ask with the quantity resting at each.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TopOfBook {
+    pub time: u64,
+    pub best_bid: Price,
+    pub bid_quantity: f64,
+    pub best_ask: Price,
+    pub ask_quantity: f64,
+}
+impl TopOfBook {
+    /// `None` while either side of the book is empty.
+    pub fn new(time: u64, bids: &PriceLevels, asks: &PriceLevels) -> Option<Self> {
+        let (best_bid, bid_quantity) = bids.best()?;
+        let (best_ask, ask_quantity) = asks.best()?;
+        Some(Self { time, best_bid, bid_quantity, best_ask, ask_quantity })
+    }
+
+    pub fn spread_ticks(&self) -> i64 {
+        self.best_ask.ticks() - self.best_bid.ticks()
+    }
+    /// Spread in basis points of mid. Negative like `spread_ticks` while the book is crossed,
+    /// zero for a book at or below a zero price.
+    pub fn spread_bps(&self) -> f64 {
+        let mid = (self.best_bid.ticks() + self.best_ask.ticks()) as f64 / 2.0;
+        if mid > 0.0 { self.spread_ticks() as f64 / mid * 10_000.0 } else { 0.0 }
+    }
+    pub fn mid(&self, tick_size: TickSize) -> f64 {
+        (self.best_bid.to_f64(tick_size) + self.best_ask.to_f64(tick_size)) / 2.0
+    }
+    /// Mid weighted towards the side with less resting quantity, where the price is more
+    /// likely to move next.
+    pub fn microprice(&self, tick_size: TickSize) -> f64 {
+        let total = self.bid_quantity + self.ask_quantity;
+        if total <= 0.0 {
+            return self.mid(tick_size);
+        }
+        (self.best_bid.to_f64(tick_size) * self.ask_quantity + self.best_ask.to_f64(tick_size) * self.bid_quantity) / total
+    }
+}
+
+/// How `CanvasOrderbook` draws the book.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    /// A bar per level, scaled to the largest level.
+    Levels,
+    /// Running sum from the best price outward.
+    Cumulative,
+}
+impl std::str::FromStr for DepthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "levels" => Ok(DepthMode::Levels),
+            "cumulative" => Ok(DepthMode::Cumulative),
+            _ => Err(format!("Unsupported depth mode: {}", s)),
+        }
+    }
+}
+
+/// Distances from mid, in percent, that liquidity is summed within.
+pub const LIQUIDITY_BANDS: [f64; 3] = [0.5, 1.0, 2.0];
+
+/// Resting quantity on each side within `percent` of mid.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BookLiquidity {
+    pub percent: f64,
+    pub bids: f64,
+    pub asks: f64,
+}
+impl BookLiquidity {
+    /// `(bids - asks) / (bids + asks)`, from -1 with only asks to 1 with only bids.
+    pub fn imbalance(&self) -> f64 {
+        let total = self.bids + self.asks;
+        if total > 0.0 { (self.bids - self.asks) / total } else { 0.0 }
+    }
+}
+
+/// Liquidity within each of `LIQUIDITY_BANDS`, empty while either side of the book is.
+/// Prices are in ticks, so percentages of mid work on them directly.
+pub fn liquidity_bands(bids: &PriceLevels, asks: &PriceLevels) -> Vec<BookLiquidity> {
+    let mid = match (bids.best(), asks.best()) {
+        (Some((best_bid, _)), Some((best_ask, _))) => (best_bid.ticks() as f64 + best_ask.ticks() as f64) / 2.0,
+        _ => return Vec::new(),
+    };
+
+    LIQUIDITY_BANDS.iter().map(|&percent| {
+        let band = mid * percent / 100.0;
+        let low = Price::from_ticks((mid - band).ceil() as i64);
+        let high = Price::from_ticks((mid + band).floor() as i64);
+        BookLiquidity {
+            percent,
+            bids: bids.range(low..=Price::MAX).map(|(_, quantity)| quantity).sum(),
+            asks: asks.range(Price::MIN..=high).map(|(_, quantity)| quantity).sum(),
+        }
+    }).collect()
+}
+
+/// Sums the levels within `range` into buckets of `bucket_ticks`, keyed by the bucket's price.
+pub fn group_orders(levels: &PriceLevels, range: RangeInclusive<Price>, bucket_ticks: i64) -> PriceLevels {
+    let mut grouped: BTreeMap<Price, f64> = BTreeMap::new();
+    for (price, quantity) in levels.range(range) {
+        *grouped.entry(price.bucket(bucket_ticks)).or_insert(0.0) += quantity;
+    }
+    let mut grouped_levels = PriceLevels::new(levels.side());
+    for (price, quantity) in grouped {
+        grouped_levels.update(price, quantity);
+    }
+    grouped_levels
+}
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BookSyncState {
+    /// No snapshot applied yet, stream diffs are only buffered.
+    AwaitingSnapshot,
+    Synced,
+    /// A gap in the update ids was detected, the book is stale until the next snapshot.
+    OutOfSync,
+}
+
+struct DepthSync {
+    state: BookSyncState,
+    last_update_id: u64,
+    /// Whether a diff overlapping the snapshot's `last_update_id` has been applied yet.
+    bridged: bool,
+    buffered_updates: VecDeque<DepthUpdate>,
+}
+
+/// Local order book kept in sync following the snapshot + diff procedure:
+/// diffs are buffered until a snapshot arrives, the ones older than the snapshot
+/// are dropped, and every applied diff must continue from the previous one.
+///
+/// Not `Clone`, as a copy would share the locked levels; `with_levels` builds an independent book.
+pub struct OrderbookManager {
+    pub(crate) bids: Arc<RwLock<PriceLevels>>,
+    pub(crate) asks: Arc<RwLock<PriceLevels>>,
+    sync: Arc<RwLock<DepthSync>>,
+}
+impl Default for OrderbookManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderbookManager {
+    pub fn new() -> Self {
+        Self {
+            bids: Arc::new(RwLock::new(PriceLevels::new(Side::Bid))),
+            asks: Arc::new(RwLock::new(PriceLevels::new(Side::Ask))),
+            sync: Arc::new(RwLock::new(DepthSync {
+                state: BookSyncState::AwaitingSnapshot,
+                last_update_id: 0,
+                bridged: false,
+                buffered_updates: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// A synced book holding just `bids` and `asks`, e.g. a recorded snapshot.
+    pub fn with_levels(bids: &[(Price, f64)], asks: &[(Price, f64)]) -> Self {
+        let mut bid_levels = PriceLevels::new(Side::Bid);
+        for &(price, quantity) in bids {
+            bid_levels.update(price, quantity);
+        }
+        let mut ask_levels = PriceLevels::new(Side::Ask);
+        for &(price, quantity) in asks {
+            ask_levels.update(price, quantity);
+        }
+        Self {
+            bids: Arc::new(RwLock::new(bid_levels)),
+            asks: Arc::new(RwLock::new(ask_levels)),
+            sync: Arc::new(RwLock::new(DepthSync {
+                state: BookSyncState::Synced,
+                last_update_id: 0,
+                bridged: false,
+                buffered_updates: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn sync_state(&self) -> BookSyncState {
+        match self.sync.read() {
+            Ok(sync) => sync.state,
+            Err(_) => BookSyncState::OutOfSync,
+        }
+    }
+
+    pub fn has_book(&self) -> bool {
+        let has_bids = self.bids.read().map(|bids| !bids.is_empty()).unwrap_or(false);
+        let has_asks = self.asks.read().map(|asks| !asks.is_empty()).unwrap_or(false);
+        has_bids || has_asks
+    }
+
+    pub fn reset(&self) {
+        match self.sync.write() {
+            Ok(mut sync) => {
+                sync.state = BookSyncState::AwaitingSnapshot;
+                sync.last_update_id = 0;
+                sync.bridged = false;
+                sync.buffered_updates.clear();
+            },
+            Err(e) => log(&format!("Failed to acquire write lock on depth sync during reset: {}", e)),
+        }
+        if let Ok(mut bids) = self.bids.write() {
+            bids.clear();
+        }
+        if let Ok(mut asks) = self.asks.write() {
+            asks.clear();
+        }
+    }
+
+    /// Replaces the book with `snapshot` and replays the buffered diffs that came after it.
+    /// Returns `true` if the buffered diffs don't line up with the snapshot and another one is needed.
+    pub fn fetch_depth(&self, snapshot: DepthSnapshot) -> bool {
+        let mut sync = match self.sync.write() {
+            Ok(sync) => sync,
+            Err(e) => {
+                log(&format!("Failed to acquire write lock on depth sync: {}", e));
+                return false;
+            }
+        };
+        if let Ok(mut bids_borrowed) = self.bids.write() {
+            bids_borrowed.clear();
+            for bid in &snapshot.bids {
+                bids_borrowed.update(bid.price, bid.quantity);
+            }
+        } else {
+            log("bids locked on render");
+        }
+        if let Ok(mut asks_borrowed) = self.asks.write() {
+            asks_borrowed.clear();
+            for ask in &snapshot.asks {
+                asks_borrowed.update(ask.price, ask.quantity);
+            }
+        } else {
+            log("asks locked on render");
+        }
+        sync.state = BookSyncState::Synced;
+        sync.last_update_id = snapshot.last_update_id;
+        sync.bridged = false;
+
+        let pending: Vec<DepthUpdate> = sync.buffered_updates.iter()
+            .filter(|update| update.final_update_id >= snapshot.last_update_id)
+            .cloned()
+            .collect();
+        for update in &pending {
+            if !self.advance(&mut sync, update) {
+                sync.state = BookSyncState::OutOfSync;
+                log(&format!("Depth snapshot {} does not line up with buffered diffs", snapshot.last_update_id));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Buffers and, once synced, applies a stream diff.
+    /// Returns `true` when this diff revealed a gap, meaning a new snapshot has to be requested.
+    pub fn apply_depth_update(&self, update: DepthUpdate) -> bool {
+        let mut sync = match self.sync.write() {
+            Ok(sync) => sync,
+            Err(e) => {
+                log(&format!("Failed to acquire write lock on depth sync: {}", e));
+                return false;
+            }
+        };
+        if sync.buffered_updates.len() >= MAX_BUFFERED_UPDATES {
+            sync.buffered_updates.pop_front();
+        }
+        sync.buffered_updates.push_back(update.clone());
+
+        if sync.state != BookSyncState::Synced {
+            return false;
+        }
+        if !self.advance(&mut sync, &update) {
+            sync.state = BookSyncState::OutOfSync;
+            log(&format!("Gap in depth updates after {}, got {}..{}", sync.last_update_id, update.first_update_id, update.final_update_id));
+            return true;
+        }
+        false
+    }
+
+    /// Applies `update` if it continues the sequence. Stale diffs are skipped; returns `false` on a gap.
+    /// As for USDⓈ-M futures, the first diff applied has to span the snapshot's `lastUpdateId`
+    /// (`U <= lastUpdateId <= u`), later ones have to chain through `pu`.
+    fn advance(&self, sync: &mut DepthSync, update: &DepthUpdate) -> bool {
+        if !sync.bridged {
+            if update.final_update_id < sync.last_update_id {
+                return true;
+            }
+            if update.first_update_id > sync.last_update_id {
+                return false;
+            }
+            sync.bridged = true;
+        } else if !update.follows(sync.last_update_id) {
+            return false;
+        }
+        self.apply_levels(update);
+        sync.last_update_id = update.final_update_id;
+        true
+    }
+
+    /// Levels beyond the deepest one in the snapshot are ignored, the stream would otherwise
+    /// leave isolated levels far from the range the snapshot covers.
+    fn apply_levels(&self, update: &DepthUpdate) {
+        if let Ok(mut bids_borrowed) = self.bids.write() {
+            let min_price = bids_borrowed.worst().map_or(Price::MIN, |(price, _)| price);
+            for bid in update.bids.iter().filter(|x| x.price >= min_price) {
+                bids_borrowed.update(bid.price, bid.quantity);
+            }
+        } else {
+            log("bids locked on render");
+        }
+        if let Ok(mut asks_borrowed) = self.asks.write() {
+            let max_price = asks_borrowed.worst().map_or(Price::MAX, |(price, _)| price);
+            for ask in update.asks.iter().filter(|x| x.price <= max_price) {
+                asks_borrowed.update(ask.price, ask.quantity);
+            }
+        } else {
+            log("asks locked on render");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(side: Side, levels: &[(i64, f64)]) -> PriceLevels {
+        let mut price_levels = PriceLevels::new(side);
+        for &(ticks, quantity) in levels {
+            price_levels.update(Price::from_ticks(ticks), quantity);
+        }
+        price_levels
+    }
+
+    fn top_of_book(best_bid: i64, bid_quantity: f64, best_ask: i64, ask_quantity: f64) -> TopOfBook {
+        TopOfBook::new(0, &levels(Side::Bid, &[(best_bid, bid_quantity)]), &levels(Side::Ask, &[(best_ask, ask_quantity)])).unwrap()
+    }
+
+    #[test]
+    fn top_of_book_needs_both_sides() {
+        let bids = levels(Side::Bid, &[(999, 1.0), (998, 5.0)]);
+        let asks = levels(Side::Ask, &[(1001, 2.0)]);
+        assert_eq!(TopOfBook::new(7, &bids, &asks), Some(TopOfBook { time: 7, best_bid: Price::from_ticks(999), bid_quantity: 1.0, best_ask: Price::from_ticks(1001), ask_quantity: 2.0 }));
+        assert_eq!(TopOfBook::new(7, &bids, &PriceLevels::new(Side::Ask)), None);
+        assert_eq!(TopOfBook::new(7, &PriceLevels::new(Side::Bid), &asks), None);
+    }
+
+    #[test]
+    fn spread_in_ticks_and_bps_of_mid() {
+        let quoted = top_of_book(999, 1.0, 1001, 1.0);
+        assert_eq!(quoted.spread_ticks(), 2);
+        assert_eq!(quoted.spread_bps(), 20.0);
+        assert_eq!(quoted.mid(TickSize::parse("0.1").unwrap()), 100.0);
+    }
+
+    #[test]
+    fn crossed_book_has_a_negative_spread() {
+        let crossed = top_of_book(1001, 1.0, 999, 1.0);
+        assert_eq!(crossed.spread_ticks(), -2);
+        assert_eq!(crossed.spread_bps(), -20.0);
+        assert_eq!(top_of_book(1000, 1.0, 1000, 1.0).spread_bps(), 0.0);
+        assert_eq!(top_of_book(0, 1.0, 0, 1.0).spread_bps(), 0.0);
+    }
+
+    #[test]
+    fn microprice_leans_towards_the_thinner_side() {
+        let tick_size = TickSize::parse("0.1").unwrap();
+        // three times the quantity on the bid puts it three quarters of the way to the ask
+        assert!((top_of_book(999, 3.0, 1001, 1.0).microprice(tick_size) - 100.05).abs() < 1e-9);
+        assert!((top_of_book(999, 1.0, 1001, 3.0).microprice(tick_size) - 99.95).abs() < 1e-9);
+        assert!((top_of_book(999, 2.0, 1001, 2.0).microprice(tick_size) - 100.0).abs() < 1e-9);
+        // without quantity it falls back to mid
+        let empty = TopOfBook { bid_quantity: 0.0, ask_quantity: 0.0, ..top_of_book(999, 1.0, 1001, 1.0) };
+        assert!((empty.microprice(tick_size) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn liquidity_bands_sum_each_side_within_the_band() {
+        let bids = levels(Side::Bid, &[(995, 1.0), (990, 2.0), (980, 4.0)]);
+        let asks = levels(Side::Ask, &[(1000, 1.0), (1005, 3.0), (1020, 5.0)]);
+        // mid is 997.5 ticks, so the bands reach 4.99, 9.98 and 19.95 ticks either way
+        assert_eq!(liquidity_bands(&bids, &asks), vec![
+            BookLiquidity { percent: 0.5, bids: 1.0, asks: 1.0 },
+            BookLiquidity { percent: 1.0, bids: 3.0, asks: 4.0 },
+            BookLiquidity { percent: 2.0, bids: 7.0, asks: 4.0 },
+        ]);
+        assert_eq!(liquidity_bands(&bids, &asks)[2].imbalance(), 3.0 / 11.0);
+    }
+
+    #[test]
+    fn liquidity_bands_include_levels_on_the_edge() {
+        // mid 1000, 1% reaches exactly 990 and 1010
+        let bids = levels(Side::Bid, &[(999, 1.0), (990, 2.0), (989, 4.0)]);
+        let asks = levels(Side::Ask, &[(1001, 1.0), (1010, 2.0), (1011, 4.0)]);
+        let bands = liquidity_bands(&bids, &asks);
+        assert_eq!((bands[1].bids, bands[1].asks), (3.0, 3.0));
+        assert_eq!(bands[1].imbalance(), 0.0);
+    }
+
+    #[test]
+    fn liquidity_bands_need_both_sides() {
+        let bids = levels(Side::Bid, &[(995, 1.0)]);
+        assert!(liquidity_bands(&bids, &PriceLevels::new(Side::Ask)).is_empty());
+        assert!(liquidity_bands(&PriceLevels::new(Side::Bid), &PriceLevels::new(Side::Ask)).is_empty());
+        assert_eq!(BookLiquidity { percent: 1.0, bids: 0.0, asks: 0.0 }.imbalance(), 0.0);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+
+/// Minimum price increment of a symbol, kept as an exact decimal `units * 10^-decimals`
+/// so tick arithmetic never goes through binary floats.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TickSize {
+    units: i64,
+    decimals: u32,
+}
+impl Default for TickSize {
+    fn default() -> Self {
+        Self { units: 1, decimals: 1 }
+    }
+}
+impl TickSize {
+    /// Parses a decimal string such as `"0.00001"` or `"0.5"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (scaled, decimals) = parse_decimal(s)?;
+        if scaled <= 0 {
+            return None;
+        }
+        // strip trailing zeros so "0.010" and "0.01" compare equal
+        let (mut units, mut decimals) = (scaled, decimals);
+        while decimals > 0 && units % 10 == 0 {
+            units /= 10;
+            decimals -= 1;
+        }
+        Some(Self { units: i64::try_from(units).ok()?, decimals })
+    }
+    /// `f64` display output is the shortest string that round-trips, which is the decimal the exchange sent.
+    pub fn from_f64(tick_size: f64) -> Option<Self> {
+        if !tick_size.is_finite() {
+            return None;
+        }
+        Self::parse(&tick_size.to_string())
+    }
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+    pub fn as_f64(&self) -> f64 {
+        self.units as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+impl fmt::Display for TickSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_scaled(self.units as i128, self.decimals))
+    }
+}
+
+/// A price as a whole number of ticks. Only meaningful together with the `TickSize` it was created with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(i64);
+impl Price {
+    pub const MIN: Price = Price(i64::MIN);
+    pub const MAX: Price = Price(i64::MAX);
+
+    pub fn from_ticks(ticks: i64) -> Self {
+        Self(ticks)
+    }
+    pub fn ticks(self) -> i64 {
+        self.0
+    }
+
+    /// Parses a decimal price string exactly, rounding half away from zero to the nearest tick.
+    /// Values too large or too precise to scale to the tick size are rejected.
+    pub fn parse(s: &str, tick_size: TickSize) -> Option<Self> {
+        let (scaled, decimals) = parse_decimal(s)?;
+        let target = tick_size.decimals;
+        let scaled = if decimals > target {
+            round_div(scaled, 10i128.checked_pow(decimals - target)?)
+        } else {
+            scaled.checked_mul(10i128.checked_pow(target - decimals)?)?
+        };
+        let ticks = round_div(scaled, tick_size.units as i128);
+        i64::try_from(ticks).ok().map(Self)
+    }
+    pub fn from_f64(price: f64, tick_size: TickSize) -> Option<Self> {
+        if !price.is_finite() {
+            return None;
+        }
+        Self::parse(&price.to_string(), tick_size)
+    }
+    pub fn to_f64(self, tick_size: TickSize) -> f64 {
+        (self.0 as f64 * tick_size.units as f64) / 10f64.powi(tick_size.decimals as i32)
+    }
+    /// Exact decimal representation with as many decimals as the tick size has.
+    pub fn format(self, tick_size: TickSize) -> String {
+        format_scaled(self.0 as i128 * tick_size.units as i128, tick_size.decimals)
+    }
+
+    /// Rounds to the nearest multiple of `bucket_ticks`.
+    pub fn bucket(self, bucket_ticks: i64) -> Self {
+        let bucket_ticks = bucket_ticks.max(1);
+        Self((self.0 + bucket_ticks / 2).div_euclid(bucket_ticks) * bucket_ticks)
+    }
+    pub fn offset(self, ticks: i64) -> Self {
+        Self(self.0.saturating_add(ticks))
+    }
+}
+
+/// Parses an optionally signed decimal into `(digits without the point, number of decimals)`.
+fn parse_decimal(s: &str) -> Option<(i128, u32)> {
+    let s = s.trim();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (int_part, frac_part) = s.split_once('.').unwrap_or((s, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+    if !int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let frac_part = frac_part.trim_end_matches('0');
+    let mut scaled: i128 = 0;
+    for c in int_part.chars().chain(frac_part.chars()) {
+        scaled = scaled.checked_mul(10)?.checked_add(c.to_digit(10)? as i128)?;
+    }
+    Some((if negative { -scaled } else { scaled }, frac_part.len() as u32))
+}
+
+/// Integer division rounding half away from zero.
+fn round_div(n: i128, d: i128) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    // `2 * r` could overflow for divisors near the top of the range
+    if r.abs() >= d.abs() - r.abs() {
+        q + n.signum() * d.signum()
+    } else {
+        q
+    }
+}
+
+fn format_scaled(scaled: i128, decimals: u32) -> String {
+    let sign = if scaled < 0 { "-" } else { "" };
+    let scaled = scaled.unsigned_abs();
+    if decimals == 0 {
+        return format!("{}{}", sign, scaled);
+    }
+    let divisor = 10u128.pow(decimals);
+    format!("{}{}.{:0width$}", sign, scaled / divisor, scaled % divisor, width = decimals as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rounds_to_ticks() {
+        let tick_size = TickSize::parse("0.5").unwrap();
+        assert_eq!(Price::parse("100.25", tick_size), Some(Price(201)));
+        assert_eq!(Price::parse("100.2", tick_size), Some(Price(200)));
+        assert_eq!(Price::parse("-0.25", tick_size), Some(Price(-1)));
+        assert_eq!(Price::parse("abc", tick_size), None);
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_input() {
+        let tick_size = TickSize::parse("0.1").unwrap();
+        assert_eq!(Price::parse("0.0000000000000000000000000000000000000001", tick_size), None);
+        assert_eq!(Price::parse("0.00000000000000000000000000000000000000000000000001", tick_size), None);
+        assert_eq!(Price::parse("99999999999999999999999999999999999", tick_size), None);
+
+        let fine_tick = TickSize::parse("0.00000000000000000000000000001").unwrap();
+        assert_eq!(Price::parse("123456789012345", fine_tick), None);
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{TickSize, Timeframe};
+
+/// First line of every recording, followed by the symbol, timeframe and tick size.
+const HEADER: &str = "#flowsurface-recording v1";
+/// Entries applied per frame at max speed, so the page stays responsive.
+const MAX_ENTRIES_PER_FRAME: usize = 2000;
+
+/// Which input a recorded payload was passed to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+    /// A raw websocket message.
+    Stream,
+    /// REST depth snapshot, `gather_depth`.
+    Depth,
+    /// REST klines, `gather_klines`.
+    Klines,
+    /// Historical trades of the kline opening at this time, `gather_hist_trades`.
+    HistTrades(u64),
+}
+impl RecordKind {
+    fn tag(&self) -> String {
+        match self {
+            RecordKind::Stream => "s".to_string(),
+            RecordKind::Depth => "d".to_string(),
+            RecordKind::Klines => "k".to_string(),
+            RecordKind::HistTrades(kline_open) => format!("t{}", kline_open),
+        }
+    }
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "s" => Some(RecordKind::Stream),
+            "d" => Some(RecordKind::Depth),
+            "k" => Some(RecordKind::Klines),
+            _ => tag.strip_prefix('t')?.parse().ok().map(RecordKind::HistTrades),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedMessage {
+    /// When the payload was received, ms since the epoch.
+    pub time: u64,
+    pub kind: RecordKind,
+    pub data: String,
+}
+
+/// Raw inputs of one session, in the order they were received.
+///
+/// Serialized as a header line and then one line per message: the ms since the previous
+/// message, the kind's tag and the payload, separated by tabs. Line breaks in payloads are
+/// replaced by spaces, which JSON treats the same.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub symbol: String,
+    pub timeframe: Timeframe,
+    pub tick_size: TickSize,
+    pub messages: Vec<RecordedMessage>,
+}
+impl Recording {
+    pub fn new(symbol: &str, timeframe: Timeframe, tick_size: TickSize) -> Self {
+        Self { symbol: symbol.to_string(), timeframe, tick_size, messages: Vec::new() }
+    }
+
+    pub fn push(&mut self, time: u64, kind: RecordKind, data: &str) {
+        // the clock may step back, keep messages in receive order regardless
+        let time = self.messages.last().map_or(time, |last| time.max(last.time));
+        self.messages.push(RecordedMessage { time, kind, data: data.to_string() });
+    }
+
+    pub fn start_time(&self) -> u64 {
+        self.messages.first().map_or(0, |message| message.time)
+    }
+    pub fn duration(&self) -> u64 {
+        self.messages.last().map_or(0, |message| message.time - self.start_time())
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!("{}\t{}\t{}\t{}\n", HEADER, self.symbol, self.timeframe.as_str(), self.tick_size);
+        let mut previous = 0;
+        for message in &self.messages {
+            let delta = if previous == 0 { message.time } else { message.time - previous };
+            previous = message.time;
+            out.push_str(&format!("{}\t{}\t{}\n", delta, message.kind.tag(), message.data.replace(['\n', '\r'], " ")));
+        }
+        out
+    }
+
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let mut lines = data.lines();
+        let header: Vec<&str> = lines.next().unwrap_or_default().split('\t').collect();
+        let (symbol, timeframe, tick_size) = match header.as_slice() {
+            [HEADER, symbol, timeframe, tick_size] => (symbol, timeframe, tick_size),
+            _ => return Err("Not a recording".to_string()),
+        };
+        let timeframe = timeframe.parse::<Timeframe>()?;
+        let tick_size = TickSize::parse(tick_size).ok_or(format!("Invalid tick size in recording: {}", tick_size))?;
+
+        let mut recording = Self::new(symbol, timeframe, tick_size);
+        let mut time = 0;
+        for (i, line) in lines.enumerate().filter(|(_, line)| !line.is_empty()) {
+            let mut fields = line.splitn(3, '\t');
+            let (delta, kind, data) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(delta), Some(tag), Some(data)) => (delta.parse::<u64>().ok(), RecordKind::from_tag(tag), data),
+                _ => (None, None, ""),
+            };
+            match (delta, kind) {
+                (Some(delta), Some(kind)) => {
+                    time += delta;
+                    recording.messages.push(RecordedMessage { time, kind, data: data.to_string() });
+                },
+                _ => return Err(format!("Malformed recording line {}", i + 2)),
+            }
+        }
+        Ok(recording)
+    }
+}
+
+/// Where the loaded market data came from, so a (re)started stream knows whether it can keep it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DataSource {
+    #[default]
+    Empty,
+    /// The live stream of a symbol, with its REST history.
+    Live(String),
+    /// A played back recording, possibly of another symbol and ending wherever it stopped, or
+    /// live data whose stream a bar replay closed.
+    Replay,
+}
+impl DataSource {
+    /// Whether the data has to be dropped before streaming `symbol`.
+    pub fn is_stale_for(&self, symbol: &str) -> bool {
+        match self {
+            DataSource::Empty => false,
+            DataSource::Live(live_symbol) => live_symbol != symbol,
+            DataSource::Replay => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplaySpeed {
+    X1,
+    X5,
+    /// As fast as frames allow.
+    Max,
+}
+impl std::str::FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1x" => Ok(ReplaySpeed::X1),
+            "5x" => Ok(ReplaySpeed::X5),
+            "max" => Ok(ReplaySpeed::Max),
+            _ => Err(format!("Unsupported replay speed: {}", s)),
+        }
+    }
+}
+
+/// Plays a recording back against wall-clock time.
+pub struct Replay {
+    pub recording: Recording,
+    speed: ReplaySpeed,
+    paused: bool,
+    /// Index of the next message to apply.
+    next: usize,
+    /// Position in the recording, ms since its start.
+    position: u64,
+    last_tick: Option<u64>,
+}
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self { recording, speed: ReplaySpeed::X1, paused: false, next: 0, position: 0, last_tick: None }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.messages.len()
+    }
+
+    pub fn set_speed(&mut self, speed: ReplaySpeed) {
+        self.speed = speed;
+    }
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.last_tick = None;
+    }
+
+    /// Messages due since the last call at `now`.
+    pub fn advance(&mut self, now: u64) -> &[RecordedMessage] {
+        let elapsed = self.last_tick.map_or(0, |last_tick| now.saturating_sub(last_tick));
+        self.last_tick = Some(now);
+        if self.paused || self.is_finished() {
+            return &[];
+        }
+        let start_time = self.recording.start_time();
+        let from = self.next;
+        match self.speed {
+            ReplaySpeed::Max => {
+                self.next = (self.next + MAX_ENTRIES_PER_FRAME).min(self.recording.messages.len());
+                self.position = self.recording.messages[self.next - 1].time - start_time;
+            },
+            ReplaySpeed::X1 | ReplaySpeed::X5 => {
+                let factor = if self.speed == ReplaySpeed::X5 { 5 } else { 1 };
+                self.position = (self.position + elapsed * factor).min(self.recording.duration());
+                let until = start_time + self.position;
+                self.next += self.recording.messages[self.next..].partition_point(|message| message.time <= until);
+            },
+        }
+        &self.recording.messages[from..self.next]
+    }
+
+    /// Moves to `position` ms into the recording. Returns the messages to apply on top of the
+    /// current state, and whether that state has to be cleared first, as when seeking back.
+    pub fn seek(&mut self, position: u64) -> (bool, &[RecordedMessage]) {
+        let position = position.min(self.recording.duration());
+        let rewind = position < self.position;
+        if rewind {
+            self.next = 0;
+        }
+        self.position = position;
+        self.last_tick = None;
+
+        let from = self.next;
+        let until = self.recording.start_time() + position;
+        self.next += self.recording.messages[self.next..].partition_point(|message| message.time <= until);
+        (rewind, &self.recording.messages[from..self.next])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(times: &[u64]) -> Recording {
+        let mut recording = Recording::new("BTCUSDT", Timeframe::M5, TickSize::parse("0.1").unwrap());
+        for (i, &time) in times.iter().enumerate() {
+            recording.push(time, RecordKind::Stream, &i.to_string());
+        }
+        recording
+    }
+    fn data(messages: &[RecordedMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.data.as_str()).collect()
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let mut recording = recording(&[1_000]);
+        recording.push(1_500, RecordKind::Depth, "{\"a\":\n1}");
+        // an earlier clock reading keeps the receive order
+        recording.push(1_400, RecordKind::Klines, "[]");
+        recording.push(3_000, RecordKind::HistTrades(60_000), "[\t]");
+
+        let serialized = recording.serialize();
+        assert_eq!(serialized, "#flowsurface-recording v1\tBTCUSDT\t5m\t0.1\n1000\ts\t0\n500\td\t{\"a\": 1}\n0\tk\t[]\n1500\tt60000\t[\t]\n");
+
+        let parsed = Recording::parse(&serialized).unwrap();
+        assert_eq!((parsed.symbol.as_str(), parsed.timeframe, parsed.tick_size), ("BTCUSDT", Timeframe::M5, recording.tick_size));
+        let times: Vec<u64> = parsed.messages.iter().map(|message| message.time).collect();
+        assert_eq!(times, vec![1_000, 1_500, 1_500, 3_000]);
+        let kinds: Vec<RecordKind> = parsed.messages.iter().map(|message| message.kind.clone()).collect();
+        assert_eq!(kinds, vec![RecordKind::Stream, RecordKind::Depth, RecordKind::Klines, RecordKind::HistTrades(60_000)]);
+        assert_eq!(data(&parsed.messages), vec!["0", "{\"a\": 1}", "[]", "[\t]"]);
+        assert_eq!(parsed.duration(), 2_000);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_recordings() {
+        assert_eq!(Recording::parse("").unwrap_err(), "Not a recording");
+        assert_eq!(Recording::parse("#flowsurface-recording v2\tBTCUSDT\t1m\t0.1").unwrap_err(), "Not a recording");
+        assert!(Recording::parse("#flowsurface-recording v1\tBTCUSDT\t2m\t0.1").is_err());
+        assert!(Recording::parse("#flowsurface-recording v1\tBTCUSDT\t1m\tx").is_err());
+
+        let header = "#flowsurface-recording v1\tBTCUSDT\t1m\t0.1\n";
+        assert_eq!(Recording::parse(&format!("{}1\ts\t{{}}\n\nx\ts\t{{}}\n", header)).unwrap_err(), "Malformed recording line 4");
+        assert_eq!(Recording::parse(&format!("{}1\tq\t{{}}\n", header)).unwrap_err(), "Malformed recording line 2");
+        assert_eq!(Recording::parse(&format!("{}1\ts\n", header)).unwrap_err(), "Malformed recording line 2");
+        assert!(Recording::parse(header).unwrap().messages.is_empty());
+    }
+
+    #[test]
+    fn advance_follows_speed_and_pause() {
+        let mut replay = Replay::new(recording(&[1_000, 1_100, 1_600, 3_000]));
+        // the first frame only starts the clock
+        assert_eq!(data(replay.advance(10_000)), vec!["0"]);
+        assert_eq!(data(replay.advance(10_100)), vec!["1"]);
+        replay.set_speed(ReplaySpeed::X5);
+        assert_eq!(data(replay.advance(10_200)), vec!["2"]);
+        assert_eq!(replay.position(), 600);
+
+        replay.set_paused(true);
+        assert!(replay.advance(20_000).is_empty());
+        replay.set_paused(false);
+        // time spent paused doesn't count
+        assert!(replay.advance(20_100).is_empty());
+        assert_eq!(replay.position(), 600);
+
+        replay.set_speed(ReplaySpeed::Max);
+        assert_eq!(data(replay.advance(20_116)), vec!["3"]);
+        assert_eq!(replay.position(), 2_000);
+        assert!(replay.is_finished());
+        assert!(replay.advance(30_000).is_empty());
+    }
+
+    #[test]
+    fn seeking_back_rewinds() {
+        let mut replay = Replay::new(recording(&[1_000, 1_100, 1_600, 3_000]));
+        let (rewind, messages) = replay.seek(200);
+        assert!(!rewind);
+        assert_eq!(data(messages), vec!["0", "1"]);
+
+        let (rewind, messages) = replay.seek(700);
+        assert!(!rewind);
+        assert_eq!(data(messages), vec!["2"]);
+
+        // back to the start, so everything up to the position is applied again from scratch
+        let (rewind, messages) = replay.seek(150);
+        assert!(rewind);
+        assert_eq!(data(messages), vec!["0", "1"]);
+
+        let (rewind, messages) = replay.seek(10_000);
+        assert!(!rewind);
+        assert_eq!(data(messages), vec!["2", "3"]);
+        assert_eq!(replay.position(), 2_000);
+    }
+
+    #[test]
+    fn replayed_data_is_dropped_when_going_live() {
+        assert!(!DataSource::Empty.is_stale_for("BTCUSDT"));
+        assert!(!DataSource::Live("BTCUSDT".to_string()).is_stale_for("BTCUSDT"));
+        assert!(DataSource::Live("BTCUSDT".to_string()).is_stale_for("ETHUSDT"));
+        // even a recording of the same symbol ends wherever it stopped, and a bar replay
+        // left a gap since the stream was closed
+        assert!(DataSource::Replay.is_stale_for("BTCUSDT"));
+    }
+}
@@ -0,0 +1,177 @@
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, window};
+
+/// Drawing operations the canvases need, so renderers don't depend on a browser context.
+pub trait Surface {
+    fn dpi(&self) -> f64;
+    fn clear(&mut self, width: f64, height: f64);
+
+    fn set_fill_style(&mut self, color: &str);
+    fn set_stroke_style(&mut self, color: &str);
+    fn set_line_width(&mut self, width: f64);
+    fn set_font_size(&mut self, size: f64);
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64);
+    /// Strokes a single segment with the current stroke style.
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64);
+    /// Fills a circle with the current fill style.
+    fn arc(&mut self, x: f64, y: f64, radius: f64);
+    /// Fills the closed shape through `points` with the current fill style.
+    fn fill_polygon(&mut self, points: &[(f64, f64)]);
+    fn fill_text(&mut self, text: &str, x: f64, y: f64);
+    fn measure_text(&mut self, text: &str) -> f64;
+}
+
+pub struct WebSurface {
+    ctx: CanvasRenderingContext2d,
+}
+impl WebSurface {
+    pub fn new(canvas: &HtmlCanvasElement) -> Result<Self, JsValue> {
+        match canvas.get_context("2d") {
+            Ok(Some(context)) => Ok(Self {
+                ctx: context.dyn_into::<CanvasRenderingContext2d>()?,
+            }),
+            Ok(None) => Err(JsValue::from_str("No 2D context available")),
+            Err(error) => Err(error),
+        }
+    }
+}
+impl Surface for WebSurface {
+    fn dpi(&self) -> f64 {
+        window().map(|w| w.device_pixel_ratio()).unwrap_or(1.0)
+    }
+    fn clear(&mut self, width: f64, height: f64) {
+        self.ctx.clear_rect(0.0, 0.0, width, height);
+    }
+
+    fn set_fill_style(&mut self, color: &str) {
+        self.ctx.set_fill_style_str(color);
+    }
+    fn set_stroke_style(&mut self, color: &str) {
+        self.ctx.set_stroke_style_str(color);
+    }
+    fn set_line_width(&mut self, width: f64) {
+        self.ctx.set_line_width(width);
+    }
+    fn set_font_size(&mut self, size: f64) {
+        self.ctx.set_font(&format!("{}px monospace", size));
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.ctx.fill_rect(x, y, width, height);
+    }
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64) {
+        self.ctx.begin_path();
+        self.ctx.move_to(x1, y1);
+        self.ctx.line_to(x2, y2);
+        self.ctx.stroke();
+    }
+    fn arc(&mut self, x: f64, y: f64, radius: f64) {
+        self.ctx.begin_path();
+        if self.ctx.arc(x, y, radius, 0.0, 2.0 * std::f64::consts::PI).is_ok() {
+            self.ctx.fill();
+        }
+    }
+    fn fill_polygon(&mut self, points: &[(f64, f64)]) {
+        let (first, rest) = match points.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        self.ctx.begin_path();
+        self.ctx.move_to(first.0, first.1);
+        for (x, y) in rest {
+            self.ctx.line_to(*x, *y);
+        }
+        self.ctx.close_path();
+        self.ctx.fill();
+    }
+    fn fill_text(&mut self, text: &str, x: f64, y: f64) {
+        let _ = self.ctx.fill_text(text, x, y);
+    }
+    fn measure_text(&mut self, text: &str) -> f64 {
+        self.ctx.measure_text(text).map(|m| m.width()).unwrap_or(0.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Clear { width: f64, height: f64 },
+    FillRect { x: f64, y: f64, width: f64, height: f64, color: String },
+    Line { x1: f64, y1: f64, x2: f64, y2: f64, color: String, width: f64 },
+    Arc { x: f64, y: f64, radius: f64, color: String },
+    Polygon { points: Vec<(f64, f64)>, color: String },
+    Text { text: String, x: f64, y: f64, font_size: f64, color: String },
+}
+
+/// In-memory surface that records every draw call along with the style it was drawn with.
+/// Text is measured as monospace, `0.6 * font_size` per character.
+#[derive(Debug)]
+pub struct RecordingSurface {
+    dpi: f64,
+    fill_style: String,
+    stroke_style: String,
+    line_width: f64,
+    font_size: f64,
+    pub commands: Vec<DrawCommand>,
+}
+impl RecordingSurface {
+    pub fn new(dpi: f64) -> Self {
+        Self {
+            dpi,
+            fill_style: "black".to_string(),
+            stroke_style: "black".to_string(),
+            line_width: 1.0,
+            font_size: 10.0,
+            commands: Vec::new(),
+        }
+    }
+    pub fn set_dpi(&mut self, dpi: f64) {
+        self.dpi = dpi;
+    }
+}
+impl Default for RecordingSurface {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+impl Surface for RecordingSurface {
+    fn dpi(&self) -> f64 {
+        self.dpi
+    }
+    fn clear(&mut self, width: f64, height: f64) {
+        self.commands.clear();
+        self.commands.push(DrawCommand::Clear { width, height });
+    }
+
+    fn set_fill_style(&mut self, color: &str) {
+        self.fill_style = color.to_string();
+    }
+    fn set_stroke_style(&mut self, color: &str) {
+        self.stroke_style = color.to_string();
+    }
+    fn set_line_width(&mut self, width: f64) {
+        self.line_width = width;
+    }
+    fn set_font_size(&mut self, size: f64) {
+        self.font_size = size;
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.commands.push(DrawCommand::FillRect { x, y, width, height, color: self.fill_style.clone() });
+    }
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64) {
+        self.commands.push(DrawCommand::Line { x1, y1, x2, y2, color: self.stroke_style.clone(), width: self.line_width });
+    }
+    fn arc(&mut self, x: f64, y: f64, radius: f64) {
+        self.commands.push(DrawCommand::Arc { x, y, radius, color: self.fill_style.clone() });
+    }
+    fn fill_polygon(&mut self, points: &[(f64, f64)]) {
+        self.commands.push(DrawCommand::Polygon { points: points.to_vec(), color: self.fill_style.clone() });
+    }
+    fn fill_text(&mut self, text: &str, x: f64, y: f64) {
+        self.commands.push(DrawCommand::Text { text: text.to_string(), x, y, font_size: self.font_size, color: self.fill_style.clone() });
+    }
+    fn measure_text(&mut self, text: &str) -> f64 {
+        text.chars().count() as f64 * self.font_size * 0.6
+    }
+}
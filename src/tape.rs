@@ -0,0 +1,109 @@
+use crate::{Price, Trade};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TapeConfig {
+    /// Consecutive same-side trades within this many ms of the first one are shown as one print,
+    /// zero lists every trade.
+    pub aggregate_ms: u64,
+    /// Prints smaller than this are left out, after aggregating.
+    pub min_size: f64,
+    /// Prints at least this large are fully highlighted, smaller ones proportionally less.
+    /// Zero scales to the largest print in view.
+    pub highlight_size: f64,
+}
+impl Default for TapeConfig {
+    fn default() -> Self {
+        Self { aggregate_ms: 0, min_size: 0.0, highlight_size: 0.0 }
+    }
+}
+
+/// One row of the tape, a single trade or several aggregated ones.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Print {
+    /// Time of the first trade.
+    pub time: u64,
+    pub price: Price,
+    /// Price of the last trade, differs from `price` when an aggregated print swept levels.
+    pub last_price: Price,
+    pub quantity: f64,
+    pub is_buyer_maker: bool,
+    pub trade_count: usize,
+}
+impl Print {
+    fn new(trade: &Trade) -> Self {
+        Self {
+            time: trade.time,
+            price: trade.price,
+            last_price: trade.price,
+            quantity: trade.quantity,
+            is_buyer_maker: trade.is_buyer_maker,
+            trade_count: 1,
+        }
+    }
+}
+
+/// Prints of `trades`, which have to be in time order, newest first.
+pub fn prints<'a>(trades: impl IntoIterator<Item = &'a Trade>, config: &TapeConfig) -> Vec<Print> {
+    let mut prints: Vec<Print> = Vec::new();
+    for trade in trades {
+        match prints.last_mut() {
+            Some(print) if config.aggregate_ms > 0
+                && print.is_buyer_maker == trade.is_buyer_maker
+                && trade.time <= print.time + config.aggregate_ms => {
+                print.last_price = trade.price;
+                print.quantity += trade.quantity;
+                print.trade_count += 1;
+            },
+            _ => prints.push(Print::new(trade)),
+        }
+    }
+    prints.retain(|print| print.quantity >= config.min_size);
+    prints.reverse();
+    prints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(ticks: i64, quantity: f64, time: u64, is_buyer_maker: bool) -> Trade {
+        Trade { price: Price::from_ticks(ticks), quantity, time, is_buyer_maker }
+    }
+    fn rows(prints: &[Print]) -> Vec<(u64, i64, i64, f64, usize)> {
+        prints.iter().map(|print| (print.time, print.price.ticks(), print.last_price.ticks(), print.quantity, print.trade_count)).collect()
+    }
+
+    #[test]
+    fn without_aggregation_every_trade_is_a_print() {
+        let trades = [trade(10, 1.0, 0, false), trade(11, 2.0, 0, false)];
+        assert_eq!(rows(&prints(&trades, &TapeConfig::default())), vec![(0, 11, 11, 2.0, 1), (0, 10, 10, 1.0, 1)]);
+    }
+
+    #[test]
+    fn aggregation_stops_at_a_side_change_and_the_window() {
+        let config = TapeConfig { aggregate_ms: 100, ..TapeConfig::default() };
+        let trades = [
+            trade(10, 1.0, 0, false),
+            trade(11, 2.0, 50, false),
+            // the window counts from the first trade of the print, inclusive
+            trade(12, 1.0, 100, false),
+            trade(12, 1.0, 101, false),
+            trade(11, 3.0, 120, true),
+            trade(10, 1.0, 130, false),
+        ];
+        assert_eq!(rows(&prints(&trades, &config)), vec![
+            (130, 10, 10, 1.0, 1),
+            (120, 11, 11, 3.0, 1),
+            (101, 12, 12, 1.0, 1),
+            (0, 10, 12, 4.0, 3),
+        ]);
+    }
+
+    #[test]
+    fn min_size_filters_after_aggregating() {
+        let config = TapeConfig { aggregate_ms: 100, min_size: 2.0, ..TapeConfig::default() };
+        let trades = [trade(10, 1.0, 0, false), trade(10, 1.0, 10, false), trade(10, 1.5, 20, true), trade(10, 2.0, 500, true)];
+        assert_eq!(rows(&prints(&trades, &config)), vec![(500, 10, 10, 2.0, 1), (0, 10, 10, 2.0, 2)]);
+        assert!(prints(&trades, &TapeConfig { min_size: 5.0, ..config }).is_empty());
+    }
+}
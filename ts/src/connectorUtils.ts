@@ -208,10 +208,39 @@ export async function fetchOI(symbol: string) {
     }
 }
 
-export async function fetchHistOI(symbol: string) {
+// openInterestHist has no period below 5m
+const OI_PERIODS: Record<string, string> = {
+    "1m": "5m",
+    "3m": "5m",
+};
+
+export async function fetchHistOI(symbol: string, interval: string) {
+    const period = OI_PERIODS[interval] ?? interval;
     try {
         let response = await fetch(
-            `https://fapi.binance.com/futures/data/openInterestHist?symbol=${symbol}&period=5m&limit=12`
+            `https://fapi.binance.com/futures/data/openInterestHist?symbol=${symbol}&period=${period}&limit=60`
+        );
+        return await response.text();
+    } catch (error) {
+        console.error(error);
+    }
+}
+
+export async function fetchFundingHistory(symbol: string) {
+    try {
+        let response = await fetch(
+            `https://fapi.binance.com/fapi/v1/fundingRate?symbol=${symbol}&limit=100`
+        );
+        return await response.text();
+    } catch (error) {
+        console.error(error);
+    }
+}
+
+export async function fetchMarkPrice(symbol: string) {
+    try {
+        let response = await fetch(
+            `https://fapi.binance.com/fapi/v1/premiumIndex?symbol=${symbol}`
         );
         return await response.text();
     } catch (error) {
@@ -230,10 +259,25 @@ export async function fetchDepthAsync(symbol: string) {
     }
 }
 
-export async function initialKlineFetch(symbol: string) {
+export async function initialKlineFetch(symbol: string, interval: string) {
+    try {
+        let response = await fetch(
+            `https://fapi.binance.com/fapi/v1/klines?symbol=${symbol}&interval=${interval}&limit=60`
+        );
+        return await response.text();
+    } catch (error) {
+        console.error(error);
+    }
+}
+
+export async function fetchKlinesSince(
+    symbol: string,
+    interval: string,
+    startTime: number
+) {
     try {
         let response = await fetch(
-            `https://fapi.binance.com/fapi/v1/klines?symbol=${symbol}&interval=1m&limit=60`
+            `https://fapi.binance.com/fapi/v1/klines?symbol=${symbol}&interval=${interval}&startTime=${startTime}&limit=1500`
         );
         return await response.text();
     } catch (error) {
@@ -4,8 +4,11 @@ import {
     combineDicts,
     fetchDepthAsync,
     fetchHistOI,
+    fetchFundingHistory,
+    fetchMarkPrice,
     fetchOI,
     initialKlineFetch,
+    fetchKlinesSince,
     fetchHistTrades,
     fetchTickerInfo,
     tickersOIfetch,
@@ -354,6 +357,9 @@ let canvasIds = [
     "#canvas-indi-2",
     "#canvas-bubble",
     "#canvas-indi-1",
+    "#canvas-indi-3",
+    "#canvas-indi-4",
+    "#canvas-tape",
 ];
 
 let canvases = canvasIds.map((id) => {
@@ -361,6 +367,9 @@ let canvases = canvasIds.map((id) => {
     canvas.getContext("2d", { alpha: false });
     return canvas;
 }) as [
+    HTMLCanvasElement,
+    HTMLCanvasElement,
+    HTMLCanvasElement,
     HTMLCanvasElement,
     HTMLCanvasElement,
     HTMLCanvasElement,
@@ -383,13 +392,42 @@ window.addEventListener(
 );
 
 let manager = wasm_module.CanvasManager.new(...canvases);
+manager.set_resync_callback(() => {
+    console.log("order book out of sync, fetching a new snapshot...");
+    fetchDepthAsync(currentSymbol).then((depth) => {
+        manager.gather_depth(depth);
+    });
+});
+
+manager.set_reconnect_callback((gapStart: number) => {
+    console.log("stream reconnected, refetching data since", gapStart);
+    fillGap(currentSymbol, currentTimeframe, gapStart);
+});
 
-let depthIntervalId: NodeJS.Timeout | number;
 let oiIntervalId: NodeJS.Timeout | number;
 
 let currentSymbol = "btcusdt";
+let currentTimeframe = "1m";
 changeSymbol(currentSymbol);
 
+const timeframes = ["1m", "3m", "5m", "15m", "1h", "4h"];
+for (const timeframe of timeframes) {
+    const button = document.getElementById("tf" + timeframe);
+    button?.addEventListener("click", function () {
+        if (timeframe === currentTimeframe) {
+            return;
+        }
+        document
+            .getElementById("tf" + currentTimeframe)
+            ?.classList.remove("selected");
+        button.classList.add("selected");
+
+        currentTimeframe = timeframe;
+        manager.set_timeframe(timeframe);
+        changeSymbol(currentSymbol);
+    });
+}
+
 function renderLoop() {
     manager.render_start();
     setTimeout(() => {
@@ -399,11 +437,11 @@ function renderLoop() {
 requestAnimationFrame(renderLoop);
 
 function changeSymbol(newSymbol: string) {
-    depthIntervalId ? clearInterval(depthIntervalId) : null;
     oiIntervalId ? clearInterval(oiIntervalId) : null;
 
     currentSymbol = newSymbol;
 
+    // prices are kept in ticks, so the tick size has to be known before any data comes in
     fetchTickerInfo(currentSymbol).then((result) => {
         if (result === null) {
             console.error("Could not fetch ticker info");
@@ -415,23 +453,28 @@ function changeSymbol(newSymbol: string) {
             minQty,
             parseFloat(tickSizeBtn.value)
         );
-    });
 
-    manager.initialize_ws(currentSymbol);
+        manager.initialize_ws(currentSymbol);
 
-    fetchDepthAsync(currentSymbol).then((depth) => {
-        manager.gather_depth(depth);
-    });
-    initialKlineFetch(currentSymbol).then((klines) => {
-        manager.gather_klines(klines);
-        getHistTrades(currentSymbol, manager);
-    });
-    fetchHistOI(currentSymbol).then((histOI) => {
-        manager.gather_hist_oi(histOI);
+        fetchDepthAsync(currentSymbol).then((depth) => {
+            manager.gather_depth(depth);
+        });
+        initialKlineFetch(currentSymbol, currentTimeframe).then((klines) => {
+            manager.gather_klines(klines);
+            getHistTrades(currentSymbol, manager);
+        });
+        fetchHistOI(currentSymbol, currentTimeframe).then((histOI) => {
+            manager.gather_hist_oi(histOI);
+        });
+        fetchFundingHistory(currentSymbol).then((funding) => {
+            manager.gather_funding(funding);
+        });
+        fetchMarkPrice(currentSymbol).then((markPrice) => {
+            manager.gather_mark_price(markPrice);
+        });
     });
 
     scheduleFetchOI();
-    scheduleFetchDepth();
 
     let tickerInfoName = document.querySelector(
         "#tickerInfo-name"
@@ -443,14 +486,6 @@ function changeSymbol(newSymbol: string) {
         showTickers();
     }
 }
-function scheduleFetchDepth() {
-    depthIntervalId = setInterval(() => {
-        fetchDepthAsync(currentSymbol).then((depth) => {
-            manager.gather_depth(depth);
-        });
-    }, 12000);
-}
-
 function scheduleFetchOI() {
     const now = new Date();
     const delay = (60 - now.getSeconds() - 1) * 1000 - now.getMilliseconds();
@@ -477,14 +512,215 @@ tickSizeBtn.addEventListener("change", function () {
     manager.set_tick_size(parseFloat(tickSizeBtn.value));
 });
 
+const volumeProfileSelect = document.querySelector(
+    "#volume-profile-select"
+) as HTMLSelectElement;
+volumeProfileSelect.addEventListener("change", function () {
+    manager.set_volume_profile(volumeProfileSelect.value);
+});
+
+const cvdAnchorSelect = document.querySelector(
+    "#cvd-anchor-select"
+) as HTMLSelectElement;
+cvdAnchorSelect.addEventListener("change", function () {
+    manager.set_cvd_anchor(cvdAnchorSelect.value);
+});
+
+const oiModeSelect = document.querySelector(
+    "#oi-mode-select"
+) as HTMLSelectElement;
+oiModeSelect.addEventListener("change", function () {
+    manager.set_oi_mode(oiModeSelect.value);
+});
+
+const oiPriceSelect = document.querySelector(
+    "#oi-price-select"
+) as HTMLSelectElement;
+oiPriceSelect.addEventListener("change", function () {
+    manager.set_oi_weighted_price(oiPriceSelect.value === "on");
+});
+
+const minLiquidationInput = document.querySelector(
+    "#min-liquidation-input"
+) as HTMLInputElement;
+minLiquidationInput.addEventListener("change", function () {
+    manager.set_min_liquidation_size(parseFloat(minLiquidationInput.value) || 0);
+});
+
+const heatmapSelect = document.querySelector(
+    "#heatmap-select"
+) as HTMLSelectElement;
+heatmapSelect.addEventListener("change", function () {
+    manager.set_heatmap_retention(parseInt(heatmapSelect.value));
+});
+
+const depthModeSelect = document.querySelector(
+    "#depth-mode-select"
+) as HTMLSelectElement;
+depthModeSelect.addEventListener("change", function () {
+    manager.set_depth_mode(depthModeSelect.value);
+});
+
+const tapeAggregationSelect = document.querySelector(
+    "#tape-aggregation-select"
+) as HTMLSelectElement;
+tapeAggregationSelect.addEventListener("change", function () {
+    manager.set_tape_aggregation(parseInt(tapeAggregationSelect.value));
+});
+
+const tapeMinSizeInput = document.querySelector(
+    "#tape-min-size-input"
+) as HTMLInputElement;
+tapeMinSizeInput.addEventListener("change", function () {
+    manager.set_tape_min_size(parseFloat(tapeMinSizeInput.value) || 0);
+});
+
+const tapeHighlightInput = document.querySelector(
+    "#tape-highlight-input"
+) as HTMLInputElement;
+tapeHighlightInput.addEventListener("change", function () {
+    manager.set_tape_highlight_size(parseFloat(tapeHighlightInput.value) || 0);
+});
+
+const largeTradePercentileSelect = document.querySelector(
+    "#large-trade-percentile-select"
+) as HTMLSelectElement;
+const largeTradeSizeInput = document.querySelector(
+    "#large-trade-size-input"
+) as HTMLInputElement;
+largeTradePercentileSelect.addEventListener("change", function () {
+    largeTradeSizeInput.value = "";
+    manager.set_large_trade_percentile(parseFloat(largeTradePercentileSelect.value));
+});
+// an absolute size takes over from the percentile until cleared
+largeTradeSizeInput.addEventListener("change", function () {
+    const size = parseFloat(largeTradeSizeInput.value);
+    if (size > 0) {
+        manager.set_large_trade_size(size);
+    } else {
+        manager.set_large_trade_percentile(parseFloat(largeTradePercentileSelect.value));
+    }
+});
+
+const recordCheckbox = document.querySelector(
+    "#record-checkbox"
+) as HTMLInputElement;
+recordCheckbox.addEventListener("change", function () {
+    if (recordCheckbox.checked) {
+        // reload the symbol so the recording starts with its snapshots
+        manager.start_recording(currentSymbol);
+        changeSymbol(currentSymbol);
+        return;
+    }
+    const recording = manager.stop_recording();
+    if (recording.length > 0) {
+        downloadText(recording, `${currentSymbol}-${Date.now()}.fsr`);
+    }
+});
+
+function downloadText(text: string, filename: string) {
+    const link = document.createElement("a");
+    link.href = URL.createObjectURL(new Blob([text], { type: "text/plain" }));
+    link.download = filename;
+    link.click();
+    URL.revokeObjectURL(link.href);
+}
+
+const exportDatasetSelect = document.querySelector(
+    "#export-dataset-select"
+) as HTMLSelectElement;
+const exportFormatSelect = document.querySelector(
+    "#export-format-select"
+) as HTMLSelectElement;
+const exportBtn = document.querySelector("#export-btn") as HTMLInputElement;
+// exports everything loaded
+exportBtn.addEventListener("click", function () {
+    const [from, to] = [BigInt(0), BigInt(Date.now())];
+    const format = exportFormatSelect.value;
+    let data: string;
+    switch (exportDatasetSelect.value) {
+        case "footprint":
+            data = manager.export_footprint(from, to, format);
+            break;
+        case "oi":
+            data = manager.export_open_interest(from, to, format);
+            break;
+        default:
+            data = manager.export_klines(from, to, format);
+    }
+    if (data.length > 0) {
+        downloadText(
+            data,
+            `${currentSymbol}-${currentTimeframe}-${exportDatasetSelect.value}.${format}`
+        );
+    }
+});
+
+const replayFileInput = document.querySelector(
+    "#replay-file-input"
+) as HTMLInputElement;
+const replaySpeedSelect = document.querySelector(
+    "#replay-speed-select"
+) as HTMLSelectElement;
+const replayPauseCheckbox = document.querySelector(
+    "#replay-pause-checkbox"
+) as HTMLInputElement;
+replayFileInput.addEventListener("change", async function () {
+    const file = replayFileInput.files?.[0];
+    if (!file) {
+        return;
+    }
+    oiIntervalId ? clearInterval(oiIntervalId) : null;
+    if (manager.load_replay(await file.text())) {
+        manager.set_replay_speed(replaySpeedSelect.value);
+        replayPauseCheckbox.checked = false;
+    }
+});
+replaySpeedSelect.addEventListener("change", function () {
+    manager.set_replay_speed(replaySpeedSelect.value);
+});
+replayPauseCheckbox.addEventListener("change", function () {
+    replayPauseCheckbox.checked ? manager.pause_replay() : manager.resume_replay();
+});
+
+const barReplayCheckbox = document.querySelector(
+    "#bar-replay-checkbox"
+) as HTMLInputElement;
+const barReplayStepSelect = document.querySelector(
+    "#bar-replay-step-select"
+) as HTMLSelectElement;
+const barReplayStepBtn = document.querySelector(
+    "#bar-replay-step-btn"
+) as HTMLInputElement;
+barReplayCheckbox.addEventListener("change", function () {
+    if (barReplayCheckbox.checked) {
+        // start a few klines back, where footprint trades are loaded
+        const keys = manager.get_kline_ohlcv_keys();
+        if (keys.length === 0) {
+            barReplayCheckbox.checked = false;
+            return;
+        }
+        oiIntervalId ? clearInterval(oiIntervalId) : null;
+        manager.start_bar_replay(Number(keys[Math.max(0, keys.length - 20)]));
+        return;
+    }
+    manager.stop_bar_replay();
+    changeSymbol(currentSymbol);
+});
+barReplayStepBtn.addEventListener("click", function () {
+    manager.step_bar_replay(barReplayStepSelect.value);
+});
+
 async function getHistTrades(
     symbol: string,
     manager: wasm_module.CanvasManager
 ) {
     const dp = manager.get_kline_ohlcv_keys();
+    const interval = Number(manager.get_timeframe_ms());
+    const timeframe = currentTimeframe;
 
     // get current kline first
-    let startTime = Number(dp[dp.length - 1]) + 60000;
+    let startTime = Number(dp[dp.length - 1]) + interval;
     const endTime = Date.now();
     let trades: any[] = [];
     let lastTradeTime = 0;
@@ -508,13 +744,13 @@ async function getHistTrades(
     } while (lastTradeTime < endTime);
     manager.gather_hist_trades(
         JSON.stringify(trades),
-        (endTime - 59999).toString()
+        (endTime - (endTime % interval)).toString()
     );
 
     // get historical klines after
     for (let i = dp.length - 1; i >= 0; i--) {
         let startTime = Number(dp[i]);
-        const endTime = startTime + 59999;
+        const endTime = startTime + interval - 1;
         let trades: any[] = [];
         let lastTradeTime = 0;
         console.log(
@@ -525,7 +761,7 @@ async function getHistTrades(
             "klines..."
         );
         while (true) {
-            if (symbol != currentSymbol) {
+            if (symbol != currentSymbol || timeframe != currentTimeframe) {
                 console.log("stopped fetching historical trades for", symbol);
                 return;
             }
@@ -553,15 +789,55 @@ async function getHistTrades(
         }
         manager.gather_hist_trades(
             JSON.stringify(trades),
-            (endTime - 59999).toString()
+            (endTime - interval + 1).toString()
         );
     }
 }
 
+async function fillGap(symbol: string, timeframe: string, gapStart: number) {
+    if (gapStart === 0) {
+        return;
+    }
+    const klines = await fetchKlinesSince(symbol, timeframe, gapStart);
+    if (symbol != currentSymbol || timeframe != currentTimeframe) {
+        return;
+    }
+    manager.gather_klines(klines);
+
+    const interval = Number(manager.get_timeframe_ms());
+    const now = Date.now();
+    for (let openTime = gapStart; openTime <= now; openTime += interval) {
+        const endTime = Math.min(openTime + interval - 1, now);
+        let startTime = openTime;
+        let trades: any[] = [];
+        while (true) {
+            if (symbol != currentSymbol || timeframe != currentTimeframe) {
+                console.log("stopped filling gap for", symbol);
+                return;
+            }
+            const fetchedTrades = await fetchHistTrades(
+                symbol,
+                startTime,
+                endTime,
+                1000
+            );
+            trades = trades.concat(fetchedTrades);
+            if (fetchedTrades.length < 1000) {
+                break;
+            }
+            startTime = fetchedTrades[fetchedTrades.length - 1].time + 1;
+            await new Promise((resolve) => setTimeout(resolve, 400));
+        }
+        manager.gather_hist_trades(JSON.stringify(trades), openTime.toString());
+    }
+}
+
 // Canvas event listeners //
 let canvasMain = document.querySelector("#canvas-main") as HTMLCanvasElement;
 let canvasIndi1 = document.querySelector("#canvas-indi-1") as HTMLCanvasElement;
 let canvasIndi2 = document.querySelector("#canvas-indi-2") as HTMLCanvasElement;
+let canvasIndi3 = document.querySelector("#canvas-indi-3") as HTMLCanvasElement;
+let canvasIndi4 = document.querySelector("#canvas-indi-4") as HTMLCanvasElement;
 let canvasDepth = document.querySelector("#canvas-depth") as HTMLCanvasElement;
 
 // Panning
@@ -605,6 +881,14 @@ canvasIndi2.addEventListener("wheel", function (event) {
     event.preventDefault();
     manager.zoom_x(-event.deltaY);
 });
+canvasIndi3.addEventListener("wheel", function (event) {
+    event.preventDefault();
+    manager.zoom_x(-event.deltaY);
+});
+canvasIndi4.addEventListener("wheel", function (event) {
+    event.preventDefault();
+    manager.zoom_x(-event.deltaY);
+});
 
 // Zoom Y
 canvasDepth.addEventListener("wheel", function (event) {